@@ -0,0 +1,21 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use qrious::DecodeOptions;
+
+/// Decodes the generated sample images with and without the pure barcode path, the
+/// others aren't clean enough for it and fall back to looking for the finders
+fn bench_decode(c: &mut Criterion) {
+    for name in ["hello-world", "micro-m2", "rmqr-r11x43"] {
+        let img = image::open(format!("test-data/{name}.png")).unwrap();
+        let mut group = c.benchmark_group(name);
+        group.bench_function("finders", |b| {
+            b.iter(|| qrious::decode(&img, &DecodeOptions::new()).unwrap())
+        });
+        group.bench_function("pure barcode", |b| {
+            b.iter(|| qrious::decode(&img, &DecodeOptions::new().pure_barcode(true)).unwrap())
+        });
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_decode);
+criterion_main!(benches);
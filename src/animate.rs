@@ -0,0 +1,310 @@
+use std::{collections::HashSet, io::Write};
+
+use xml::{
+    writer::{Result, XmlEvent},
+    EmitterConfig, EventWriter,
+};
+
+use crate::{
+    qr::{self, Symbol},
+    version,
+};
+
+/// Light modules around the symbol
+const MARGIN: usize = 1;
+/// Rows under the symbol for the caption of the current codeword
+const CAPTION_ROWS: usize = 3;
+/// Steps a skipped function region stays highlighted for
+const SKIP_HIGHLIGHT_STEPS: f32 = 4.0;
+
+/// Where a data module's bit came from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Origin {
+    /// The codeword of the interleaved stream, its block, and which of the data or
+    /// ec codewords of the block it is
+    Codeword {
+        codeword: usize,
+        block: usize,
+        index: usize,
+        ec: bool,
+    },
+    /// Filler after the last codeword
+    Remainder,
+}
+
+/// Writes an SVG that walks the data modules of a QR code in placement order, one
+/// every `step` seconds. Each module is coloured by its block, lighter for data and
+/// darker for ec codewords, with a dot where its bit (before masking) is 1. Function
+/// modules are greyed out, and flash when the walk jumps over them.
+pub fn write_placement_svg<W: Write>(symbol: &Symbol, step: f32, sink: W) -> Result<()> {
+    let version = symbol.version;
+    let size = version::version_to_size(version);
+    let num_blocks = version::block_layout(version, symbol.format.ec_level)
+        .data_lens
+        .len();
+    let mask_fn = qr::get_mask_fn(symbol.format.mask).expect("Masks are 3 bits");
+    let map = qr::module_map(version, symbol.format.ec_level);
+    let positions: Vec<(usize, usize)> = map.iter().map(|module| (module.x, module.y)).collect();
+    let origins: Vec<Origin> = map
+        .iter()
+        .map(|module| match module.codeword {
+            Some(bit) => Origin::Codeword {
+                codeword: bit.codeword,
+                block: bit.block,
+                index: bit.index,
+                ec: bit.ec,
+            },
+            None => Origin::Remainder,
+        })
+        .collect();
+
+    let width = size + 2 * MARGIN;
+    let height = size + 2 * MARGIN + CAPTION_ROWS;
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(sink);
+    writer.write(
+        XmlEvent::start_element("svg")
+            .attr("xmlns", "http://www.w3.org/2000/svg")
+            .attr("width", &(width * 20).to_string())
+            .attr("height", &(height * 20).to_string())
+            .attr("viewBox", &format!("0 0 {width} {height}"))
+            .attr("font-family", "sans-serif"),
+    )?;
+    write_rect(&mut writer, 0.0, 0.0, width as f32, height as f32, "white")?;
+
+    let is_data: HashSet<(usize, usize)> = positions.iter().copied().collect();
+    writer.write(XmlEvent::start_element("g").attr("id", "function-modules"))?;
+    for y in 0..size {
+        for x in (0..size).filter(|&x| !is_data.contains(&(x, y))) {
+            let fill = if symbol.matrix.get(x, y) {
+                "#666"
+            } else {
+                "#ddd"
+            };
+            write_rect(
+                &mut writer,
+                (x + MARGIN) as f32,
+                (y + MARGIN) as f32,
+                1.0,
+                1.0,
+                fill,
+            )?;
+        }
+    }
+    writer.write(XmlEvent::end_element())?;
+
+    writer.write(XmlEvent::start_element("g").attr("id", "data-modules"))?;
+    for (i, (&(x, y), origin)) in positions.iter().zip(&origins).enumerate() {
+        let begin = format!("{}s", i as f32 * step);
+        writer.write(XmlEvent::start_element("g").attr("opacity", "0"))?;
+        write_reveal(&mut writer, &begin)?;
+        let (left, top) = ((x + MARGIN) as f32, (y + MARGIN) as f32);
+        write_rect(
+            &mut writer,
+            left,
+            top,
+            1.0,
+            1.0,
+            &origin_color(origin, num_blocks),
+        )?;
+        if symbol.matrix.get(x, y) != mask_fn(x as u32, y as u32) {
+            writer.write(
+                XmlEvent::start_element("circle")
+                    .attr("cx", &(left + 0.5).to_string())
+                    .attr("cy", &(top + 0.5).to_string())
+                    .attr("r", "0.2")
+                    .attr("fill", "black"),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+        }
+        writer.write(XmlEvent::end_element())?;
+    }
+    writer.write(XmlEvent::end_element())?;
+
+    writer.write(
+        XmlEvent::start_element("g")
+            .attr("id", "path")
+            .attr("stroke", "black")
+            .attr("stroke-width", "0.08"),
+    )?;
+    for (i, pair) in positions.windows(2).enumerate() {
+        let [(x1, y1), (x2, y2)] = [pair[0], pair[1]];
+        let begin = format!("{}s", (i + 1) as f32 * step);
+        writer.write(
+            XmlEvent::start_element("line")
+                .attr("x1", &(x1 + MARGIN).to_string())
+                .attr("y1", &(y1 + MARGIN).to_string())
+                .attr("x2", &(x2 + MARGIN).to_string())
+                .attr("y2", &(y2 + MARGIN).to_string())
+                .attr("transform", "translate(0.5 0.5)")
+                .attr("opacity", "0"),
+        )?;
+        write_reveal(&mut writer, &begin)?;
+        writer.write(XmlEvent::end_element())?;
+
+        // jumping over a function region in the same pair of columns
+        if x1.abs_diff(x2) <= 1 && y1.abs_diff(y2) > 1 {
+            writer.write(
+                XmlEvent::start_element("rect")
+                    .attr("x", &(x1.min(x2) + MARGIN).to_string())
+                    .attr("y", &(y1.min(y2) + 1 + MARGIN).to_string())
+                    .attr("width", &(x1.abs_diff(x2) + 1).to_string())
+                    .attr("height", &(y1.abs_diff(y2) - 1).to_string())
+                    .attr("fill", "red")
+                    .attr("opacity", "0"),
+            )?;
+            writer.write(
+                XmlEvent::start_element("animate")
+                    .attr("attributeName", "opacity")
+                    .attr("values", "0;0.6;0")
+                    .attr("dur", &format!("{}s", SKIP_HIGHLIGHT_STEPS * step))
+                    .attr("begin", &begin),
+            )?;
+            writer.write(XmlEvent::end_element())?;
+            writer.write(XmlEvent::end_element())?;
+        }
+    }
+    writer.write(XmlEvent::end_element())?;
+
+    write_captions(&mut writer, &origins, step, size, width)?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+pub fn placement_svg(symbol: &Symbol, step: f32) -> String {
+    let mut svg = Vec::new();
+    write_placement_svg(symbol, step, &mut svg).expect("Writing to a Vec can't fail");
+    String::from_utf8(svg).expect("The writer emits UTF-8")
+}
+
+fn origin_color(origin: &Origin, num_blocks: usize) -> String {
+    match *origin {
+        Origin::Codeword {
+            codeword,
+            block,
+            ec,
+            ..
+        } => {
+            let hue = 360 * block / num_blocks;
+            let lightness = if ec { 45 } else { 70 } + if codeword % 2 == 0 { 0 } else { 10 };
+            format!("hsl({hue}, 70%, {lightness}%)")
+        }
+        Origin::Remainder => "#aaa".to_string(),
+    }
+}
+
+/// One caption under the symbol per codeword, shown while its bits are placed
+fn write_captions<W: Write>(
+    writer: &mut EventWriter<W>,
+    origins: &[Origin],
+    step: f32,
+    size: usize,
+    width: usize,
+) -> Result<()> {
+    let font_size = CAPTION_ROWS as f32 / 2.5;
+    writer.write(
+        XmlEvent::start_element("g")
+            .attr("id", "captions")
+            .attr("font-size", &font_size.to_string())
+            .attr("text-anchor", "middle"),
+    )?;
+    let mut start = 0;
+    for run in origins.chunk_by(|a, b| a == b) {
+        let text = match run[0] {
+            Origin::Codeword {
+                codeword,
+                block,
+                index,
+                ec,
+            } => {
+                let kind = if ec { "ec" } else { "data" };
+                format!("Codeword {codeword}: block {}, {kind} {index}", block + 1)
+            }
+            Origin::Remainder => "Remainder bits".to_string(),
+        };
+        let end = start + run.len();
+        writer.write(
+            XmlEvent::start_element("text")
+                .attr("x", &(width as f32 / 2.0).to_string())
+                .attr(
+                    "y",
+                    &((size + 2 * MARGIN) as f32 + font_size * 1.5).to_string(),
+                )
+                .attr("visibility", "hidden"),
+        )?;
+        writer.write(XmlEvent::characters(&text))?;
+        let mut set = XmlEvent::start_element("set")
+            .attr("attributeName", "visibility")
+            .attr("to", "visible");
+        let begin = format!("{}s", start as f32 * step);
+        let end_time = format!("{}s", end as f32 * step);
+        set = set.attr("begin", &begin);
+        // the last caption stays
+        if end < origins.len() {
+            set = set.attr("end", &end_time);
+        } else {
+            set = set.attr("fill", "freeze");
+        }
+        writer.write(set)?;
+        writer.write(XmlEvent::end_element())?;
+        writer.write(XmlEvent::end_element())?;
+        start = end;
+    }
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+/// Makes the enclosing element visible from `begin` on
+fn write_reveal<W: Write>(writer: &mut EventWriter<W>, begin: &str) -> Result<()> {
+    writer.write(
+        XmlEvent::start_element("set")
+            .attr("attributeName", "opacity")
+            .attr("to", "1")
+            .attr("begin", begin)
+            .attr("fill", "freeze"),
+    )?;
+    writer.write(XmlEvent::end_element())
+}
+
+fn write_rect<W: Write>(
+    writer: &mut EventWriter<W>,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    fill: &str,
+) -> Result<()> {
+    writer.write(
+        XmlEvent::start_element("rect")
+            .attr("x", &x.to_string())
+            .attr("y", &y.to_string())
+            .attr("width", &width.to_string())
+            .attr("height", &height.to_string())
+            .attr("fill", fill),
+    )?;
+    writer.write(XmlEvent::end_element())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::EcLevel;
+
+    #[test]
+    fn test_placement_svg() {
+        let symbol = crate::encode(b"HELLO WORLD", EcLevel::Q).unwrap();
+        let svg = placement_svg(&symbol, 0.05);
+
+        // 26 codewords of 8 bits, and 0 remainder bits in version 1
+        assert_eq!(svg.matches("<text").count(), 26);
+        assert!(svg.contains("Codeword 0: block 1, data 0"));
+        assert!(svg.contains("Codeword 25: block 1, ec 12"));
+        assert_eq!(
+            svg.matches(r#"attributeName="opacity" to="1""#).count(),
+            208 * 2 - 1
+        );
+        // the walk jumps over the horizontal timing pattern in the middle columns
+        assert!(svg.contains(r#"values="0;0.6;0""#));
+    }
+}
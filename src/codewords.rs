@@ -0,0 +1,263 @@
+use crate::{error::Result, rs};
+
+/// How the codewords of a symbol are split into error correction blocks.
+/// Shorter blocks come first, longer ones have one more data codeword.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockLayout {
+    pub data_lens: Vec<usize>,
+    pub ec_len: usize,
+}
+
+impl BlockLayout {
+    pub fn new(total_codewords: usize, num_blocks: usize, ec_len: usize) -> Self {
+        let short_len = total_codewords / num_blocks;
+        let num_long = total_codewords % num_blocks;
+        let data_lens = (0..num_blocks)
+            .map(|i| short_len - ec_len + usize::from(i >= num_blocks - num_long))
+            .collect();
+        Self { data_lens, ec_len }
+    }
+
+    pub fn num_data_codewords(&self) -> usize {
+        self.data_lens.iter().sum()
+    }
+
+    /// Splits the interleaved codeword stream back into its blocks (data followed by ec)
+    pub fn deinterleave(&self, codewords: &[u8]) -> Vec<Vec<u8>> {
+        let mut blocks: Vec<Vec<u8>> = self
+            .data_lens
+            .iter()
+            .map(|len| Vec::with_capacity(len + self.ec_len))
+            .collect();
+        let mut iter = codewords.iter().copied();
+
+        let max_data_len = self.data_lens.iter().copied().max().unwrap_or(0);
+        for i in 0..max_data_len {
+            for (block, &len) in blocks.iter_mut().zip(self.data_lens.iter()) {
+                if i < len {
+                    block.extend(iter.next());
+                }
+            }
+        }
+        for _ in 0..self.ec_len {
+            for block in blocks.iter_mut() {
+                block.extend(iter.next());
+            }
+        }
+        blocks
+    }
+
+    /// Which block each codeword of the interleaved stream is from, and where in it
+    pub fn block_positions(&self) -> Vec<(usize, usize)> {
+        let mut result =
+            Vec::with_capacity(self.num_data_codewords() + self.data_lens.len() * self.ec_len);
+        let max_data_len = self.data_lens.iter().copied().max().unwrap_or(0);
+        for i in 0..max_data_len {
+            result.extend(
+                self.data_lens
+                    .iter()
+                    .enumerate()
+                    .filter(|&(_, &len)| i < len)
+                    .map(|(block, _)| (block, i)),
+            );
+        }
+        for i in 0..self.ec_len {
+            result.extend(self.data_lens.iter().map(|&len| len + i).enumerate());
+        }
+        result
+    }
+
+    /// Splits the data codewords into blocks, appends the ec codewords of each and
+    /// interleaves them, the inverse of [`BlockLayout::deinterleave`]
+    pub fn interleave(&self, data: &[u8]) -> Vec<u8> {
+        let mut blocks = Vec::with_capacity(self.data_lens.len());
+        let mut rest = data;
+        for &len in self.data_lens.iter() {
+            let (block, next) = rest.split_at(len);
+            blocks.push((block, rs::ec_codewords(block, self.ec_len)));
+            rest = next;
+        }
+
+        let mut result = Vec::with_capacity(data.len() + blocks.len() * self.ec_len);
+        let max_data_len = self.data_lens.iter().copied().max().unwrap_or(0);
+        for i in 0..max_data_len {
+            result.extend(blocks.iter().filter_map(|(block, _)| block.get(i)));
+        }
+        for i in 0..self.ec_len {
+            result.extend(blocks.iter().map(|(_, ec)| ec[i]));
+        }
+        result
+    }
+
+    /// The codewords with the errors in each block fixed, still interleaved
+    pub fn correct_codewords(&self, codewords: &[u8]) -> Result<Vec<u8>> {
+        let mut blocks = self.deinterleave(codewords);
+        for block in blocks.iter_mut() {
+            rs::correct(block, self.ec_len)?;
+        }
+        Ok(self
+            .block_positions()
+            .into_iter()
+            .map(|(block, i)| blocks[block][i])
+            .collect())
+    }
+
+    /// Deinterleaves and error corrects the codewords, returns the data codewords
+    /// in order along with the number of corrected codewords
+    pub fn correct(&self, codewords: &[u8]) -> Result<(Vec<u8>, usize)> {
+        let mut data = Vec::with_capacity(self.num_data_codewords());
+        let mut corrected = 0;
+        for (mut block, &len) in self
+            .deinterleave(codewords)
+            .into_iter()
+            .zip(self.data_lens.iter())
+        {
+            corrected += rs::correct(&mut block, self.ec_len)?;
+            data.extend_from_slice(&block[..len]);
+        }
+        Ok((data, corrected))
+    }
+}
+
+/// Which bit of which codeword a data module holds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CodewordBit {
+    /// Index in the codeword stream as placed in the symbol
+    pub codeword: usize,
+    pub block: usize,
+    /// Index among the data or the ec codewords of the block
+    pub index: usize,
+    pub ec: bool,
+    /// 0 is the most significant bit
+    pub bit: usize,
+}
+
+/// A data module and the codeword bit placed in it, `None` for remainder bits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModuleBit {
+    pub x: usize,
+    pub y: usize,
+    pub codeword: Option<CodewordBit>,
+}
+
+/// Maps each of `positions`, in placement order, to the codeword bit it holds.
+/// The data may end halfway into its last codeword after `num_data_bits`, as in M1
+/// and M3, in which case the ec codewords start right after it.
+pub fn module_map(
+    positions: &[(usize, usize)],
+    layout: &BlockLayout,
+    num_data_bits: usize,
+) -> Vec<ModuleBit> {
+    let block_positions = layout.block_positions();
+    let num_data_codewords = layout.num_data_codewords();
+    positions
+        .iter()
+        .enumerate()
+        .map(|(i, &(x, y))| {
+            let (codeword, bit) = if i < num_data_bits {
+                (i / 8, i % 8)
+            } else {
+                let ec_bit = i - num_data_bits;
+                (num_data_codewords + ec_bit / 8, ec_bit % 8)
+            };
+            let codeword = block_positions.get(codeword).map(|&(block, index)| {
+                let data_len = layout.data_lens[block];
+                CodewordBit {
+                    codeword,
+                    block,
+                    index: index.checked_sub(data_len).unwrap_or(index),
+                    ec: index >= data_len,
+                    bit,
+                }
+            });
+            ModuleBit { x, y, codeword }
+        })
+        .collect()
+}
+
+/// Module positions in data placement order: two columns wide, right to left,
+/// alternating upwards and downwards, skipping function modules. `skip_column`
+/// is the vertical timing pattern of a QR code, which shifts the columns left of it.
+pub fn placement_order(
+    width: usize,
+    height: usize,
+    skip_column: Option<usize>,
+    is_function: impl Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut result = vec![];
+    let mut right = width as isize - 1;
+    let mut upwards = true;
+    while right >= 1 {
+        if Some(right as usize) == skip_column {
+            right -= 1;
+        }
+        for i in 0..height {
+            let y = if upwards { height - 1 - i } else { i };
+            for x in [right as usize, right as usize - 1] {
+                if !is_function(x, y) {
+                    result.push((x, y));
+                }
+            }
+        }
+        upwards = !upwards;
+        right -= 2;
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_deinterleave() {
+        // 5-Q: 2 blocks of 15 data codewords, 2 of 16, all with 18 ec codewords
+        let layout = BlockLayout::new(134, 4, 18);
+        assert_eq!(layout.data_lens, vec![15, 15, 16, 16]);
+
+        let codewords: Vec<u8> = (0..134).map(|i| i as u8).collect();
+        let blocks = layout.deinterleave(&codewords);
+        assert_eq!(blocks[0][..3], [0, 4, 8]);
+        assert_eq!(blocks[3][14..17], [59, 61, 134 - 72 + 3]);
+        assert_eq!(blocks[2][15], 60);
+
+        let positions = layout.block_positions();
+        for (i, &(block, idx)) in positions.iter().enumerate() {
+            assert_eq!(blocks[block][idx], i as u8);
+        }
+    }
+
+    #[test]
+    fn test_module_map() {
+        // M1: 2.5 data codewords and 2 ec codewords in one block
+        let layout = BlockLayout::new(5, 1, 2);
+        let positions: Vec<(usize, usize)> = (0..40).map(|i| (i, 0)).collect();
+        let map = module_map(&positions, &layout, 20);
+
+        let bit = |i: usize| map[i].codeword.unwrap();
+        assert_eq!((bit(19).codeword, bit(19).bit, bit(19).ec), (2, 3, false));
+        assert_eq!((bit(20).codeword, bit(20).index, bit(20).ec), (3, 0, true));
+        assert_eq!((bit(35).codeword, bit(35).bit), (4, 7));
+        assert_eq!(map[36].codeword, None);
+    }
+
+    #[test]
+    fn test_correct_codewords() {
+        let layout = BlockLayout::new(134, 4, 18);
+        let data: Vec<u8> = (0..62).collect();
+        let codewords = layout.interleave(&data);
+        let mut damaged = codewords.clone();
+        damaged[5] ^= 0xFF;
+        damaged[100] ^= 0x01;
+        assert_eq!(layout.correct_codewords(&damaged).unwrap(), codewords);
+    }
+
+    #[test]
+    fn test_interleave() {
+        let layout = BlockLayout::new(134, 4, 18);
+        let data: Vec<u8> = (0..62).collect();
+        let codewords = layout.interleave(&data);
+        assert_eq!(codewords.len(), 134);
+        assert_eq!(layout.correct(&codewords).unwrap(), (data, 0));
+    }
+}
@@ -0,0 +1,916 @@
+use std::{
+    borrow::Cow,
+    fmt::{self, Display},
+    ops::RangeInclusive,
+};
+
+use image::{GenericImageView, Pixel};
+
+use crate::{
+    codewords::{BlockLayout, ModuleBit},
+    encode::Penalty,
+    error::{DecodeError, Result},
+    format::EcLevel,
+    img::Binarizer,
+    matrix::ModuleMatrix,
+    micro::{self, MicroCode, MicroSymbol},
+    qr::{self, Code, DetectorParams, Output},
+    rmqr::{self, RmqrCode, RmqrSymbol},
+    segment::{Bitstream, Message},
+    util::Rect,
+    version,
+    viz::Visualizer,
+};
+
+const FINDER_NUM_ELEMS: usize = 7;
+
+/// How much scaling the downscaled view of the image does
+const DOWNSCALE_FACTOR: u32 = 2;
+
+/// How hard [`decode`] tries before giving up, see [`DecodeOptions::effort`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Effort {
+    /// Only reads the image as it is
+    Fast,
+    /// Also reads a QR code transposed, in case it is mirrored
+    #[default]
+    Normal,
+    /// Also tries the image inverted, rotated by each quarter turn and downscaled
+    Thorough,
+}
+
+/// Tunables for [`decode`], built up from the defaults
+///
+/// ```
+/// use qrious::{Binarizer, DecodeOptions, Effort};
+///
+/// let options = DecodeOptions::new()
+///     .binarizer(Binarizer::Otsu)
+///     .effort(Effort::Thorough)
+///     .versions(1..=10)
+///     .max_symbols(1);
+/// ```
+#[derive(Debug, Clone)]
+pub struct DecodeOptions {
+    binarizer: Binarizer,
+    try_inverted: bool,
+    try_rotations: bool,
+    try_downscaled: bool,
+    pure_barcode: bool,
+    max_symbols: usize,
+    detector: DetectorParams,
+}
+
+impl Default for DecodeOptions {
+    fn default() -> Self {
+        Self {
+            binarizer: Binarizer::default(),
+            try_inverted: false,
+            try_rotations: false,
+            try_downscaled: false,
+            pure_barcode: false,
+            max_symbols: usize::MAX,
+            detector: DetectorParams::default(),
+        }
+    }
+}
+
+impl DecodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn binarizer(mut self, binarizer: Binarizer) -> Self {
+        self.binarizer = binarizer;
+        self
+    }
+
+    /// Sets all the `try_*` options at once
+    pub fn effort(self, effort: Effort) -> Self {
+        let thorough = effort == Effort::Thorough;
+        self.try_mirrored(effort != Effort::Fast)
+            .try_inverted(thorough)
+            .try_rotations(thorough)
+            .try_downscaled(thorough)
+    }
+
+    /// Reads a QR code transposed too, when its format info isn't clean
+    pub fn try_mirrored(mut self, try_mirrored: bool) -> Self {
+        self.detector.try_mirrored = try_mirrored;
+        self
+    }
+
+    /// Tries light symbols on a dark background too
+    pub fn try_inverted(mut self, try_inverted: bool) -> Self {
+        self.try_inverted = try_inverted;
+        self
+    }
+
+    /// Tries the image turned by each quarter turn, the grid is only placed upright
+    pub fn try_rotations(mut self, try_rotations: bool) -> Self {
+        self.try_rotations = try_rotations;
+        self
+    }
+
+    /// Tries the image at a smaller scale, which smooths out noise in large images
+    pub fn try_downscaled(mut self, try_downscaled: bool) -> Self {
+        self.try_downscaled = try_downscaled;
+        self
+    }
+
+    /// The QR versions to expect, symbols of any other version are rejected
+    pub fn versions(mut self, versions: RangeInclusive<u8>) -> Self {
+        self.detector.versions = versions;
+        self
+    }
+
+    /// The Micro QR versions to expect, 1 (M1) to 4 (M4)
+    pub fn micro_versions(mut self, versions: RangeInclusive<u8>) -> Self {
+        self.detector.micro_versions = versions;
+        self
+    }
+
+    /// The rMQR version indicators to expect, 0 (R7x43) to 31 (R17x139)
+    pub fn rmqr_versions(mut self, versions: RangeInclusive<u8>) -> Self {
+        self.detector.rmqr_versions = versions;
+        self
+    }
+
+    /// The image is a generated symbol on a clean background rather than a photo.
+    /// The grid is then placed over the dark pixels directly, without looking for
+    /// the finders, and there's no point in trying harder on it. If that fails the
+    /// finders are looked for as usual.
+    pub fn pure_barcode(mut self, pure_barcode: bool) -> Self {
+        self.pure_barcode = pure_barcode;
+        self
+    }
+
+    /// Stops after this many symbols are decoded
+    pub fn max_symbols(mut self, max_symbols: usize) -> Self {
+        self.max_symbols = max_symbols;
+        self
+    }
+
+    /// Replaces the detector params, including the versions and mirroring set above
+    pub fn detector(mut self, detector: DetectorParams) -> Self {
+        self.detector = detector;
+        self
+    }
+
+    pub fn detector_params(&self) -> &DetectorParams {
+        &self.detector
+    }
+
+    /// The transformed images to look in, in order, the first that has a symbol wins
+    fn views(&self) -> Vec<View> {
+        let mut views = vec![View::Original];
+        if self.pure_barcode {
+            return views;
+        }
+        if self.try_inverted {
+            views.push(View::Inverted);
+        }
+        if self.try_rotations {
+            views.extend([View::Rotated90, View::Rotated180, View::Rotated270]);
+        }
+        if self.try_downscaled {
+            views.push(View::Downscaled);
+        }
+        views
+    }
+}
+
+/// A transformation of the image to try decoding
+#[derive(Debug, Clone, Copy)]
+enum View {
+    Original,
+    Inverted,
+    /// Turned clockwise
+    Rotated90,
+    Rotated180,
+    Rotated270,
+    Downscaled,
+}
+
+impl View {
+    fn apply<'a>(&self, img: &'a image::DynamicImage) -> Cow<'a, image::DynamicImage> {
+        match self {
+            Self::Original => Cow::Borrowed(img),
+            Self::Inverted => {
+                let mut inverted = img.clone();
+                inverted.invert();
+                Cow::Owned(inverted)
+            }
+            Self::Rotated90 => Cow::Owned(img.rotate90()),
+            Self::Rotated180 => Cow::Owned(img.rotate180()),
+            Self::Rotated270 => Cow::Owned(img.rotate270()),
+            Self::Downscaled => Cow::Owned(img.resize(
+                img.width() / DOWNSCALE_FACTOR,
+                img.height() / DOWNSCALE_FACTOR,
+                image::imageops::FilterType::Triangle,
+            )),
+        }
+    }
+
+    /// Maps `rect` in the transformed image back to the original, `width` by `height`, one
+    fn unmap(&self, rect: &Rect, width: u32, height: u32) -> Rect {
+        let (width, height) = (width as f32, height as f32);
+        let (left, top, right, bottom) = rect.to_corners();
+        match self {
+            Self::Original | Self::Inverted => rect.clone(),
+            Self::Rotated90 => Rect::from_corners(top, height - right, bottom, height - left),
+            Self::Rotated180 => {
+                Rect::from_corners(width - right, height - bottom, width - left, height - top)
+            }
+            Self::Rotated270 => Rect::from_corners(width - bottom, left, width - top, right),
+            Self::Downscaled => {
+                let scale = DOWNSCALE_FACTOR as f32;
+                Rect::from_corners(left * scale, top * scale, right * scale, bottom * scale)
+            }
+        }
+    }
+}
+
+/// The version of a symbol of any kind
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Version {
+    Qr(u8),
+    /// M1 to M4
+    Micro(u8),
+    /// rMQR versions are named after their size, as R{height}x{width}
+    Rmqr {
+        width: usize,
+        height: usize,
+    },
+}
+
+impl Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Version::Qr(version) => write!(f, "{version}"),
+            Version::Micro(version) => write!(f, "M{version}"),
+            Version::Rmqr { width, height } => write!(f, "R{height}x{width}"),
+        }
+    }
+}
+
+/// The kind of symbol that was decoded, with what its format info says about it
+#[derive(Debug, Clone, PartialEq)]
+pub enum SymbolKind {
+    Qr {
+        version: u8,
+        mask: u8,
+        mirrored: bool,
+        /// How the mask the generator picked scores, see [`crate::encode::penalty`]
+        penalty: Penalty,
+    },
+    Micro {
+        version: u8,
+        mask: u8,
+    },
+    /// The version indicator, and the size it stands for
+    Rmqr {
+        version: u8,
+        width: usize,
+        height: usize,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedSymbol {
+    pub kind: SymbolKind,
+    /// M1 symbols have no error correction, only detection
+    pub ec_level: Option<EcLevel>,
+    /// Where the symbol is in the image, in pixels
+    pub bounds: Rect,
+    pub message: Message,
+}
+
+impl DecodedSymbol {
+    pub fn text(&self) -> String {
+        self.message.text()
+    }
+}
+
+/// The grid placed over a symbol in the image, whichever kind it is
+#[derive(Clone, Copy)]
+pub enum Grid<'a> {
+    Qr(&'a Code),
+    Micro(&'a MicroCode),
+    Rmqr(&'a RmqrCode),
+}
+
+impl Grid<'_> {
+    pub fn bounds(&self) -> &Rect {
+        match self {
+            Grid::Qr(code) => &code.bounds,
+            Grid::Micro(code) => &code.bounds,
+            Grid::Rmqr(code) => &code.bounds,
+        }
+    }
+
+    /// The module in column `x` and row `y`, in pixels
+    pub fn idx_to_module(&self, x: usize, y: usize) -> Rect {
+        match self {
+            Grid::Qr(code) => code.idx_to_module(x, y),
+            Grid::Micro(code) => code.idx_to_module(x, y),
+            Grid::Rmqr(code) => code.idx_to_module(x, y),
+        }
+    }
+}
+
+/// A symbol read from its grid, with its format info, before its data is decoded
+#[derive(Clone, Copy)]
+pub enum ReadSymbol<'a> {
+    Qr(&'a qr::Symbol),
+    Micro(&'a MicroSymbol),
+    Rmqr(&'a RmqrSymbol),
+}
+
+impl ReadSymbol<'_> {
+    pub fn matrix(&self) -> &ModuleMatrix {
+        match self {
+            ReadSymbol::Qr(symbol) => &symbol.matrix,
+            ReadSymbol::Micro(symbol) => &symbol.matrix,
+            ReadSymbol::Rmqr(symbol) => &symbol.matrix,
+        }
+    }
+
+    pub fn block_layout(&self) -> BlockLayout {
+        match self {
+            ReadSymbol::Qr(symbol) => version::block_layout(symbol.version, symbol.format.ec_level),
+            ReadSymbol::Micro(symbol) => symbol.format.block_layout(),
+            ReadSymbol::Rmqr(symbol) => symbol.format.block_layout(),
+        }
+    }
+
+    /// M1 symbols only detect errors, they can't correct them
+    pub fn corrects_errors(&self) -> bool {
+        !matches!(self, ReadSymbol::Micro(symbol) if symbol.format.ec_level.is_none())
+    }
+
+    /// The codewords as read, data then ec for Micro QR and interleaved otherwise
+    pub fn codewords(&self) -> Result<Vec<u8>> {
+        Ok(match self {
+            ReadSymbol::Qr(symbol) => symbol.data_iter()?.collect(),
+            ReadSymbol::Micro(symbol) => symbol.codewords(),
+            ReadSymbol::Rmqr(symbol) => symbol.codewords(),
+        })
+    }
+
+    /// Which block, codeword and bit each data module holds, at its position in
+    /// [`Self::matrix`]
+    pub fn module_map(&self) -> Vec<ModuleBit> {
+        match self {
+            ReadSymbol::Qr(symbol) => qr::module_map(symbol.version, symbol.format.ec_level),
+            ReadSymbol::Micro(symbol) => symbol.format.module_map(),
+            ReadSymbol::Rmqr(symbol) => symbol.format.module_map(),
+        }
+    }
+
+    /// Unmasked data bits in placement order, at their positions in [`Self::matrix`]
+    pub fn bit_iter(&self) -> Result<Box<dyn Iterator<Item = Output> + '_>> {
+        Ok(match self {
+            ReadSymbol::Qr(symbol) => Box::new(symbol.bit_iter()?),
+            ReadSymbol::Micro(symbol) => Box::new(symbol.bit_iter()),
+            ReadSymbol::Rmqr(symbol) => Box::new(symbol.bit_iter()),
+        })
+    }
+
+    /// Maps a position in [`Self::matrix`] back to the grid sampled from the image,
+    /// which differs for a mirrored QR code
+    pub fn grid_position(&self, x: usize, y: usize) -> (usize, usize) {
+        match self {
+            ReadSymbol::Qr(symbol) => symbol.grid_position(x, y),
+            _ => (x, y),
+        }
+    }
+
+    pub fn bitstream(&self) -> Result<Bitstream> {
+        match self {
+            ReadSymbol::Qr(symbol) => symbol.bitstream(),
+            ReadSymbol::Micro(symbol) => symbol.bitstream(),
+            ReadSymbol::Rmqr(symbol) => symbol.bitstream(),
+        }
+    }
+}
+
+/// Hooks into the steps of [`decode_with`], to inspect or draw what each of them
+/// found, in pixels of the image that was searched. They all do nothing by default,
+/// and an error from one stops decoding.
+pub trait DecodeObserver {
+    /// Where looking for the finders draws its candidates
+    fn visualizer(&mut self) -> Option<&mut dyn Visualizer> {
+        None
+    }
+
+    fn finders(&mut self, _finders: &[Rect]) -> Result<()> {
+        Ok(())
+    }
+
+    fn grid(&mut self, _img: &image::DynamicImage, _grid: Grid) -> Result<()> {
+        Ok(())
+    }
+
+    /// Called before the data of `symbol` is decoded, which can still fail
+    fn symbol(
+        &mut self,
+        _img: &image::DynamicImage,
+        _grid: Grid,
+        _symbol: ReadSymbol,
+    ) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Observes nothing, for [`decode`]
+struct NoObserver;
+
+impl DecodeObserver for NoObserver {}
+
+/// Finds and decodes the symbols in `img`. A single finder is a Micro QR code, or
+/// an rMQR code if it doesn't have the timing patterns of one, and two or three
+/// finders are a QR code. More finders are grouped into QR codes by their size
+/// and the triangle they make.
+///
+/// When nothing is found in the image as it is, the views the options ask for are
+/// tried in turn, and the error from the image as it is is returned if none work.
+pub fn decode(img: &image::DynamicImage, options: &DecodeOptions) -> Result<Vec<DecodedSymbol>> {
+    decode_with(img, options, &mut NoObserver)
+}
+
+/// [`decode`], telling `observer` about each step. Every view that is tried is
+/// observed in turn, and the pure barcode path isn't observed at all.
+pub fn decode_with(
+    img: &image::DynamicImage,
+    options: &DecodeOptions,
+    observer: &mut dyn DecodeObserver,
+) -> Result<Vec<DecodedSymbol>> {
+    if options.pure_barcode {
+        if let Ok(symbol) = decode_pure(img, options) {
+            return Ok(vec![symbol]);
+        }
+    }
+
+    let (width, height) = img.dimensions();
+    let mut first_err = None;
+    for view in options.views() {
+        let viewed = options.binarizer.binarize(&view.apply(img));
+        match decode_view(&viewed, options, observer) {
+            Ok(mut symbols) => {
+                symbols.truncate(options.max_symbols);
+                for symbol in symbols.iter_mut() {
+                    symbol.bounds = view.unmap(&symbol.bounds, width, height);
+                }
+                return Ok(symbols);
+            }
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+    Err(first_err.expect("The image as it is is always tried"))
+}
+
+fn decode_view(
+    img: &image::DynamicImage,
+    options: &DecodeOptions,
+    observer: &mut dyn DecodeObserver,
+) -> Result<Vec<DecodedSymbol>> {
+    let finders = qr::find_patterns(img, &options.detector, observer.visualizer())?;
+    observer.finders(&finders)?;
+    let symbol = match finders.as_slice() {
+        [finder] => match MicroCode::from_finder(img, finder, &options.detector) {
+            Ok(code) => decode_micro(img, &code, observer)?,
+            Err(_) => decode_rmqr(
+                img,
+                &RmqrCode::from_finder(img, finder, &options.detector)?,
+                observer,
+            )?,
+        },
+        [_, _] | [_, _, _] => decode_qr(
+            img,
+            &Code::from_finders(img, &finders, &options.detector, None)?,
+            observer,
+        )?,
+        [] => {
+            return Err(DecodeError::FinderNotFound {
+                expected: 3,
+                found: 0,
+            })
+        }
+        _ => return decode_many(img, &finders, options, observer),
+    };
+    Ok(vec![symbol])
+}
+
+/// Samples the grid straight from where the dark pixels are, for an image that has
+/// nothing but the symbol in it
+fn decode_pure(img: &image::DynamicImage, options: &DecodeOptions) -> Result<DecodedSymbol> {
+    // with a global threshold only the pixels that are read need to be compared,
+    // anything else binarizes to 0 and 255 first
+    let binarized;
+    let (img, threshold) = match options.binarizer {
+        Binarizer::Threshold(threshold) => (img, threshold),
+        binarizer => {
+            binarized = binarizer.binarize(img);
+            (&binarized, 128)
+        }
+    };
+    let is_dark = |x: u32, y: u32| img.get_pixel(x, y).to_luma().0[0] <= threshold;
+
+    let (bounds, width, height) =
+        locate_pure(img.dimensions(), is_dark).ok_or(DecodeError::FinderNotFound {
+            expected: 1,
+            found: 0,
+        })?;
+    check_size(width, height)?;
+    let matrix = sample_centres(&bounds, width, height, is_dark);
+    let mut symbol = decode_matrix(matrix, options)?;
+    symbol.bounds = bounds;
+    Ok(symbol)
+}
+
+/// Decodes modules that were sampled some other way, or built by hand. The kind of
+/// symbol is told by the size: rMQR if it isn't square, Micro QR up to M4 and QR
+/// above. The bounds of the result are in modules.
+pub fn decode_matrix(matrix: ModuleMatrix, options: &DecodeOptions) -> Result<DecodedSymbol> {
+    let (width, height) = (matrix.width(), matrix.height());
+    check_size(width, height)?;
+    let bounds = Rect::from_corners(0.0, 0.0, width as f32, height as f32);
+    if width != height {
+        let symbol = RmqrSymbol::new(matrix)?.check_version(&options.detector)?;
+        DecodedSymbol::from_rmqr(&symbol, bounds)
+    } else if width <= micro::version_to_size(micro::MAX_VERSION) {
+        let symbol = MicroSymbol::new(matrix)?.check_version(&options.detector)?;
+        DecodedSymbol::from_micro(&symbol, bounds)
+    } else {
+        let code = Code::from_bounds(bounds.clone(), width, &options.detector);
+        DecodedSymbol::from_qr(&code.read_matrix(matrix)?, bounds)
+    }
+}
+
+fn check_size(width: usize, height: usize) -> Result<()> {
+    let is_valid_size = if width != height {
+        rmqr::is_valid_size(width, height)
+    } else {
+        version::size_to_version(width).is_some()
+            || (1..=micro::MAX_VERSION).any(|version| micro::version_to_size(version) == width)
+    };
+    if !is_valid_size {
+        return Err(DecodeError::InvalidSize { width, height });
+    }
+    Ok(())
+}
+
+/// Reads each module from the pixel at its centre, which is enough for a clean image
+fn sample_centres(
+    bounds: &Rect,
+    width: usize,
+    height: usize,
+    is_dark: impl Fn(u32, u32) -> bool,
+) -> ModuleMatrix {
+    let elem_width = bounds.width() / width as f32;
+    let elem_height = bounds.height() / height as f32;
+    let mut matrix = ModuleMatrix::new(width, height);
+    for y in 0..height {
+        for x in 0..width {
+            let px = bounds.left() + (x as f32 + 0.5) * elem_width;
+            let py = bounds.top() + (y as f32 + 0.5) * elem_height;
+            matrix.set(x, y, is_dark(px as u32, py as u32));
+        }
+    }
+    matrix
+}
+
+/// The bounds of the dark pixels, and how many modules they span each way. Every
+/// kind of symbol has a finder in its top left corner, and dark modules somewhere
+/// along each of its other edges, so on a clean background those are the bounds of
+/// the symbol. The top edge of the finder is the first run, and 7 modules wide.
+fn locate_pure(
+    (width, height): (u32, u32),
+    is_dark: impl Fn(u32, u32) -> bool,
+) -> Option<(Rect, usize, usize)> {
+    let (left, top) = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .find(|&(x, y)| is_dark(x, y))?;
+    let bottom = (top..height)
+        .rev()
+        .find(|&y| (0..width).any(|x| is_dark(x, y)))?
+        + 1;
+    let right = (left..width)
+        .rev()
+        .find(|&x| (top..bottom).any(|y| is_dark(x, y)))?
+        + 1;
+
+    let finder_width = (left..right).take_while(|&x| is_dark(x, top)).count();
+    let elem_size = finder_width as f32 / FINDER_NUM_ELEMS as f32;
+    let bounds = Rect::from_corners(left as f32, top as f32, right as f32, bottom as f32);
+    let num_cols = (bounds.width() / elem_size).round() as usize;
+    let num_rows = (bounds.height() / elem_size).round() as usize;
+    Some((bounds, num_cols, num_rows))
+}
+
+/// Decodes every group of three finders that isn't part of an already decoded symbol
+fn decode_many(
+    img: &image::DynamicImage,
+    finders: &[Rect],
+    options: &DecodeOptions,
+    observer: &mut dyn DecodeObserver,
+) -> Result<Vec<DecodedSymbol>> {
+    let mut symbols: Vec<DecodedSymbol> = vec![];
+    let mut first_err = None;
+    for group in finder_groups(finders, options.detector.finder_ratio_tolerance) {
+        if symbols.len() >= options.max_symbols {
+            break;
+        }
+        let taken = symbols.iter().any(|symbol| {
+            group
+                .iter()
+                .any(|finder| symbol.bounds.contains(finder.cx(), finder.cy()))
+        });
+        if taken {
+            continue;
+        }
+        match Code::from_finders(img, &group, &options.detector, None)
+            .and_then(|code| decode_qr(img, &code, observer))
+        {
+            Ok(symbol) => symbols.push(symbol),
+            Err(err) => {
+                first_err.get_or_insert(err);
+            }
+        }
+    }
+
+    if symbols.is_empty() {
+        return Err(first_err.unwrap_or(DecodeError::FinderNotFound {
+            expected: 3,
+            found: finders.len(),
+        }));
+    }
+    Ok(symbols)
+}
+
+/// Groups of three finders of about the same size, that sit on the corners of a
+/// right isosceles triangle like the finders of a QR code do
+fn finder_groups(finders: &[Rect], tolerance: f32) -> Vec<[Rect; 3]> {
+    let is_almost_same = |a: f32, b: f32| (a / b - 1.0).abs() < tolerance;
+    let distance = |a: &Rect, b: &Rect| (a.cx() - b.cx()).hypot(a.cy() - b.cy());
+
+    let mut groups = vec![];
+    for (i, a) in finders.iter().enumerate() {
+        for (j, b) in finders.iter().enumerate().skip(i + 1) {
+            for c in finders.iter().skip(j + 1) {
+                let size = (a.width() + b.width() + c.width()) / 3.0;
+                if ![a, b, c].iter().all(|f| is_almost_same(f.width(), size)) {
+                    continue;
+                }
+                let mut sides = [distance(a, b), distance(b, c), distance(a, c)];
+                sides.sort_by(f32::total_cmp);
+                if is_almost_same(sides[0], sides[1])
+                    && is_almost_same(sides[2], sides[0].hypot(sides[1]))
+                {
+                    groups.push([a.clone(), b.clone(), c.clone()]);
+                }
+            }
+        }
+    }
+    groups
+}
+
+fn decode_qr(
+    img: &image::DynamicImage,
+    code: &Code,
+    observer: &mut dyn DecodeObserver,
+) -> Result<DecodedSymbol> {
+    observer.grid(img, Grid::Qr(code))?;
+    let symbol = code.read_symbol(img)?;
+    observer.symbol(img, Grid::Qr(code), ReadSymbol::Qr(&symbol))?;
+    DecodedSymbol::from_qr(&symbol, code.bounds.clone())
+}
+
+fn decode_micro(
+    img: &image::DynamicImage,
+    code: &MicroCode,
+    observer: &mut dyn DecodeObserver,
+) -> Result<DecodedSymbol> {
+    observer.grid(img, Grid::Micro(code))?;
+    let symbol = code.read_symbol(img)?;
+    observer.symbol(img, Grid::Micro(code), ReadSymbol::Micro(&symbol))?;
+    DecodedSymbol::from_micro(&symbol, code.bounds.clone())
+}
+
+fn decode_rmqr(
+    img: &image::DynamicImage,
+    code: &RmqrCode,
+    observer: &mut dyn DecodeObserver,
+) -> Result<DecodedSymbol> {
+    observer.grid(img, Grid::Rmqr(code))?;
+    let symbol = code.read_symbol(img)?;
+    observer.symbol(img, Grid::Rmqr(code), ReadSymbol::Rmqr(&symbol))?;
+    DecodedSymbol::from_rmqr(&symbol, code.bounds.clone())
+}
+
+impl DecodedSymbol {
+    fn from_qr(symbol: &qr::Symbol, bounds: Rect) -> Result<Self> {
+        Ok(Self {
+            kind: SymbolKind::Qr {
+                version: symbol.version,
+                mask: symbol.format.mask,
+                mirrored: symbol.mirrored,
+                penalty: symbol.penalty(),
+            },
+            ec_level: Some(symbol.format.ec_level),
+            bounds,
+            message: symbol.decode()?,
+        })
+    }
+
+    fn from_micro(symbol: &MicroSymbol, bounds: Rect) -> Result<Self> {
+        Ok(Self {
+            kind: SymbolKind::Micro {
+                version: symbol.format.version,
+                mask: symbol.format.mask,
+            },
+            ec_level: symbol.format.ec_level,
+            bounds,
+            message: symbol.decode()?,
+        })
+    }
+
+    fn from_rmqr(symbol: &RmqrSymbol, bounds: Rect) -> Result<Self> {
+        Ok(Self {
+            kind: SymbolKind::Rmqr {
+                version: symbol.format.version,
+                width: symbol.format.width(),
+                height: symbol.format.height(),
+            },
+            ec_level: Some(symbol.format.ec_level),
+            bounds,
+            message: symbol.decode()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let options = DecodeOptions::default();
+        for (path, text) in [
+            ("test-data/hello-world.png", "hello world"),
+            ("test-data/micro-m2.png", "01234567"),
+            ("test-data/rmqr-r11x43.png", "qrious rMQR"),
+        ] {
+            let img = image::open(path).unwrap();
+            let symbols = decode(&img, &options).unwrap();
+            assert_eq!(symbols.len(), 1);
+            assert_eq!(symbols[0].text(), text);
+        }
+    }
+
+    #[test]
+    fn test_decode_pure() {
+        for (path, text) in [
+            ("test-data/hello-world.png", "hello world"),
+            ("test-data/micro-m2.png", "01234567"),
+            ("test-data/rmqr-r11x43.png", "qrious rMQR"),
+        ] {
+            let img = image::open(path).unwrap();
+            let symbol = decode_pure(&img, &DecodeOptions::new()).unwrap();
+            assert_eq!(symbol.text(), text);
+        }
+    }
+
+    #[test]
+    fn test_decode_matrix() {
+        let matrix = crate::encode(b"HELLO WORLD", EcLevel::Q).unwrap().matrix;
+        let options = DecodeOptions::new();
+        assert_eq!(
+            decode_matrix(matrix.clone(), &options).unwrap().text(),
+            "HELLO WORLD"
+        );
+
+        let mirrored = decode_matrix(matrix.transpose(), &options).unwrap();
+        assert!(matches!(
+            mirrored.kind,
+            SymbolKind::Qr { mirrored: true, .. }
+        ));
+        assert_eq!(mirrored.text(), "HELLO WORLD");
+        assert!(decode_matrix(matrix.rotate(), &options).is_err());
+    }
+
+    #[test]
+    fn test_decode_views() {
+        let img = image::open("test-data/hello-world.png").unwrap();
+        let upright = decode(&img, &DecodeOptions::new()).unwrap();
+        let thorough = DecodeOptions::new().effort(Effort::Thorough);
+
+        let mut inverted = img.clone();
+        inverted.invert();
+        for img in [img.rotate90(), img.rotate180(), img.rotate270(), inverted] {
+            assert!(decode(&img, &DecodeOptions::new()).is_err());
+            let symbols = decode(&img, &thorough).unwrap();
+            assert_eq!(symbols[0].text(), "hello world");
+        }
+
+        // the bounds are mapped back to the image that was passed in
+        let rotated = decode(&img.rotate180(), &thorough).unwrap();
+        let (width, height) = img.dimensions();
+        let (left, top, right, bottom) = upright[0].bounds.to_corners();
+        let expected = Rect::from_corners(
+            width as f32 - right,
+            height as f32 - bottom,
+            width as f32 - left,
+            height as f32 - top,
+        );
+        assert!((rotated[0].bounds.left() - expected.left()).abs() < 1.0);
+        assert!((rotated[0].bounds.top() - expected.top()).abs() < 1.0);
+    }
+
+    #[test]
+    fn test_decode_versions() {
+        let img = image::open("test-data/hello-world.png").unwrap();
+        let result = decode(&img, &DecodeOptions::new().versions(2..=40));
+        assert!(matches!(
+            result,
+            Err(DecodeError::VersionOutOfRange { version: 1, .. })
+        ));
+
+        let img = image::open("test-data/micro-m2.png").unwrap();
+        let result = decode(&img, &DecodeOptions::new().micro_versions(3..=4));
+        assert!(matches!(
+            result,
+            Err(DecodeError::VersionOutOfRange { version: 2, .. })
+        ));
+        assert!(decode(&img, &DecodeOptions::new().versions(2..=40)).is_ok());
+
+        // R11x43
+        let img = image::open("test-data/rmqr-r11x43.png").unwrap();
+        let result = decode(&img, &DecodeOptions::new().rmqr_versions(0..=10));
+        assert!(matches!(
+            result,
+            Err(DecodeError::VersionOutOfRange { version: 11, .. })
+        ));
+    }
+
+    #[test]
+    fn test_decode_cropped() {
+        // only the top left finder is left, right at the edge
+        let img = image::open("test-data/hello-world.png").unwrap();
+        let cropped = img.crop_imm(0, 0, 40, 40);
+        assert!(decode(&cropped, &DecodeOptions::default()).is_err());
+    }
+
+    #[test]
+    fn test_decode_cropped_finder() {
+        // two finders are left, and the third would be placed outside the image
+        let img = image::open("test-data/hello-world.png").unwrap();
+        let (width, height) = img.dimensions();
+        for percent in [70, 80, 90] {
+            let narrow = img.crop_imm(0, 0, width * percent / 100, height);
+            assert!(decode(&narrow, &DecodeOptions::default()).is_err());
+            let short = img.crop_imm(0, 0, width, height * percent / 100);
+            assert!(decode(&short, &DecodeOptions::default()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_uneven_lighting() {
+        let img = image::open("test-data/hello-world.png").unwrap().to_luma8();
+        let width = img.width();
+        // darken towards the right, so the light modules there end up below middle grey
+        let shaded = image::ImageBuffer::from_fn(width, img.height(), |x, y| {
+            let shade = 1.0 - 0.75 * x as f32 / width as f32;
+            image::Luma([(img.get_pixel(x, y).0[0] as f32 * shade) as u8])
+        });
+        let shaded = image::DynamicImage::ImageLuma8(shaded);
+
+        assert!(decode(&shaded, &DecodeOptions::new()).is_err());
+        let adaptive = DecodeOptions::new().binarizer(Binarizer::Adaptive {
+            radius: 15,
+            min_contrast: 20,
+        });
+        let symbols = decode(&shaded, &adaptive).unwrap();
+        assert_eq!(symbols[0].text(), "hello world");
+    }
+
+    #[test]
+    fn test_decode_many() {
+        let img = image::open("test-data/hello-world.png").unwrap().to_luma8();
+        let (width, height) = img.dimensions();
+        let mut pair = image::GrayImage::from_pixel(width * 2, height, image::Luma([255]));
+        image::imageops::replace(&mut pair, &img, 0, 0);
+        image::imageops::replace(&mut pair, &img, width as i64, 0);
+        let pair = image::DynamicImage::ImageLuma8(pair);
+
+        let symbols = decode(&pair, &DecodeOptions::new()).unwrap();
+        assert_eq!(symbols.len(), 2);
+        assert!(symbols.iter().all(|symbol| symbol.text() == "hello world"));
+
+        let symbols = decode(&pair, &DecodeOptions::new().max_symbols(1)).unwrap();
+        assert_eq!(symbols.len(), 1);
+    }
+}
@@ -0,0 +1,412 @@
+use std::ops::RangeInclusive;
+
+use crate::{
+    error::EncodeError,
+    format::{self, EcLevel, FormatInfo},
+    matrix::ModuleMatrix,
+    qr::{self, get_mask_fn, Symbol},
+    segment::{self, BitWriter, HeaderSpec, Mode, Segment},
+    version,
+};
+
+const FINDER_NUM_ELEMS: usize = 7;
+const TIMER_PATTERN_OFFSET: usize = FINDER_NUM_ELEMS - 1;
+const ALIGNMENT_PATTERN_NUM_ELEMS: usize = 5;
+/// Alternate to fill up the data codewords after the terminator
+const PAD_CODEWORDS: [u8; 2] = [0xEC, 0x11];
+const NUM_MASKS: u8 = 8;
+/// Versions that share the widths of the character counts, so the same segments
+const VERSION_BANDS: [RangeInclusive<u8>; 3] = [1..=9, 10..=26, 27..=40];
+
+/// Penalty weights from the spec, for runs, blocks, finder-like patterns and balance
+const PENALTY_RUN: u32 = 3;
+const PENALTY_BLOCK: u32 = 3;
+const PENALTY_FINDER_LIKE: u32 = 40;
+const PENALTY_BALANCE: u32 = 10;
+/// Runs start to count at this length
+const MIN_PENALTY_RUN: usize = 5;
+/// 1:1:3:1:1 with 4 light modules on one side
+const FINDER_LIKE_PATTERNS: [[bool; 11]; 2] = [
+    [
+        true, false, true, true, true, false, true, false, false, false, false,
+    ],
+    [
+        false, false, false, false, true, false, true, true, true, false, true,
+    ],
+];
+
+/// What goes into a symbol besides the data. Anything not forced is picked to
+/// make the symbol as small, and then as easy to read, as it can be.
+///
+/// ```
+/// use qrious::{format::EcLevel, segment::Mode, EncodeOptions};
+///
+/// let options = EncodeOptions::new().ec_level(EcLevel::Q).mode(Mode::Byte);
+/// let symbol = qrious::encode_with(b"12345", &options).unwrap();
+/// assert_eq!(symbol.decode().unwrap().segments[0].mode, Mode::Byte);
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct EncodeOptions {
+    ec_level: EcLevel,
+    mask: Option<u8>,
+    mode: Option<Mode>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            ec_level: EcLevel::M,
+            mask: None,
+            mode: None,
+        }
+    }
+}
+
+impl EncodeOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn ec_level(mut self, ec_level: EcLevel) -> Self {
+        self.ec_level = ec_level;
+        self
+    }
+
+    /// Uses this mask pattern, instead of the one with the lowest [`penalty`]
+    pub fn mask(mut self, mask: u8) -> Self {
+        self.mask = Some(mask);
+        self
+    }
+
+    /// Puts all the data in segments of this mode, instead of switching between
+    /// the modes to take the fewest bits. The data has to be valid for it.
+    pub fn mode(mut self, mode: Mode) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+}
+
+/// Encodes `data` into the smallest QR code that holds it at `ec_level`, with
+/// the default [`EncodeOptions`] otherwise
+pub fn encode(data: &[u8], ec_level: EcLevel) -> Result<Symbol, EncodeError> {
+    encode_with(data, &EncodeOptions::new().ec_level(ec_level))
+}
+
+/// Encodes `data` into the smallest QR code that holds it. Unless forced, the mode
+/// switches wherever that saves bits, and every mask is tried to keep the one with
+/// the lowest [`penalty`].
+pub fn encode_with(data: &[u8], options: &EncodeOptions) -> Result<Symbol, EncodeError> {
+    let ec_level = options.ec_level;
+    let (version, segments) = plan_segments(data, options)?;
+    let masks = match options.mask {
+        Some(mask) => mask..=mask,
+        None => 0..=NUM_MASKS - 1,
+    };
+
+    let mut best: Option<(u32, Symbol)> = None;
+    for mask in masks {
+        let format = FormatInfo { ec_level, mask };
+        let matrix = build_matrix(&segments, version, &format)?;
+        let score = penalty(&matrix).total();
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, _)| score < *best_score)
+        {
+            let symbol = Symbol {
+                matrix,
+                format,
+                format_errors: 0,
+                version,
+                mirrored: false,
+            };
+            best = Some((score, symbol));
+        }
+    }
+    Ok(best.expect("There is at least one mask").1)
+}
+
+/// How much a masked symbol looks like it could trip up a reader, by each of the
+/// four rules masks are chosen by. Lower is better.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Penalty {
+    /// Runs of 5 or more modules of the same colour in a row or column
+    pub runs: u32,
+    /// 2x2 blocks of the same colour, overlapping ones each count
+    pub blocks: u32,
+    /// 1:1:3:1:1 patterns next to 4 light modules, which look like finders
+    pub finder_like: u32,
+    /// How far the share of dark modules is from half
+    pub balance: u32,
+}
+
+impl Penalty {
+    pub fn total(&self) -> u32 {
+        self.runs + self.blocks + self.finder_like + self.balance
+    }
+}
+
+/// Scores the modules of a QR code by the penalty rules of the spec, for picking
+/// a mask or checking the one a generator picked. Finder-like patterns are only
+/// looked for within the symbol, not running into the quiet zone.
+pub fn penalty(matrix: &ModuleMatrix) -> Penalty {
+    let (width, height) = (matrix.width(), matrix.height());
+    let rows = (0..height).map(|y| (0..width).map(|x| matrix.get(x, y)).collect::<Vec<_>>());
+    let cols = (0..width).map(|x| (0..height).map(|y| matrix.get(x, y)).collect::<Vec<_>>());
+    let lines: Vec<Vec<bool>> = rows.chain(cols).collect();
+
+    let runs = lines
+        .iter()
+        .flat_map(|line| line.chunk_by(|a, b| a == b))
+        .filter(|run| run.len() >= MIN_PENALTY_RUN)
+        .map(|run| PENALTY_RUN + (run.len() - MIN_PENALTY_RUN) as u32)
+        .sum();
+
+    let num_blocks = (1..height)
+        .flat_map(|y| (1..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| {
+            let colour = matrix.get(x, y);
+            [(x - 1, y), (x, y - 1), (x - 1, y - 1)]
+                .iter()
+                .all(|&(x, y)| matrix.get(x, y) == colour)
+        })
+        .count() as u32;
+
+    let num_finder_like = lines
+        .iter()
+        .flat_map(|line| line.windows(FINDER_LIKE_PATTERNS[0].len()))
+        .filter(|window| FINDER_LIKE_PATTERNS.iter().any(|pattern| window == pattern))
+        .count() as u32;
+
+    let total = width * height;
+    let dark = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .filter(|&(x, y)| matrix.get(x, y))
+        .count();
+    // whole 5% steps off 50%, from the exact ratio
+    let deviation = (dark * 20).abs_diff(total * 10) / total;
+
+    Penalty {
+        runs,
+        blocks: num_blocks * PENALTY_BLOCK,
+        finder_like: num_finder_like * PENALTY_FINDER_LIKE,
+        balance: deviation as u32 * PENALTY_BALANCE,
+    }
+}
+
+fn segments_len(segments: &[Segment], spec: &HeaderSpec) -> usize {
+    segments
+        .iter()
+        .map(|segment| spec.segment_len(segment.mode, segment.count))
+        .sum()
+}
+
+fn data_capacity(version: u8, ec_level: EcLevel) -> usize {
+    version::block_layout(version, ec_level).num_data_codewords() * 8
+}
+
+/// The smallest version that holds the data, with the segments it's split into.
+/// The segments only change with the widths of the counts, so once per band.
+fn plan_segments(data: &[u8], options: &EncodeOptions) -> Result<(u8, Vec<Segment>), EncodeError> {
+    if let Some(mode) = options.mode {
+        if let Some(index) = segment::first_invalid(data, mode) {
+            return Err(EncodeError::InvalidData { mode, index });
+        }
+    }
+
+    let mut needed = 0;
+    for band in VERSION_BANDS {
+        let spec = HeaderSpec::qr(*band.start());
+        let segments = match options.mode {
+            Some(mode) => segment::split_segments(data, mode, &spec),
+            None => segment::optimal_segments(data, &spec),
+        };
+        needed = segments_len(&segments, &spec);
+        if let Some(version) = band
+            .into_iter()
+            .find(|&version| needed <= data_capacity(version, options.ec_level))
+        {
+            return Ok((version, segments));
+        }
+    }
+    Err(EncodeError::DataTooLong {
+        needed,
+        capacity: data_capacity(version::MAX_VERSION, options.ec_level),
+    })
+}
+
+/// The segments followed by the terminator, padded to fill all data codewords
+fn data_codewords(segments: &[Segment], version: u8, ec_level: EcLevel) -> Vec<u8> {
+    let spec = HeaderSpec::qr(version);
+    let capacity = data_capacity(version, ec_level);
+    let mut writer = BitWriter::new();
+    segment::write_segments(&mut writer, segments, &spec);
+    writer.write(0, spec.terminator_bits.min(capacity - writer.len()));
+
+    let mut codewords = writer.into_bytes();
+    let num_pad = capacity / 8 - codewords.len();
+    codewords.extend(PAD_CODEWORDS.iter().cycle().take(num_pad));
+    codewords
+}
+
+fn build_matrix(
+    segments: &[Segment],
+    version: u8,
+    format: &FormatInfo,
+) -> Result<ModuleMatrix, EncodeError> {
+    let mask_fn = get_mask_fn(format.mask).ok_or(EncodeError::InvalidMask { mask: format.mask })?;
+    let codewords = version::block_layout(version, format.ec_level).interleave(&data_codewords(
+        segments,
+        version,
+        format.ec_level,
+    ));
+
+    let mut matrix = function_patterns(version);
+    // the remainder bits after the last codeword are zero
+    for (i, (x, y)) in qr::data_positions(version).into_iter().enumerate() {
+        let bit = codewords
+            .get(i / 8)
+            .is_some_and(|codeword| codeword >> (7 - i % 8) & 1 == 1);
+        matrix.set(x, y, bit != mask_fn(x as u32, y as u32));
+    }
+    format::write_format_info(&mut matrix, format);
+    Ok(matrix)
+}
+
+/// Finders, timing and alignment patterns, the dark module and the version info.
+/// Separators and the format info are left light.
+fn function_patterns(version: u8) -> ModuleMatrix {
+    let size = version::version_to_size(version);
+    let mut matrix = ModuleMatrix::new(size, size);
+
+    // rings around the centre, only the one next to the centre block is light
+    let mut draw_rings = |left: usize, top: usize, num_elems: usize, light_ring: usize| {
+        let centre = num_elems / 2;
+        for y in 0..num_elems {
+            for x in 0..num_elems {
+                let ring = x.abs_diff(centre).max(y.abs_diff(centre));
+                matrix.set(left + x, top + y, ring != light_ring);
+            }
+        }
+    };
+    let far = size - FINDER_NUM_ELEMS;
+    for (left, top) in [(0, 0), (far, 0), (0, far)] {
+        draw_rings(left, top, FINDER_NUM_ELEMS, 2);
+    }
+    for top_left in version::alignment_pattern_positions(version) {
+        draw_rings(top_left.x, top_left.y, ALIGNMENT_PATTERN_NUM_ELEMS, 1);
+    }
+
+    for i in FINDER_NUM_ELEMS + 1..far - 1 {
+        matrix.set(i, TIMER_PATTERN_OFFSET, i.is_multiple_of(2));
+        matrix.set(TIMER_PATTERN_OFFSET, i, i.is_multiple_of(2));
+    }
+    matrix.set(FINDER_NUM_ELEMS + 1, far - 1, true);
+    format::write_version_info(&mut matrix, version);
+    matrix
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{self, RenderOptions};
+
+    #[test]
+    fn test_data_codewords() {
+        // "HELLO WORLD" 1-M from the nayuki/thonky walkthroughs
+        let spec = HeaderSpec::qr(1);
+        let segments = segment::split_segments(b"HELLO WORLD", Mode::Alphanumeric, &spec);
+        assert_eq!(
+            data_codewords(&segments, 1, EcLevel::M),
+            vec![32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17]
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip() {
+        let long_text = "qrious ".repeat(40);
+        for (text, ec_level, expected_version) in [
+            ("01234567", EcLevel::H, 1),
+            ("HELLO WORLD", EcLevel::Q, 1),
+            ("hello world", EcLevel::L, 1),
+            (long_text.as_str(), EcLevel::M, 12),
+        ] {
+            let symbol = encode(text.as_bytes(), ec_level).unwrap();
+            assert_eq!(symbol.version, expected_version);
+            assert_eq!(symbol.decode().unwrap().text(), text);
+
+            let options = RenderOptions::new().module_size(4);
+            let img = image::DynamicImage::ImageRgb8(render::to_image(&symbol.matrix, &options));
+            let decoded = crate::decode(&img, &crate::DecodeOptions::new()).unwrap();
+            assert_eq!(decoded[0].text(), text);
+        }
+    }
+
+    #[test]
+    fn test_penalty() {
+        let mut matrix = ModuleMatrix::new(21, 21);
+        for y in 0..21 {
+            for x in 0..21 {
+                matrix.set(x, y, true);
+            }
+        }
+        assert_eq!(
+            penalty(&matrix),
+            Penalty {
+                runs: 42 * (3 + 16),
+                blocks: 20 * 20 * 3,
+                finder_like: 0,
+                balance: 100,
+            }
+        );
+
+        let symbol = encode(b"HELLO WORLD", EcLevel::Q).unwrap();
+        let best = penalty(&symbol.matrix).total();
+        for mask in 0..NUM_MASKS {
+            let options = EncodeOptions::new().ec_level(EcLevel::Q).mask(mask);
+            let other = encode_with(b"HELLO WORLD", &options).unwrap();
+            assert!(penalty(&other.matrix).total() >= best);
+        }
+
+        // 201 of 441 modules is 45.6% dark, less than a whole step off 50%
+        let mut matrix = ModuleMatrix::new(21, 21);
+        for i in 0..201 {
+            matrix.set(i % 21, i / 21, true);
+        }
+        assert_eq!(penalty(&matrix).balance, 0);
+    }
+
+    #[test]
+    fn test_encode_mixed_modes() {
+        let url = b"HTTPS://EXAMPLE.COM/12345678901234";
+        let symbol = encode(url, EcLevel::H).unwrap();
+        let message = symbol.decode().unwrap();
+        assert_eq!(message.text().as_bytes(), url);
+        assert_eq!(message.segments.len(), 2);
+
+        let options = EncodeOptions::new().ec_level(EcLevel::H).mode(Mode::Byte);
+        let forced = encode_with(url, &options).unwrap();
+        assert_eq!(forced.decode().unwrap().segments[0].mode, Mode::Byte);
+        assert!(forced.version > symbol.version);
+
+        let options = EncodeOptions::new().mode(Mode::Numeric);
+        assert!(matches!(
+            encode_with(b"123A", &options),
+            Err(EncodeError::InvalidData {
+                mode: Mode::Numeric,
+                index: 3
+            })
+        ));
+    }
+
+    #[test]
+    fn test_encode_too_long() {
+        let data = vec![b'a'; 1300];
+        assert!(matches!(
+            encode(&data, EcLevel::H),
+            Err(EncodeError::DataTooLong {
+                capacity: 10208,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,87 @@
+use thiserror::Error;
+
+use crate::{decode::Version, segment::Mode};
+
+pub type Result<T, E = DecodeError> = std::result::Result<T, E>;
+
+/// Why some data couldn't be encoded into a symbol
+#[derive(Debug, Error)]
+pub enum EncodeError {
+    #[error("Data needs {needed} bits, the largest symbol holds {capacity}")]
+    DataTooLong { needed: usize, capacity: usize },
+    #[error("No mask pattern {mask:#05b}")]
+    InvalidMask { mask: u8 },
+    #[error("Byte {index} of the data can't be encoded in {mode:?} mode")]
+    InvalidData { mode: Mode, index: usize },
+}
+
+/// Why modules couldn't be read from text or a PBM file
+#[derive(Debug, Error)]
+pub enum MatrixParseError {
+    #[error("No modules in the input")]
+    Empty,
+    /// Lines and columns count from 1
+    #[error("Unexpected {ch:?} at line {line}, column {column}")]
+    InvalidChar {
+        line: usize,
+        column: usize,
+        ch: char,
+    },
+    #[error("Not a PBM file, or its header is broken")]
+    InvalidPbmHeader,
+    #[error("PBM data ends after {found} of {expected} pixels")]
+    TruncatedPbm { expected: usize, found: usize },
+    /// Pixels count from 0, in reading order
+    #[error("Unexpected {ch:?} for PBM pixel {pixel}")]
+    InvalidPbmPixel { pixel: usize, ch: char },
+}
+
+/// Why a symbol couldn't be found or decoded, at whichever step that stopped
+#[derive(Debug, Error)]
+pub enum DecodeError {
+    #[error("Expected {expected} finders, found {found}")]
+    FinderNotFound { expected: usize, found: usize },
+    #[error("No {} timing pattern next to the finder", if *.horizontal { "horizontal" } else { "vertical" })]
+    TimingNotFound { horizontal: bool },
+    #[error("Timing module ({x}, {y}) is not as expected")]
+    TimingMismatch { x: usize, y: usize },
+    #[error("{width}x{height} modules is not a valid symbol size")]
+    InvalidSize { width: usize, height: usize },
+    /// Holds the raw bits of every copy of the format info
+    #[error("Format info is unreadable ({copies:#x?})")]
+    FormatUnreadable { copies: Vec<u32> },
+    #[error("Version info is unreadable ({copies:#x?})")]
+    VersionUnreadable { copies: Vec<u32> },
+    /// QR codes have their version in the version info, Micro QR and rMQR in the format info
+    #[error(
+        "{} says {from_info}, but the symbol size says {from_size}",
+        if matches!(.from_info, Version::Qr(_)) { "Version info" } else { "Format info" }
+    )]
+    VersionMismatch {
+        from_size: Version,
+        from_info: Version,
+    },
+    #[error("Version {version} is outside of the expected {min}..={max}")]
+    VersionOutOfRange { version: u8, min: u8, max: u8 },
+    #[error("No mask pattern {mask:#05b}")]
+    InvalidMask { mask: u8 },
+    /// A block needed `corrected` codewords fixed, more than its ec codewords can.
+    /// When the errors can't be located, that is at least one more than `capacity`.
+    #[error("Too many errors: {corrected} found, can fix {capacity}")]
+    TooManyErrors { corrected: usize, capacity: usize },
+    #[error("Unsupported mode indicator {indicator:#b}")]
+    UnsupportedMode { indicator: u32 },
+    #[error("Invalid {mode:?} value {value}")]
+    InvalidData { mode: Mode, value: u32 },
+    #[error("Needed {needed} bits, only {remaining} left")]
+    TruncatedData { needed: usize, remaining: usize },
+    /// Decoding failed on a grid that was placed with an inferred finder
+    #[error("{source}, the finder inferred at ({x:.1}, {y:.1}) is likely misplaced")]
+    InferredFinderMisplaced {
+        x: f32,
+        y: f32,
+        source: Box<DecodeError>,
+    },
+    #[error("Could not write the visualization: {0}")]
+    Visualization(#[from] std::io::Error),
+}
@@ -0,0 +1,361 @@
+use std::{
+    fmt::{Display, Write as _},
+    fs, io,
+    path::Path,
+};
+
+use crate::{
+    codewords::BlockLayout,
+    format::{self, FormatInfo, FORMAT_XOR_MASK},
+    qr::{self, Code, Symbol},
+    rs,
+    segment::{Bitstream, FieldKind, Message},
+    util::Rect,
+    version,
+};
+
+/// Data fields listed in full before the rest of a run is left out
+const MAX_DATA_FIELDS: usize = 8;
+
+/// Narrates each decoding step with the numbers read from one image, as Markdown.
+/// Steps are added in the order they ran, so a failed decode still reads up to
+/// where it stopped.
+pub struct Explanation {
+    title: String,
+    sections: Vec<(String, String)>,
+    error: Option<String>,
+}
+
+impl Explanation {
+    pub fn new(title: impl Into<String>) -> Self {
+        Self {
+            title: title.into(),
+            sections: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// A step that has no method of its own
+    pub fn section(&mut self, heading: impl Into<String>, body: impl Into<String>) {
+        self.sections.push((heading.into(), body.into()));
+    }
+
+    pub fn finders(&mut self, finders: &[Rect]) {
+        let mut body = String::from(
+            "A finder pattern is 7 modules wide. Scanning a line through its centre \
+             crosses dark, light, dark, light and dark runs in the ratio 1:1:3:1:1, \
+             which rarely happens by chance. Lines that match in both directions are \
+             merged into candidates:\n\n\
+             | Finder | Centre (px) | Size (px) | Module size (px) |\n\
+             | --- | --- | --- | --- |\n",
+        );
+        for (i, finder) in finders.iter().enumerate() {
+            writeln!(
+                body,
+                "| {} | ({:.1}, {:.1}) | {:.1} x {:.1} | {:.2} |",
+                i + 1,
+                finder.cx(),
+                finder.cy(),
+                finder.width(),
+                finder.height(),
+                finder.width() / 7.0,
+            )
+            .unwrap();
+        }
+        let kind = match finders.len() {
+            1 => "\nOne finder means a Micro QR or rMQR code.",
+            3 => "\nThree finders mark the corners of a QR code.",
+            _ => "",
+        };
+        body.push_str(kind);
+        self.section("Finder patterns", body);
+    }
+
+    /// How the modules were laid out between the finders
+    pub fn grid(&mut self, bounds: &Rect, width: usize, height: usize, inferred: Option<&Rect>) {
+        let mut body = format!(
+            "The symbol spans ({:.1}, {:.1}) to ({:.1}, {:.1}). Counting the modules \
+             of the timing patterns gives a grid of {width} x {height} modules, each \
+             {:.2} x {:.2} px.",
+            bounds.left,
+            bounds.top,
+            bounds.right,
+            bounds.bottom,
+            bounds.width() / width as f32,
+            bounds.height() / height as f32,
+        );
+        if let Some(finder) = inferred {
+            write!(
+                body,
+                " Only two finders were found, so the third was placed at ({:.1}, {:.1}) \
+                 to complete the square.",
+                finder.cx(),
+                finder.cy()
+            )
+            .unwrap();
+        }
+        self.section("Module grid", body);
+    }
+
+    /// Reads the timing patterns of a QR code and whether they alternate
+    pub fn timing(&mut self, code: &Code, img: &image::DynamicImage) {
+        let pattern = |modules: &mut dyn Iterator<Item = Rect>| -> String {
+            modules
+                .map(|module| {
+                    if code.is_white_module(img, &module) {
+                        '0'
+                    } else {
+                        '1'
+                    }
+                })
+                .collect()
+        };
+        let horiz = pattern(&mut code.horiz_timing_iter());
+        let vert = pattern(&mut code.vert_timing_iter());
+        let result = match code.check_timing(img) {
+            Ok(()) => "Both alternate as expected, so the grid is placed right.".to_string(),
+            Err(err) => format!("They don't: {err}."),
+        };
+        self.section(
+            "Timing patterns",
+            format!(
+                "Row 6 and column 6 between the finders, read from the light separator on, \
+                 alternate light (0) and dark (1):\n\n\
+                 - horizontal: `{horiz}`\n\
+                 - vertical: `{vert}`\n\n{result}"
+            ),
+        );
+    }
+
+    /// Both copies of the format info of a QR code, and the mask they select
+    pub fn format(&mut self, symbol: &Symbol) {
+        let size = symbol.matrix.width();
+        let mut body = format!(
+            "The grid is {size} x {size} modules, and a QR code of version v is \
+             17 + 4v modules wide, so this is version {}.",
+            symbol.version
+        );
+        if symbol.version >= version::MIN_VERSION_WITH_INFO {
+            body.push_str(" From version 7 on that is confirmed by the version info blocks.");
+        }
+        if symbol.mirrored {
+            body.push_str(" The symbol is mirrored, so rows and columns are swapped from here on.");
+        }
+        write!(
+            body,
+            "\n\nThe format info is 15 bits, stored twice. Each copy is xor-ed with \
+             `{FORMAT_XOR_MASK:015b}` so it is never all light, and holds 2 bits of error \
+             correction level and 3 of mask followed by 10 BCH bits, which correct up \
+             to 3 wrong bits:\n\n\
+             | Copy | Read | Unmasked | Level + mask | BCH | Nearest valid | Wrong bits |\n\
+             | --- | --- | --- | --- | --- | --- | --- |\n"
+        )
+        .unwrap();
+        let words = format::read_format_words(&symbol.matrix);
+        for (name, word) in ["Top left", "Top right + bottom left"].iter().zip(words) {
+            let unmasked = word ^ FORMAT_XOR_MASK;
+            let (nearest, distance) = match FormatInfo::decode(word) {
+                Some((format, distance)) => (
+                    format!("`{:015b}`", format.encode() ^ FORMAT_XOR_MASK),
+                    distance.to_string(),
+                ),
+                None => ("none".to_string(), "more than 3".to_string()),
+            };
+            writeln!(
+                body,
+                "| {name} | `{word:015b}` | `{unmasked:015b}` | `{:02b} {:03b}` | `{:010b}` | \
+                 {nearest} | {distance} |",
+                unmasked >> 13,
+                unmasked >> 10 & 0b111,
+                unmasked & 0x3FF,
+            )
+            .unwrap();
+        }
+        let mask = symbol.format.mask;
+        write!(
+            body,
+            "\nThe closer copy wins, with {} wrong bits: error correction level **{:?}** \
+             and mask **`{mask:03b}`**.",
+            symbol.format_errors, symbol.format.ec_level,
+        )
+        .unwrap();
+        if let Some(formula) = qr::mask_formula(mask) {
+            write!(
+                body,
+                "\n\nThe mask inverts every data module where {formula}, x being the \
+                 column and y the row. Inverting them again gives back the data bits.",
+            )
+            .unwrap();
+        }
+        self.section("Format info and mask", body);
+    }
+
+    /// The syndromes of each block of `codewords`, as read and before correction
+    pub fn syndromes(&mut self, layout: &BlockLayout, codewords: &[u8]) {
+        let mut body = format!(
+            "The codewords are split into {} block(s), each its data codewords followed by \
+             {} error correction codewords. Each block is a Reed-Solomon code word, and \
+             evaluating it at the first {} powers of the generator gives its syndromes. \
+             They are all zero when no codeword is wrong, otherwise they locate the wrong \
+             ones and their values:\n\n\
+             | Block | Data | Syndromes | Result |\n\
+             | --- | --- | --- | --- |\n",
+            layout.data_lens.len(),
+            layout.ec_len,
+            layout.ec_len,
+        );
+        for (i, (mut block, &data_len)) in layout
+            .deinterleave(codewords)
+            .into_iter()
+            .zip(&layout.data_lens)
+            .enumerate()
+        {
+            let syndromes: Vec<String> = rs::syndromes(&block, layout.ec_len)
+                .iter()
+                .map(|s| format!("{s:02x}"))
+                .collect();
+            let result = match rs::correct(&mut block, layout.ec_len) {
+                Ok(0) => "no errors".to_string(),
+                Ok(n) => format!("{n} codeword(s) corrected"),
+                Err(err) => err.to_string(),
+            };
+            writeln!(
+                body,
+                "| {} | {data_len} | `{}` | {result} |",
+                i + 1,
+                syndromes.join(" ")
+            )
+            .unwrap();
+        }
+        self.section("Error correction", body);
+    }
+
+    /// The segment headers of the corrected data, and what follows them
+    pub fn bitstream(&mut self, bitstream: &Bitstream) {
+        let mut body = String::from(
+            "The data is a sequence of segments, each a mode indicator, a character \
+             count and the characters in that mode. A terminator of zeroes ends it, and \
+             pad codewords fill the rest:\n\n\
+             | Bits | Field | Value | Meaning |\n\
+             | --- | --- | --- | --- |\n",
+        );
+        let fields = &bitstream.fields;
+        let mut i = 0;
+        while i < fields.len() {
+            let kind = fields[i].kind;
+            let run = fields[i..]
+                .iter()
+                .take_while(|field| field.kind == kind)
+                .count();
+            let grouped = matches!(kind, FieldKind::Data | FieldKind::PadCodeword);
+            let run = if grouped { run } else { 1 };
+            let (first, last) = (&fields[i], &fields[i + run - 1]);
+            let bits = format!("{}..{}", first.start, last.start + last.len);
+            if run == 1 {
+                writeln!(
+                    body,
+                    "| {bits} | {kind:?} | `{:0width$b}` | {} |",
+                    first.value,
+                    escape(&first.meaning),
+                    width = first.len,
+                )
+                .unwrap();
+            } else {
+                let mut meanings: Vec<String> = fields[i..i + run]
+                    .iter()
+                    .take(MAX_DATA_FIELDS)
+                    .map(|field| escape(&field.meaning))
+                    .collect();
+                if run > MAX_DATA_FIELDS {
+                    meanings.push(format!("and {} more", run - MAX_DATA_FIELDS));
+                }
+                writeln!(
+                    body,
+                    "| {bits} | {run} x {kind:?} | | {} |",
+                    meanings.join(", ")
+                )
+                .unwrap();
+            }
+            i += run;
+        }
+        if let Some(err) = &bitstream.error {
+            write!(
+                body,
+                "\nParsing stopped at bit {}: {err}.",
+                last_bit(bitstream)
+            )
+            .unwrap();
+        }
+        self.section("Segments", body);
+    }
+
+    pub fn message(&mut self, message: &Message) {
+        self.section(
+            "Result",
+            format!(
+                "{} codeword(s) were fixed by error correction, and the text is `{}`.",
+                message.corrected,
+                message.text()
+            ),
+        );
+    }
+
+    /// Where decoding stopped
+    pub fn error(&mut self, error: impl Display) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!("# Decoding {}\n", self.title);
+        for (i, (heading, body)) in self.sections.iter().enumerate() {
+            write!(out, "\n## {}. {heading}\n\n{}\n", i + 1, body.trim_end()).unwrap();
+        }
+        if let Some(error) = &self.error {
+            write!(out, "\n## Decoding stopped\n\n{error}\n").unwrap();
+        }
+        out
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_markdown())
+    }
+}
+
+fn last_bit(bitstream: &Bitstream) -> usize {
+    bitstream
+        .fields
+        .last()
+        .map_or(0, |field| field.start + field.len)
+}
+
+/// Keeps a table cell from being split or formatted
+fn escape(text: &str) -> String {
+    text.replace('|', "\\|").replace('`', "\\`")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::EcLevel;
+
+    #[test]
+    fn test_to_markdown() {
+        let symbol = crate::encode(b"HELLO WORLD", EcLevel::Q).unwrap();
+        let mut explanation = Explanation::new("hello.png");
+        explanation.format(&symbol);
+        let codewords: Vec<u8> = symbol.data_iter().unwrap().collect();
+        explanation.syndromes(&version::block_layout(1, EcLevel::Q), &codewords);
+        explanation.bitstream(&symbol.bitstream().unwrap());
+        explanation.error("Expected 3 finders, found 2");
+
+        let markdown = explanation.to_markdown();
+        assert!(markdown.starts_with("# Decoding hello.png\n"));
+        assert!(markdown.contains("## 1. Format info and mask"));
+        assert!(markdown.contains("error correction level **Q**"));
+        assert!(
+            markdown.contains("| 1 | 13 | `00 00 00 00 00 00 00 00 00 00 00 00 00` | no errors |")
+        );
+        assert!(markdown.contains(r#"| 4..13 | Count | `000001011` | 11 characters |"#));
+        assert!(markdown.contains("## Decoding stopped\n\nExpected 3 finders, found 2"));
+    }
+}
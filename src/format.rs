@@ -0,0 +1,252 @@
+use crate::{
+    decode::Version,
+    error::{DecodeError, Result},
+    matrix::ModuleMatrix,
+    version,
+};
+
+const FORMAT_GENERATOR: u32 = 0b101_0011_0111;
+/// Keeps the format word from being all zeroes
+pub const FORMAT_XOR_MASK: u32 = 0b101_0100_0001_0010;
+const VERSION_GENERATOR: u32 = 0b1_1111_0010_0101;
+const FORMAT_NUM_BITS: usize = 15;
+const VERSION_NUM_BITS: usize = 18;
+/// The BCH codes of the format and version info of every symbol type have a minimum
+/// distance of at least 7, so up to 3 bit errors can be corrected.
+pub const MAX_BCH_ERRORS: u32 = 3;
+
+/// Remainder of the polynomial division of `value` by `generator` over GF(2)
+pub fn bch_remainder(value: u32, generator: u32) -> u32 {
+    let generator_degree = 31 - generator.leading_zeros();
+    let mut value = value;
+    while value != 0 {
+        let degree = 31 - value.leading_zeros();
+        if degree < generator_degree {
+            break;
+        }
+        value ^= generator << (degree - generator_degree);
+    }
+    value
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EcLevel {
+    L,
+    M,
+    Q,
+    H,
+}
+
+impl EcLevel {
+    fn from_bits(bits: u8) -> Self {
+        match bits & 0b11 {
+            0b01 => EcLevel::L,
+            0b00 => EcLevel::M,
+            0b11 => EcLevel::Q,
+            _ => EcLevel::H,
+        }
+    }
+
+    fn to_bits(self) -> u8 {
+        match self {
+            EcLevel::L => 0b01,
+            EcLevel::M => 0b00,
+            EcLevel::Q => 0b11,
+            EcLevel::H => 0b10,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FormatInfo {
+    pub ec_level: EcLevel,
+    pub mask: u8,
+}
+
+impl FormatInfo {
+    /// The 15 bit format word, as it is placed in the symbol (already xor-ed with the mask)
+    pub fn encode(&self) -> u32 {
+        let data = ((self.ec_level.to_bits() as u32) << 3 | self.mask as u32) << 10;
+        (data | bch_remainder(data, FORMAT_GENERATOR)) ^ FORMAT_XOR_MASK
+    }
+
+    /// Finds the nearest valid format word, returns it with its hamming distance to `bits`
+    pub fn decode(bits: u32) -> Option<(Self, u32)> {
+        (0..32u8)
+            .map(|data| FormatInfo {
+                ec_level: EcLevel::from_bits(data >> 3),
+                mask: data & 0b111,
+            })
+            .map(|format| (format, (format.encode() ^ bits).count_ones()))
+            .min_by_key(|(_, distance)| *distance)
+            .filter(|(_, distance)| *distance <= MAX_BCH_ERRORS)
+    }
+}
+
+/// Positions of the first copy of the format info (around the top left finder), bit 0 first
+fn format_positions_tl() -> impl Iterator<Item = (usize, usize)> {
+    (0..FORMAT_NUM_BITS).map(|i| match i {
+        0..=5 => (8, i),
+        6 => (8, 7),
+        7 => (8, 8),
+        8 => (7, 8),
+        _ => (14 - i, 8),
+    })
+}
+
+/// Positions of the second copy of the format info (split between the top right
+/// and bottom left finders), bit 0 first
+fn format_positions_split(width: usize, height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..FORMAT_NUM_BITS).map(move |i| match i {
+        0..=7 => (width - 1 - i, 8),
+        _ => (8, height - FORMAT_NUM_BITS + i),
+    })
+}
+
+fn read_word(matrix: &ModuleMatrix, positions: impl Iterator<Item = (usize, usize)>) -> u32 {
+    positions
+        .enumerate()
+        .filter(|(_, (x, y))| matrix.get(*x, *y))
+        .fold(0, |word, (i, _)| word | 1 << i)
+}
+
+/// Both copies of the format word as read, around the top left finder and then
+/// split between the other two
+pub fn read_format_words(matrix: &ModuleMatrix) -> [u32; 2] {
+    [
+        read_word(matrix, format_positions_tl()),
+        read_word(
+            matrix,
+            format_positions_split(matrix.width(), matrix.height()),
+        ),
+    ]
+}
+
+/// Reads both copies of the format info and returns the one closest to a valid word,
+/// along with the number of bits that had to be corrected
+pub fn read_format_info(matrix: &ModuleMatrix) -> Result<(FormatInfo, u32)> {
+    let [tl, split] = read_format_words(matrix);
+    [tl, split]
+        .into_iter()
+        .filter_map(FormatInfo::decode)
+        .min_by_key(|(_, distance)| *distance)
+        .ok_or(DecodeError::FormatUnreadable {
+            copies: vec![tl, split],
+        })
+}
+
+fn write_word(
+    matrix: &mut ModuleMatrix,
+    positions: impl Iterator<Item = (usize, usize)>,
+    word: u32,
+) {
+    for (i, (x, y)) in positions.enumerate() {
+        matrix.set(x, y, word >> i & 1 == 1);
+    }
+}
+
+/// Places both copies of the format info
+pub fn write_format_info(matrix: &mut ModuleMatrix, format: &FormatInfo) {
+    let word = format.encode();
+    let (width, height) = (matrix.width(), matrix.height());
+    write_word(matrix, format_positions_tl(), word);
+    write_word(matrix, format_positions_split(width, height), word);
+}
+
+pub fn encode_version_info(version: u8) -> u32 {
+    let data = (version as u32) << 12;
+    data | bch_remainder(data, VERSION_GENERATOR)
+}
+
+/// Finds the nearest valid version word, returns it with its hamming distance to `bits`
+pub fn decode_version_info(bits: u32) -> Option<(u8, u32)> {
+    (version::MIN_VERSION_WITH_INFO..=version::MAX_VERSION)
+        .map(|version| (version, (encode_version_info(version) ^ bits).count_ones()))
+        .min_by_key(|(_, distance)| *distance)
+        .filter(|(_, distance)| *distance <= MAX_BCH_ERRORS)
+}
+
+/// Positions of the version info block above the bottom left finder, bit 0 first.
+/// The block left of the top right finder is its transpose.
+fn version_positions_bl(height: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..VERSION_NUM_BITS).map(move |i| (i / 3, height - 11 + i % 3))
+}
+
+fn version_positions_tr(width: usize) -> impl Iterator<Item = (usize, usize)> {
+    (0..VERSION_NUM_BITS).map(move |i| (width - 11 + i % 3, i / 3))
+}
+
+/// Places both version info blocks, only versions 7 and up have them
+pub fn write_version_info(matrix: &mut ModuleMatrix, version: u8) {
+    if version < version::MIN_VERSION_WITH_INFO {
+        return;
+    }
+    let word = encode_version_info(version);
+    let (width, height) = (matrix.width(), matrix.height());
+    write_word(matrix, version_positions_bl(height), word);
+    write_word(matrix, version_positions_tr(width), word);
+}
+
+/// Derives the version from the symbol size, and checks it against the
+/// version info blocks for versions 7 and up
+pub fn read_version(matrix: &ModuleMatrix) -> Result<u8> {
+    let invalid_size = DecodeError::InvalidSize {
+        width: matrix.width(),
+        height: matrix.height(),
+    };
+    if matrix.width() != matrix.height() {
+        return Err(invalid_size);
+    }
+    let size_version = version::size_to_version(matrix.width()).ok_or(invalid_size)?;
+    if size_version < version::MIN_VERSION_WITH_INFO {
+        return Ok(size_version);
+    }
+
+    let bl = read_word(matrix, version_positions_bl(matrix.height()));
+    let tr = read_word(matrix, version_positions_tr(matrix.width()));
+    let (version, _) = [bl, tr]
+        .into_iter()
+        .filter_map(decode_version_info)
+        .min_by_key(|(_, distance)| *distance)
+        .ok_or(DecodeError::VersionUnreadable {
+            copies: vec![bl, tr],
+        })?;
+
+    if version != size_version {
+        return Err(DecodeError::VersionMismatch {
+            from_size: Version::Qr(size_version),
+            from_info: Version::Qr(version),
+        });
+    }
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_info_encode() {
+        let format = FormatInfo {
+            ec_level: EcLevel::M,
+            mask: 0b101,
+        };
+        assert_eq!(format.encode(), 0b100_0000_1100_1110);
+    }
+
+    #[test]
+    fn test_format_info_decode_with_errors() {
+        let format = FormatInfo {
+            ec_level: EcLevel::Q,
+            mask: 0b011,
+        };
+        let corrupted = format.encode() ^ 0b100_0001_0000_0001;
+        assert_eq!(FormatInfo::decode(corrupted), Some((format, 3)));
+    }
+
+    #[test]
+    fn test_version_info_encode() {
+        assert_eq!(encode_version_info(7), 0b00_0111_1100_1001_0100);
+        assert_eq!(decode_version_info(0b00_0111_1100_1001_0111), Some((7, 2)));
+    }
+}
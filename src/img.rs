@@ -1,14 +1,16 @@
-use anyhow::anyhow;
-use anyhow::Result;
 use image::{GenericImageView, Pixel};
 
 use crate::util::Rect;
 
-pub fn is_white<T: Pixel<Subpixel = u8>>(pixel: &T) -> bool {
-    pixel.to_luma().0[0] > 128
+/// Whether `pixel` is lighter than `threshold`, see [`DetectorParams::white_threshold`]
+///
+/// [`DetectorParams::white_threshold`]: crate::qr::DetectorParams::white_threshold
+pub fn is_white<T: Pixel<Subpixel = u8>>(pixel: &T, threshold: u8) -> bool {
+    pixel.to_luma().0[0] > threshold
 }
 
-pub fn is_white_module(img: &image::DynamicImage, module: &Rect) -> bool {
+/// Share of the pixels fully inside `module` that are lighter than `threshold`
+pub fn white_ratio(img: &image::DynamicImage, module: &Rect, threshold: u8) -> f32 {
     let (left, top, right, bottom) = module.to_corners();
 
     let start_y = top.ceil() as u32;
@@ -19,28 +21,168 @@ pub fn is_white_module(img: &image::DynamicImage, module: &Rect) -> bool {
     let mut val = 0;
     for y in start_y..end_y {
         for x in start_x..end_x {
-            if is_white(&img.get_pixel(x, y)) {
+            if is_white(&img.get_pixel(x, y), threshold) {
                 val += 1;
             }
         }
     }
 
-    val as f32 / (module.width() * module.height()) > 0.5
+    val as f32 / (module.width() * module.height())
+}
+
+/// How a grayscale image is split into black and white before the patterns are
+/// looked for. Everything after that reads pixels with [`is_white`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Binarizer {
+    /// Pixels lighter than this are white
+    Threshold(u8),
+    /// The threshold that best splits the histogram in two (Otsu's method)
+    Otsu,
+    /// Compares each pixel to the mean of the square `radius` pixels around it, so
+    /// uneven lighting doesn't swallow parts of the symbol. Squares whose standard
+    /// deviation is below `min_contrast` are flat, and use the Otsu threshold instead.
+    Adaptive { radius: u32, min_contrast: u8 },
+}
+
+impl Default for Binarizer {
+    fn default() -> Self {
+        Self::Threshold(128)
+    }
+}
+
+impl Binarizer {
+    pub fn binarize(&self, img: &image::DynamicImage) -> image::DynamicImage {
+        let mut luma = img.to_luma8();
+        match *self {
+            Self::Threshold(threshold) => apply_global_threshold(&mut luma, threshold),
+            Self::Otsu => {
+                let threshold = otsu_threshold(&luma);
+                apply_global_threshold(&mut luma, threshold);
+            }
+            Self::Adaptive {
+                radius,
+                min_contrast,
+            } => {
+                let fallback = otsu_threshold(&luma);
+                let sums = IntegralImage::new(&luma);
+                let (width, height) = luma.dimensions();
+                apply_threshold(&mut luma, |x, y| {
+                    let left = x.saturating_sub(radius);
+                    let top = y.saturating_sub(radius);
+                    let right = (x + radius + 1).min(width);
+                    let bottom = (y + radius + 1).min(height);
+                    let (mean, std_dev) = sums.mean_and_std_dev(left, top, right, bottom);
+                    if std_dev < min_contrast as f64 {
+                        fallback
+                    } else {
+                        mean as u8
+                    }
+                });
+            }
+        }
+        image::DynamicImage::ImageLuma8(luma)
+    }
+}
+
+fn apply_global_threshold(luma: &mut image::GrayImage, threshold: u8) {
+    for value in luma.iter_mut() {
+        *value = if *value > threshold { 255 } else { 0 };
+    }
+}
+
+fn apply_threshold(luma: &mut image::GrayImage, threshold_at: impl Fn(u32, u32) -> u8) {
+    for (x, y, pixel) in luma.enumerate_pixels_mut() {
+        pixel.0[0] = if pixel.0[0] > threshold_at(x, y) {
+            255
+        } else {
+            0
+        };
+    }
+}
+
+/// The threshold that maximizes the variance between the pixels on either side of it
+fn otsu_threshold(luma: &image::GrayImage) -> u8 {
+    let mut histogram = [0u64; 256];
+    for pixel in luma.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+    let total = luma.pixels().len() as f64;
+    let total_sum: f64 = (0..256).map(|i| i as f64 * histogram[i] as f64).sum();
+
+    let mut best = (0.0, 128);
+    let mut below = 0.0;
+    let mut below_sum = 0.0;
+    for (value, &count) in histogram.iter().enumerate() {
+        below += count as f64;
+        below_sum += value as f64 * count as f64;
+        let above = total - below;
+        if below == 0.0 || above == 0.0 {
+            continue;
+        }
+        let mean_diff = below_sum / below - (total_sum - below_sum) / above;
+        let variance = below * above * mean_diff * mean_diff;
+        if variance > best.0 {
+            best = (variance, value as u8);
+        }
+    }
+    best.1
+}
+
+/// Sums of the pixels, and of their squares, above and left of each pixel
+struct IntegralImage {
+    width: usize,
+    sums: Vec<u64>,
+    squares: Vec<u64>,
+}
+
+impl IntegralImage {
+    fn new(luma: &image::GrayImage) -> Self {
+        let width = luma.width() as usize + 1;
+        let height = luma.height() as usize + 1;
+        let mut sums = vec![0; width * height];
+        let mut squares = vec![0; width * height];
+        for (x, y, pixel) in luma.enumerate_pixels() {
+            let (x, y) = (x as usize + 1, y as usize + 1);
+            let value = pixel.0[0] as u64;
+            let idx = y * width + x;
+            sums[idx] = value + sums[idx - 1] + sums[idx - width] - sums[idx - width - 1];
+            squares[idx] =
+                value * value + squares[idx - 1] + squares[idx - width] - squares[idx - width - 1];
+        }
+        Self {
+            width,
+            sums,
+            squares,
+        }
+    }
+
+    fn mean_and_std_dev(&self, left: u32, top: u32, right: u32, bottom: u32) -> (f64, f64) {
+        let area_sum = |values: &[u64]| {
+            let at = |x: u32, y: u32| values[y as usize * self.width + x as usize];
+            (at(right, bottom) + at(left, top) - at(left, bottom) - at(right, top)) as f64
+        };
+        let area = ((right - left) * (bottom - top)) as f64;
+        let mean = area_sum(&self.sums) / area;
+        let variance = area_sum(&self.squares) / area - mean * mean;
+        (mean, variance.max(0.0).sqrt())
+    }
 }
 
 pub struct HorizIterator<'a> {
     img: &'a image::DynamicImage,
     x: u32,
     y: u32,
+    threshold: u8,
 }
 
 impl<'a> HorizIterator<'a> {
-    pub fn new(img: &'a image::DynamicImage, y: u32) -> Result<Self> {
-        if y >= img.height() {
-            Err(anyhow!("invalid y for the image"))
-        } else {
-            Ok(Self { img, x: 0, y })
-        }
+    pub fn new(img: &'a image::DynamicImage, y: u32, threshold: u8) -> Option<Self> {
+        (y < img.height()).then_some(Self {
+            img,
+            x: 0,
+            y,
+            threshold,
+        })
     }
 }
 
@@ -48,7 +190,7 @@ impl<'a> Iterator for HorizIterator<'a> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = is_white(&self.img.get_pixel(self.x, self.y));
+        let result = is_white(&self.img.get_pixel(self.x, self.y), self.threshold);
         self.x += 1;
         if self.x >= self.img.width() {
             None
@@ -59,19 +201,17 @@ impl<'a> Iterator for HorizIterator<'a> {
 }
 
 pub trait ToHoriz {
-    fn to_horiz(&self, y: u32) -> HorizIterator;
+    fn to_horiz(&self, y: u32, threshold: u8) -> HorizIterator<'_>;
 }
 
 impl ToHoriz for image::DynamicImage {
-    fn to_horiz(&self, y: u32) -> HorizIterator {
-        match HorizIterator::new(self, y) {
-            Ok(iter) => iter,
-            Err(_) => HorizIterator {
-                img: self,
-                x: self.width() - 1,
-                y,
-            },
-        }
+    fn to_horiz(&self, y: u32, threshold: u8) -> HorizIterator<'_> {
+        HorizIterator::new(self, y, threshold).unwrap_or(HorizIterator {
+            img: self,
+            x: self.width() - 1,
+            y,
+            threshold,
+        })
     }
 }
 
@@ -79,15 +219,17 @@ pub struct VertIterator<'a> {
     img: &'a image::DynamicImage,
     x: u32,
     y: u32,
+    threshold: u8,
 }
 
 impl<'a> VertIterator<'a> {
-    pub fn new(img: &'a image::DynamicImage, x: u32) -> Result<Self> {
-        if x >= img.width() {
-            Err(anyhow!("invalid y for the image"))
-        } else {
-            Ok(Self { img, x, y: 0 })
-        }
+    pub fn new(img: &'a image::DynamicImage, x: u32, threshold: u8) -> Option<Self> {
+        (x < img.width()).then_some(Self {
+            img,
+            x,
+            y: 0,
+            threshold,
+        })
     }
 }
 
@@ -95,7 +237,7 @@ impl<'a> Iterator for VertIterator<'a> {
     type Item = bool;
 
     fn next(&mut self) -> Option<Self::Item> {
-        let result = is_white(&self.img.get_pixel(self.x, self.y));
+        let result = is_white(&self.img.get_pixel(self.x, self.y), self.threshold);
         self.y += 1;
         if self.y >= self.img.height() {
             None
@@ -106,18 +248,16 @@ impl<'a> Iterator for VertIterator<'a> {
 }
 
 pub trait ToVert {
-    fn to_vert(&self, x: u32) -> VertIterator;
+    fn to_vert(&self, x: u32, threshold: u8) -> VertIterator<'_>;
 }
 
 impl ToVert for image::DynamicImage {
-    fn to_vert(&self, x: u32) -> VertIterator {
-        match VertIterator::new(self, x) {
-            Ok(iter) => iter,
-            Err(_) => VertIterator {
-                img: self,
-                x,
-                y: self.height() - 1,
-            },
-        }
+    fn to_vert(&self, x: u32, threshold: u8) -> VertIterator<'_> {
+        VertIterator::new(self, x, threshold).unwrap_or(VertIterator {
+            img: self,
+            x,
+            y: self.height() - 1,
+            threshold,
+        })
     }
 }
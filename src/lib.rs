@@ -0,0 +1,33 @@
+//! Finds and decodes QR, Micro QR and rMQR codes in images. [`decode`] does it all
+//! in one go, the modules below expose each step for inspecting and visualizing it.
+//! [`encode`] goes the other way, from data to the modules of a QR code, and
+//! [`render`] draws those as PNG, SVG or text. [`explain`] narrates a decode step
+//! by step as Markdown.
+
+pub mod animate;
+pub mod codewords;
+mod decode;
+pub mod encode;
+pub mod error;
+pub mod explain;
+pub mod format;
+pub mod img;
+pub mod matrix;
+pub mod micro;
+pub mod qr;
+pub mod render;
+pub mod report;
+pub mod rmqr;
+pub mod rs;
+pub mod segment;
+pub mod util;
+pub mod version;
+pub mod viz;
+
+pub use decode::{
+    decode, decode_matrix, decode_with, DecodeObserver, DecodeOptions, DecodedSymbol, Effort, Grid,
+    ReadSymbol, SymbolKind, Version,
+};
+pub use encode::{encode, encode_with, penalty, EncodeOptions, Penalty};
+pub use error::{DecodeError, EncodeError, MatrixParseError, Result};
+pub use img::Binarizer;
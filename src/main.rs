@@ -1,15 +1,107 @@
-mod img;
-mod qr;
-mod util;
-mod viz;
-use anyhow::{anyhow, Result};
-use clap::Parser;
+use anyhow::Result;
+use clap::{Parser, ValueEnum};
 use image::{GenericImageView, ImageReader};
-use qr::{DataBitIter, HorizFormatIter, HorizTimingIter, Output, VertFormatIter, VertTimingIter};
-use std::{fs, path::PathBuf};
-use util::Rect;
+use qrious::{
+    animate,
+    codewords::{BlockLayout, ModuleBit},
+    explain::Explanation,
+    img,
+    matrix::ModuleMatrix,
+    micro::MicroSymbol,
+    qr::{
+        self, AlignmentPatternIter, DetectorParams, HorizFormatIter, HorizTimingIter, Output,
+        VertFormatIter, VertTimingIter,
+    },
+    report::{Layer, Report},
+    rmqr::{self, RmqrSymbol},
+    segment::{Bitstream, Message},
+    util::Rect,
+    viz::{Background, SvgVisualizer, Visualizer},
+    DecodeObserver, DecodeOptions, Grid, ReadSymbol,
+};
+use std::{
+    fmt::Write as _,
+    fs::{self, File},
+    io::BufWriter,
+    path::{Path, PathBuf},
+    time::Instant,
+};
 
-use crate::{qr::AlignmentPatternIter, viz::Visualizer};
+/// Seconds between placing each module in the placement animation
+const PLACEMENT_STEP: f32 = 0.02;
+
+/// Where each data bit of a symbol ends up, for the tooltips of the data modules
+struct BitOrigins {
+    map: Vec<ModuleBit>,
+    /// The codewords as read, data then ec for Micro QR and interleaved otherwise
+    raw: Vec<u8>,
+    /// The same codewords after error correction, if it succeeded
+    corrected: Option<Vec<u8>>,
+    /// Share of white pixels above which a module reads as white
+    threshold: f32,
+}
+
+impl BitOrigins {
+    fn new(map: Vec<ModuleBit>, layout: &BlockLayout, raw: Vec<u8>, threshold: f32) -> Self {
+        Self {
+            map,
+            corrected: layout.correct_codewords(&raw).ok(),
+            raw,
+            threshold,
+        }
+    }
+
+    fn title(
+        &self,
+        i: usize,
+        (x, y): (usize, usize),
+        white_ratio: f32,
+        confidence: f32,
+        raw_bit: bool,
+        bit: bool,
+    ) -> String {
+        let mut title = format!(
+            "module ({x}, {y})\ndarkness {:.2}, threshold {:.2}, confidence {:.2}\nmask {}, raw bit {}, unmasked bit {}",
+            1.0 - white_ratio,
+            1.0 - self.threshold,
+            confidence,
+            (raw_bit != bit) as u8,
+            raw_bit as u8,
+            bit as u8,
+        );
+        let Some(origin) = self.map.get(i).and_then(|module| module.codeword) else {
+            title.push_str("\nremainder bit");
+            return title;
+        };
+        let kind = if origin.ec { "ec" } else { "data" };
+        title.push_str(&format!(
+            "\ncodeword {}, bit {}\nblock {}, {kind} {}",
+            origin.codeword,
+            origin.bit,
+            origin.block + 1,
+            origin.index,
+        ));
+        let codeword = origin.codeword;
+        match &self.corrected {
+            Some(corrected) => {
+                let flipped = (corrected[codeword] ^ self.raw[codeword]) >> (7 - origin.bit) & 1;
+                title.push_str(if flipped == 1 {
+                    "\ncorrected by RS"
+                } else {
+                    "\nnot changed by RS"
+                });
+            }
+            None => title.push_str("\nnot error corrected"),
+        }
+        title
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum MapFormat {
+    Csv,
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -19,83 +111,503 @@ struct Cli {
 
     /// The output directory
     output: PathBuf,
+
+    /// Copy the input next to the SVGs and link it, instead of embedding it in them
+    #[arg(long)]
+    copy_input: bool,
+
+    /// Also write which block, codeword and bit each data module holds to codeword-map.csv or .json
+    #[arg(long, value_name = "FORMAT")]
+    codeword_map: Option<MapFormat>,
+
+    /// Print every field of the data codewords with its bit range and meaning
+    #[arg(long)]
+    bitstream: bool,
+
+    /// Also write explain.md, narrating each step with the numbers read from the image
+    #[arg(long)]
+    explain: bool,
+
+    /// Read the input as modules drawn as text (`#` and `.`, or blocks and spaces)
+    /// or as a PBM file with a pixel per module, and decode those directly
+    #[arg(long)]
+    matrix: bool,
 }
 
 fn main() -> Result<()> {
     let cli = Cli::parse();
+    if cli.matrix {
+        return decode_matrix_file(&cli);
+    }
 
-    let file_name = init_output_dir(&cli)?;
+    let background = init_output_dir(&cli)?;
 
-    let img = ImageReader::open(&cli.output.join(&file_name))?;
+    let start = Instant::now();
+    let img = ImageReader::open(&cli.input)?;
     let img = img.decode()?.grayscale();
     let (width, height) = img.dimensions();
 
-    let mut dbg_vis = Visualizer::new(
-        width,
-        height,
-        &cli.output.join("debug.svg"),
-        Some(file_name),
-    )?;
-    let mut decoded_vis = Visualizer::new(width, height, &cli.output.join("decoded.svg"), None)?;
-
-    let code = qr::Code::new(&img, Some(&mut dbg_vis))?;
-    code.bounds.draw(&mut dbg_vis, "gray", None)?;
-    code.bounds.draw(&mut decoded_vis, "gray", None)?;
-    inspect_timing(code.horiz_timing_iter(), &img)?;
-    inspect_timing(code.vert_timing_iter(), &img)?;
-
-    let alignment_iter = AlignmentPatternIter::for_code(&code, &img);
-    for pattern in alignment_iter {
-        pattern
-            .module
-            .draw(&mut dbg_vis, "blue", Some("rgba(0, 255, 0, 0.6)"))?;
-    }
-
-    viz_timing_iter(
-        code.horiz_timing_iter(),
-        code.vert_timing_iter(),
-        &mut dbg_vis,
-    )?;
-    viz_format_iter(
-        code.horiz_format_iter(),
-        code.vert_format_iter(),
-        &mut dbg_vis,
-    )?;
-    viz_bits(code.bit_iter(&img)?, &mut decoded_vis, &mut dbg_vis)?;
-
-    let iter = code.data_iter(&img)?;
-    let encoding = iter.encoding;
-    println!("encoding = {encoding:#05b}");
-    let data: Vec<_> = iter.collect();
-    println!("data = {:?}", data);
-    if encoding == 0b0010 || encoding == 0b0100 {
-        let ascii: String = data.iter().map(|&b| b as char).collect();
-        println!("ascii = {:?}", ascii);
+    let mut report = Report::new(cli.input.display().to_string(), width, height);
+    report.background(background.clone());
+    report.timing("load", start.elapsed());
+
+    let mut explanation = Explanation::new(cli.input.display().to_string());
+    let result = run(&cli, &img, background, &mut report, &mut explanation);
+    if let Err(err) = &result {
+        report.error(err);
+        explanation.error(err);
+    }
+    report.write(&cli.output.join("report.html"))?;
+    if cli.explain {
+        explanation.write(&cli.output.join("explain.md"))?;
+    }
+    result
+}
+
+/// Decodes a matrix drawn as text or PBM, and writes the report for it
+fn decode_matrix_file(cli: &Cli) -> Result<()> {
+    fs::create_dir_all(&cli.output)?;
+    let data = fs::read(&cli.input)?;
+    let matrix = if data.starts_with(b"P1") || data.starts_with(b"P4") {
+        ModuleMatrix::from_pbm(&data)?
     } else {
-        return Err(anyhow!("Unsupported encoding {encoding:#05b}"));
+        ModuleMatrix::from_text(&String::from_utf8(data)?)?
     }
+    .without_quiet_zone();
 
-    return Ok(());
+    let (width, height) = (matrix.width(), matrix.height());
+    println!("modules = {width}x{height}");
+    let mut report = Report::new(cli.input.display().to_string(), width as u32, height as u32);
+    report.matrix(&matrix);
+    let result = report.time("decode", || {
+        qrious::decode_matrix(matrix, &DecodeOptions::default())
+    });
+    match &result {
+        Ok(symbol) => {
+            println!("kind = {:?}, ec_level = {:?}", symbol.kind, symbol.ec_level);
+            report.info("kind", format!("{:?}", symbol.kind));
+            report.info(
+                "ec level",
+                symbol
+                    .ec_level
+                    .map_or("detection only".into(), |ec| format!("{ec:?}")),
+            );
+            report.message(&symbol.message);
+            print_message(&symbol.message);
+        }
+        Err(err) => report.error(err),
+    }
+    report.write(&cli.output.join("report.html"))?;
+    result?;
+    Ok(())
 }
 
-fn viz_bits(
-    iter: DataBitIter,
-    decoded_vis: &mut Visualizer,
-    dbg_vis: &mut Visualizer,
+fn run(
+    cli: &Cli,
+    img: &image::DynamicImage,
+    background: Background,
+    report: &mut Report,
+    explanation: &mut Explanation,
 ) -> Result<()> {
+    let (width, height) = img.dimensions();
+    let options = DecodeOptions::default();
+    let mut observer = CliObserver {
+        cli,
+        params: options.detector_params(),
+        dbg_vis: SvgVisualizer::create(
+            width,
+            height,
+            &cli.output.join("debug.svg"),
+            Some(background),
+        )?,
+        decoded_vis: SvgVisualizer::create(width, height, &cli.output.join("decoded.svg"), None)?,
+        report,
+        explanation,
+        placement: None,
+        codeword_map: None,
+        step_start: Instant::now(),
+    };
+    observer
+        .dbg_vis
+        .begin_layer("Candidates", "blue", Some("opacity: 0.5"))?;
+    let result = qrious::decode_with(img, &options, &mut observer);
+    observer.step("decode");
+    if let Some(placement) = &observer.placement {
+        fs::write(cli.output.join("placement.svg"), placement)?;
+    }
+    if let (Some(map), Some(format)) = (&observer.codeword_map, cli.codeword_map) {
+        write_codeword_map(&cli.output, map, format)?;
+    }
+    for symbol in result? {
+        observer.report.message(&symbol.message);
+        observer.explanation.message(&symbol.message);
+        print_message(&symbol.message);
+    }
+    Ok(())
+}
+
+/// Draws, reports and explains each step of decoding as the library gets to it
+struct CliObserver<'a> {
+    cli: &'a Cli,
+    params: &'a DetectorParams,
+    dbg_vis: SvgVisualizer<BufWriter<File>>,
+    decoded_vis: SvgVisualizer<BufWriter<File>>,
+    report: &'a mut Report,
+    explanation: &'a mut Explanation,
+    /// The placement animation, written once decoding is done
+    placement: Option<String>,
+    /// The data modules in the sampled grid, for `--codeword-map`
+    codeword_map: Option<Vec<ModuleBit>>,
+    /// When the step being timed started
+    step_start: Instant,
+}
+
+impl CliObserver<'_> {
+    /// Adds the time since the last step as `stage`
+    fn step(&mut self, stage: &str) {
+        self.report.timing(stage, self.step_start.elapsed());
+        self.step_start = Instant::now();
+    }
+
+    /// The alignment, timing and format modules of a QR code
+    fn qr_grid(&mut self, img: &image::DynamicImage, code: &qr::Code) -> qrious::Result<()> {
+        self.explanation.grid(
+            &code.bounds,
+            code.num_horiz_elems(),
+            code.num_vert_elems(),
+            code.inferred_finder.as_ref(),
+        );
+        self.explanation.timing(code, img);
+
+        self.dbg_vis.begin_layer("Alignment", "blue", None)?;
+        for pattern in AlignmentPatternIter::for_code(code, img) {
+            pattern
+                .module
+                .draw(&mut self.dbg_vis, "blue", Some("rgba(0, 255, 0, 0.6)"))?;
+            self.report.rect(Layer::Alignment, &pattern.module);
+        }
+        self.dbg_vis.end_layer()?;
+
+        viz_timing_iter(
+            code.horiz_timing_iter(),
+            code.vert_timing_iter(),
+            &mut self.dbg_vis,
+            self.report,
+        )?;
+        viz_format_iter(
+            code.horiz_format_iter(),
+            code.vert_format_iter(),
+            &mut self.dbg_vis,
+            self.report,
+        )
+    }
+
+    fn qr_symbol(&mut self, symbol: &qr::Symbol) {
+        println!(
+            "version = {}, ec_level = {:?}, mask = {:#05b}, mirrored = {}, penalty = {}",
+            symbol.version,
+            symbol.format.ec_level,
+            symbol.format.mask,
+            symbol.mirrored,
+            symbol.penalty().total()
+        );
+        self.report.info("version", symbol.version);
+        self.report
+            .info("ec level", format!("{:?}", symbol.format.ec_level));
+        self.report
+            .info("mask", format!("{:#05b}", symbol.format.mask));
+        self.report.info("format errors", symbol.format_errors);
+        self.report.info("mirrored", symbol.mirrored);
+        self.report.info("penalty", symbol.penalty().total());
+        self.explanation.format(symbol);
+        self.placement = Some(animate::placement_svg(symbol, PLACEMENT_STEP));
+    }
+
+    fn micro_symbol(&mut self, grid: Grid, symbol: &MicroSymbol) {
+        println!(
+            "version = M{}, ec_level = {:?}, mask = {:#04b}, format_errors = {}",
+            symbol.format.version, symbol.format.ec_level, symbol.format.mask, symbol.format_errors
+        );
+        self.report
+            .info("version", format!("M{}", symbol.format.version));
+        let ec_level = symbol.format.ec_level;
+        self.report.info(
+            "ec level",
+            ec_level.map_or("detection only".into(), |ec| format!("{ec:?}")),
+        );
+        self.report
+            .info("mask", format!("{:#04b}", symbol.format.mask));
+        self.report.info("format errors", symbol.format_errors);
+        let size = symbol.format.size();
+        self.explanation.grid(grid.bounds(), size, size, None);
+        self.explanation.section(
+            "Format info and mask",
+            format!(
+                "The 15 bit format info next to the finder reads as symbol M{}, error \
+                 correction level {}, and Micro QR mask `{:02b}`, after correcting {} wrong \
+                 bit(s). The mask inverts every data module where {}.{}",
+                symbol.format.version,
+                ec_level.map_or("none".into(), |ec| format!("{ec:?}")),
+                symbol.format.mask,
+                symbol.format_errors,
+                qr::mask_formula(symbol.format.qr_mask()).expect("All micro masks exist in QR"),
+                if ec_level.is_none() {
+                    " M1 symbols only detect errors, they don't correct them."
+                } else {
+                    ""
+                },
+            ),
+        );
+    }
+
+    fn rmqr_symbol(&mut self, grid: Grid, symbol: &RmqrSymbol) {
+        println!(
+            "version = {}, ec_level = {:?}, format_errors = {}",
+            symbol.format.name(),
+            symbol.format.ec_level,
+            symbol.format_errors
+        );
+        self.report.info("version", symbol.format.name());
+        self.report
+            .info("ec level", format!("{:?}", symbol.format.ec_level));
+        self.report.info("format errors", symbol.format_errors);
+        self.explanation.grid(
+            grid.bounds(),
+            symbol.format.width(),
+            symbol.format.height(),
+            None,
+        );
+        self.explanation.section(
+            "Format info",
+            format!(
+                "The 18 bit format info next to the finder and the sub finder reads as {}, error correction \
+                 level {:?}, after correcting {} wrong bit(s). rMQR codes always use the mask \
+                 {}.",
+                symbol.format.name(),
+                symbol.format.ec_level,
+                symbol.format_errors,
+                qr::mask_formula(rmqr::MASK_PATTERN).expect("rMQR mask exists in QR"),
+            ),
+        );
+    }
+
+    /// What every kind of symbol shows once it's read: the matrix, the codeword
+    /// map, the syndromes, the data modules with their tooltips and the bitstream
+    fn symbol_details(
+        &mut self,
+        img: &image::DynamicImage,
+        grid: Grid,
+        symbol: ReadSymbol,
+    ) -> qrious::Result<()> {
+        let matrix = symbol.matrix();
+        self.report.matrix(matrix);
+        let map = symbol.module_map();
+        if self.cli.codeword_map.is_some() {
+            // in the grid sampled from the image, which a mirrored symbol transposes
+            self.codeword_map = Some(
+                map.iter()
+                    .map(|module| {
+                        let (x, y) = symbol.grid_position(module.x, module.y);
+                        ModuleBit { x, y, ..*module }
+                    })
+                    .collect(),
+            );
+        }
+        let layout = symbol.block_layout();
+        let codewords = symbol.codewords()?;
+        self.explanation.syndromes(&layout, &codewords);
+        let mut origins = BitOrigins::new(map, &layout, codewords, self.params.module_white_ratio);
+        if !symbol.corrects_errors() {
+            origins.corrected = None;
+        }
+        viz_bits(
+            symbol.bit_iter()?,
+            |x, y| {
+                let (x, y) = symbol.grid_position(x, y);
+                grid.idx_to_module(x, y)
+            },
+            |i, output, module| {
+                let (x, y) = (output.x as usize, output.y as usize);
+                origins.title(
+                    i,
+                    symbol.grid_position(x, y),
+                    img::white_ratio(img, module, self.params.white_threshold),
+                    matrix.confidence(x, y),
+                    matrix.get(x, y),
+                    output.bit,
+                )
+            },
+            &mut self.decoded_vis,
+            &mut self.dbg_vis,
+            self.report,
+        )?;
+
+        let bitstream = symbol.bitstream();
+        if let Ok(bitstream) = &bitstream {
+            self.explanation.bitstream(bitstream);
+        }
+        if self.cli.bitstream {
+            print_bitstream(&bitstream?);
+        }
+        Ok(())
+    }
+}
+
+impl DecodeObserver for CliObserver<'_> {
+    fn visualizer(&mut self) -> Option<&mut dyn Visualizer> {
+        Some(&mut self.dbg_vis)
+    }
+
+    fn finders(&mut self, finders: &[Rect]) -> qrious::Result<()> {
+        self.step("find patterns");
+        self.dbg_vis.end_layer()?;
+        self.dbg_vis.begin_layer("Finders", "yellow", None)?;
+        for finder in finders {
+            finder.draw(&mut self.dbg_vis, "yellow", None)?;
+            self.report.rect(Layer::Finders, finder);
+        }
+        self.explanation.finders(finders);
+        Ok(())
+    }
+
+    fn grid(&mut self, img: &image::DynamicImage, grid: Grid) -> qrious::Result<()> {
+        self.step("place grid");
+        if let Grid::Qr(qr::Code {
+            inferred_finder: Some(finder),
+            ..
+        }) = grid
+        {
+            finder.draw(&mut self.dbg_vis, "yellow", None)?;
+        }
+        self.dbg_vis.end_layer()?;
+        grid.bounds().draw(&mut self.dbg_vis, "gray", None)?;
+        grid.bounds().draw(&mut self.decoded_vis, "gray", None)?;
+        match grid {
+            Grid::Qr(code) => self.qr_grid(img, code),
+            _ => Ok(()),
+        }
+    }
+
+    fn symbol(
+        &mut self,
+        img: &image::DynamicImage,
+        grid: Grid,
+        symbol: ReadSymbol,
+    ) -> qrious::Result<()> {
+        self.step("read symbol");
+        match symbol {
+            ReadSymbol::Qr(symbol) => self.qr_symbol(symbol),
+            ReadSymbol::Micro(symbol) => self.micro_symbol(grid, symbol),
+            ReadSymbol::Rmqr(symbol) => self.rmqr_symbol(grid, symbol),
+        }
+        self.symbol_details(img, grid, symbol)
+    }
+}
+
+fn print_message(message: &Message) {
+    for segment in message.segments.iter() {
+        println!(
+            "segment = {:?}, count = {}, data = {:?}",
+            segment.mode, segment.count, segment.data
+        );
+    }
+    println!("corrected = {}", message.corrected);
+    println!("text = {:?}", message.text());
+}
+
+fn print_bitstream(bitstream: &Bitstream) {
+    for field in &bitstream.fields {
+        println!("{field}");
+    }
+    if let Some(err) = &bitstream.error {
+        println!("stopped: {err}");
+    }
+}
+
+fn viz_bits(
+    iter: impl Iterator<Item = Output>,
+    to_module: impl Fn(usize, usize) -> Rect,
+    title: impl Fn(usize, &Output, &Rect) -> String,
+    decoded_vis: &mut dyn Visualizer,
+    dbg_vis: &mut dyn Visualizer,
+    report: &mut Report,
+) -> qrious::Result<()> {
+    dbg_vis.begin_layer("Data order", "orange", None)?;
     for (i, item) in iter.enumerate() {
-        let Output { module, bit, .. } = item;
-        if bit {
+        let module = to_module(item.x as usize, item.y as usize);
+        if item.bit {
             module.draw(decoded_vis, "black", Some("black"))?;
         }
-        module.draw(dbg_vis, "orange", None)?;
+        let title = title(i, &item, &module);
+        module.draw_with_title(dbg_vis, "orange", None, &title)?;
         dbg_vis.draw_text(module.cx(), module.cy(), i.to_string().as_str(), "red")?;
+        report.labelled_rect(Layer::DataOrder, &module, i.to_string(), Some(title));
+    }
+    dbg_vis.end_layer()?;
+    Ok(())
+}
+
+/// Writes `map` to codeword-map.csv or .json in `output`, one row or object per
+/// data module. Remainder bits leave the codeword fields empty.
+fn write_codeword_map(output: &Path, map: &[ModuleBit], format: MapFormat) -> Result<()> {
+    let mut out = String::new();
+    match format {
+        MapFormat::Csv => {
+            out.push_str("x,y,codeword,block,index,kind,bit\n");
+            for module in map {
+                write!(out, "{},{},", module.x, module.y)?;
+                match module.codeword {
+                    Some(bit) => writeln!(
+                        out,
+                        "{},{},{},{},{}",
+                        bit.codeword,
+                        bit.block,
+                        bit.index,
+                        if bit.ec { "ec" } else { "data" },
+                        bit.bit
+                    )?,
+                    None => writeln!(out, ",,,remainder,")?,
+                }
+            }
+        }
+        MapFormat::Json => {
+            out.push_str("[\n");
+            for (i, module) in map.iter().enumerate() {
+                write!(out, r#"  {{"x": {}, "y": {}, "#, module.x, module.y)?;
+                match module.codeword {
+                    Some(bit) => write!(
+                        out,
+                        r#""codeword": {}, "block": {}, "index": {}, "kind": "{}", "bit": {}}}"#,
+                        bit.codeword,
+                        bit.block,
+                        bit.index,
+                        if bit.ec { "ec" } else { "data" },
+                        bit.bit
+                    )?,
+                    None => write!(
+                        out,
+                        r#""codeword": null, "block": null, "index": null, "kind": "remainder", "bit": null}}"#
+                    )?,
+                }
+                out.push_str(if i + 1 < map.len() { ",\n" } else { "\n" });
+            }
+            out.push_str("]\n");
+        }
     }
+    let extension = match format {
+        MapFormat::Csv => "csv",
+        MapFormat::Json => "json",
+    };
+    fs::write(output.join("codeword-map").with_extension(extension), out)?;
     Ok(())
 }
 
-fn init_output_dir(cli: &Cli) -> Result<PathBuf> {
+fn init_output_dir(cli: &Cli) -> Result<Background> {
     fs::create_dir_all(&cli.output)?;
+    if !cli.copy_input {
+        return Ok(Background::embed_file(&cli.input)?);
+    }
+
     // copy the input file to the output directory
     let extension = cli.input.extension().unwrap();
     let file_name = PathBuf::new()
@@ -104,44 +616,35 @@ fn init_output_dir(cli: &Cli) -> Result<PathBuf> {
     let output_file_name = cli.output.join(file_name.clone());
     fs::copy(&cli.input, &output_file_name)?;
 
-    Ok(file_name)
+    Ok(Background::Link(file_name))
 }
 
 fn viz_timing_iter(
     horiz_iter: HorizTimingIter,
     vert_iter: VertTimingIter,
-    visualizer: &mut Visualizer,
-) -> Result<()> {
-    for module in horiz_iter {
-        module.draw(visualizer, "red", None)?;
-    }
-    for module in vert_iter {
+    visualizer: &mut dyn Visualizer,
+    report: &mut Report,
+) -> qrious::Result<()> {
+    visualizer.begin_layer("Timing", "red", None)?;
+    for module in horiz_iter.chain(vert_iter) {
         module.draw(visualizer, "red", None)?;
+        report.rect(Layer::Timing, &module);
     }
+    visualizer.end_layer()?;
     Ok(())
 }
 
 fn viz_format_iter(
     horiz_iter: HorizFormatIter,
     vert_iter: VertFormatIter,
-    visualizer: &mut Visualizer,
-) -> Result<()> {
-    for module in horiz_iter {
-        module.draw(visualizer, "purple", None)?;
-    }
-    for module in vert_iter {
+    visualizer: &mut dyn Visualizer,
+    report: &mut Report,
+) -> qrious::Result<()> {
+    visualizer.begin_layer("Format", "purple", None)?;
+    for module in horiz_iter.chain(vert_iter) {
         module.draw(visualizer, "purple", None)?;
+        report.rect(Layer::Format, &module);
     }
-    Ok(())
-}
-
-fn inspect_timing(iter: impl Iterator<Item = Rect>, img: &image::DynamicImage) -> Result<()> {
-    let mut expected = true;
-    for module in iter {
-        if img::is_white_module(img, &module) != expected {
-            return Err(anyhow!("Timing module {:?} is not as expected", &module));
-        }
-        expected = !expected;
-    }
+    visualizer.end_layer()?;
     Ok(())
 }
@@ -0,0 +1,360 @@
+use crate::error::MatrixParseError;
+
+/// The sampled modules of a symbol, `true` is dark. Each module also has a
+/// confidence from 0, as likely light as dark, to 1, no doubt about it. Modules
+/// that weren't sampled from an image, like those of an encoded symbol, have 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModuleMatrix {
+    width: usize,
+    height: usize,
+    modules: Vec<bool>,
+    confidence: Vec<f32>,
+}
+
+impl ModuleMatrix {
+    pub fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            modules: vec![false; width * height],
+            confidence: vec![1.0; width * height],
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> bool {
+        self.modules[y * self.width + x]
+    }
+
+    pub fn set(&mut self, x: usize, y: usize, dark: bool) {
+        self.modules[y * self.width + x] = dark;
+    }
+
+    pub fn confidence(&self, x: usize, y: usize) -> f32 {
+        self.confidence[y * self.width + x]
+    }
+
+    pub fn set_confidence(&mut self, x: usize, y: usize, confidence: f32) {
+        self.confidence[y * self.width + x] = confidence;
+    }
+
+    /// Sets a module from the share of white pixels sampled in it, which is light
+    /// above `threshold`. The further the share is from it, the higher the confidence.
+    pub fn set_sampled(&mut self, x: usize, y: usize, white_ratio: f32, threshold: f32) {
+        let white = white_ratio > threshold;
+        let confidence = if white {
+            (white_ratio - threshold) / (1.0 - threshold)
+        } else {
+            (threshold - white_ratio) / threshold
+        };
+        self.set(x, y, !white);
+        self.set_confidence(x, y, confidence.clamp(0.0, 1.0));
+    }
+
+    /// Reads modules drawn as text, one row per line with `#` or `█` for dark and
+    /// `.` or a space for light. Lines with half blocks (`▀` and `▄`, as
+    /// [`crate::render::to_unicode`] draws them) hold two rows each. Short lines
+    /// are padded with light modules, as trailing spaces tend to get lost.
+    pub fn from_text(text: &str) -> Result<Self, MatrixParseError> {
+        let lines: Vec<&str> = text
+            .lines()
+            .skip_while(|line| line.trim().is_empty())
+            .collect();
+        let num_lines = lines
+            .iter()
+            .rposition(|line| !line.trim().is_empty())
+            .map_or(0, |last| last + 1);
+        let lines = &lines[..num_lines];
+        let half_blocks = text.contains(['▀', '▄']);
+
+        let width = lines
+            .iter()
+            .map(|line| line.chars().count())
+            .max()
+            .unwrap_or(0);
+        let rows_per_line = if half_blocks { 2 } else { 1 };
+        let mut matrix = Self::new(width, lines.len() * rows_per_line);
+        if matrix.modules.is_empty() {
+            return Err(MatrixParseError::Empty);
+        }
+        for (i, line) in lines.iter().enumerate() {
+            for (x, ch) in line.chars().enumerate() {
+                let (top, bottom) = match ch {
+                    '#' | '█' => (true, true),
+                    '.' | ' ' => (false, false),
+                    '▀' if half_blocks => (true, false),
+                    '▄' if half_blocks => (false, true),
+                    _ => {
+                        return Err(MatrixParseError::InvalidChar {
+                            line: i + 1,
+                            column: x + 1,
+                            ch,
+                        })
+                    }
+                };
+                let y = i * rows_per_line;
+                matrix.set(x, y, top);
+                if half_blocks {
+                    matrix.set(x, y + 1, bottom);
+                }
+            }
+        }
+        Ok(matrix)
+    }
+
+    /// Reads a plain (P1) or raw (P4) PBM image, one pixel per module
+    pub fn from_pbm(data: &[u8]) -> Result<Self, MatrixParseError> {
+        let (raw, width, height, body) =
+            parse_pbm_header(data).ok_or(MatrixParseError::InvalidPbmHeader)?;
+        let expected = width
+            .checked_mul(height)
+            .ok_or(MatrixParseError::InvalidPbmHeader)?;
+        if expected == 0 {
+            return Err(MatrixParseError::Empty);
+        }
+        let truncated = |found| MatrixParseError::TruncatedPbm { expected, found };
+        // the size comes from the file, so it is checked against the data before
+        // anything that large is allocated
+        if raw {
+            let row_len = width.div_ceil(8);
+            if body.len() < row_len * height {
+                return Err(truncated(body.len() / row_len * width));
+            }
+            let mut matrix = Self::new(width, height);
+            for y in 0..height {
+                let row = body
+                    .get(y * row_len..(y + 1) * row_len)
+                    .ok_or(truncated(y * width))?;
+                for x in 0..width {
+                    matrix.set(x, y, row[x / 8] >> (7 - x % 8) & 1 == 1);
+                }
+            }
+            Ok(matrix)
+        } else {
+            let mut pixels = body
+                .split(|&byte| byte == b'\n')
+                .flat_map(|line| line.split(|&byte| byte == b'#').next().unwrap_or(&[]))
+                .filter(|byte| !byte.is_ascii_whitespace());
+            // each pixel takes at least a byte
+            if body.len() < expected {
+                return Err(truncated(pixels.count()));
+            }
+            let mut matrix = Self::new(width, height);
+            for i in 0..expected {
+                let dark = match pixels.next() {
+                    Some(b'1') => true,
+                    Some(b'0') => false,
+                    Some(&byte) => {
+                        return Err(MatrixParseError::InvalidPbmPixel {
+                            pixel: i,
+                            ch: byte as char,
+                        })
+                    }
+                    None => return Err(truncated(i)),
+                };
+                matrix.set(i % width, i / width, dark);
+            }
+            Ok(matrix)
+        }
+    }
+
+    /// Crops the light margin around the symbol. Every kind of symbol has dark
+    /// modules in its first and last row and column, so this is exactly the quiet
+    /// zone. A matrix with no dark modules is returned as it is.
+    pub fn without_quiet_zone(&self) -> Self {
+        let dark: Vec<(usize, usize)> = self
+            .iter()
+            .filter(|&(_, _, dark)| dark)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        let (Some(left), Some(right)) = (
+            dark.iter().map(|&(x, _)| x).min(),
+            dark.iter().map(|&(x, _)| x).max(),
+        ) else {
+            return self.clone();
+        };
+        let top = dark[0].1;
+        let bottom = dark[dark.len() - 1].1;
+        self.remap(right - left + 1, bottom - top + 1, |x, y| {
+            (x + left, y + top)
+        })
+    }
+
+    /// Every module as `(x, y, dark)`, row by row
+    pub fn iter(&self) -> impl Iterator<Item = (usize, usize, bool)> + '_ {
+        self.modules
+            .iter()
+            .enumerate()
+            .map(|(i, &dark)| (i % self.width, i / self.width, dark))
+    }
+
+    /// Swaps rows and columns, which undoes a symbol that was mirrored
+    /// along its main diagonal (e.g. printed on the back of a transparency).
+    pub fn transpose(&self) -> Self {
+        self.remap(self.height, self.width, |x, y| (y, x))
+    }
+
+    /// Flips left and right
+    pub fn mirror(&self) -> Self {
+        self.remap(self.width, self.height, |x, y| (self.width - 1 - x, y))
+    }
+
+    /// Turns a quarter clockwise
+    pub fn rotate(&self) -> Self {
+        self.remap(self.height, self.width, |x, y| (y, self.height - 1 - x))
+    }
+
+    /// A `width` by `height` matrix whose module `(x, y)` is the one at `from(x, y)`
+    fn remap(
+        &self,
+        width: usize,
+        height: usize,
+        from: impl Fn(usize, usize) -> (usize, usize),
+    ) -> Self {
+        let mut result = Self::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let (from_x, from_y) = from(x, y);
+                result.set(x, y, self.get(from_x, from_y));
+                result.set_confidence(x, y, self.confidence(from_x, from_y));
+            }
+        }
+        result
+    }
+}
+
+/// Whether the PBM is raw, its size, and the pixel data after the header
+fn parse_pbm_header(data: &[u8]) -> Option<(bool, usize, usize, &[u8])> {
+    let raw = match data.get(..2)? {
+        b"P1" => false,
+        b"P4" => true,
+        _ => return None,
+    };
+    let mut pos = 2;
+    let mut numbers = [0; 2];
+    for number in numbers.iter_mut() {
+        // whitespace and comments up to the next number
+        loop {
+            match data.get(pos)? {
+                byte if byte.is_ascii_whitespace() => pos += 1,
+                b'#' => pos += data[pos..].iter().position(|&byte| byte == b'\n')?,
+                _ => break,
+            }
+        }
+        let len = data[pos..]
+            .iter()
+            .take_while(|byte| byte.is_ascii_digit())
+            .count();
+        *number = std::str::from_utf8(&data[pos..pos + len])
+            .ok()?
+            .parse()
+            .ok()?;
+        pos += len;
+    }
+    // a single whitespace character separates the header from raw data
+    if !data.get(pos)?.is_ascii_whitespace() {
+        return None;
+    }
+    Some((raw, numbers[0], numbers[1], &data[pos + 1..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_transforms() {
+        let mut matrix = ModuleMatrix::new(3, 2);
+        matrix.set(1, 0, true);
+        matrix.set_sampled(2, 1, 0.2, 0.5);
+        assert!(matrix.get(2, 1));
+        assert!((matrix.confidence(2, 1) - 0.6).abs() < 1e-6);
+
+        let rotated = matrix.rotate();
+        assert_eq!((rotated.width(), rotated.height()), (2, 3));
+        assert!(rotated.get(1, 1) && rotated.get(0, 2));
+        assert_eq!(rotated.confidence(0, 2), matrix.confidence(2, 1));
+        assert_eq!(rotated.rotate().rotate().rotate(), matrix);
+        assert_eq!(matrix.mirror().mirror(), matrix);
+        assert_eq!(rotated.mirror(), matrix.transpose());
+
+        let dark: Vec<(usize, usize)> = matrix
+            .iter()
+            .filter(|&(_, _, dark)| dark)
+            .map(|(x, y, _)| (x, y))
+            .collect();
+        assert_eq!(dark, [(1, 0), (2, 1)]);
+    }
+
+    #[test]
+    fn test_from_text() {
+        let matrix = ModuleMatrix::from_text("\n  \n  #.#\n  .#\n\n").unwrap();
+        assert_eq!((matrix.width(), matrix.height()), (5, 2));
+        let trimmed = matrix.without_quiet_zone();
+        assert_eq!((trimmed.width(), trimmed.height()), (3, 2));
+        assert!(trimmed.get(0, 0) && !trimmed.get(1, 0) && trimmed.get(1, 1));
+        assert!(!trimmed.get(2, 1));
+
+        let half_blocks = ModuleMatrix::from_text("▀▄█ \n").unwrap();
+        assert_eq!((half_blocks.width(), half_blocks.height()), (4, 2));
+        assert!(half_blocks.get(0, 0) && half_blocks.get(1, 1) && half_blocks.get(2, 1));
+        assert!(!half_blocks.get(1, 0) && !half_blocks.get(3, 1));
+
+        assert!(matches!(
+            ModuleMatrix::from_text("#.\n#x"),
+            Err(MatrixParseError::InvalidChar {
+                line: 2,
+                column: 2,
+                ch: 'x'
+            })
+        ));
+    }
+
+    #[test]
+    fn test_from_pbm() {
+        let plain = ModuleMatrix::from_pbm(b"P1\n# a comment\n3 2\n1 0 1\n011\n").unwrap();
+        let raw = ModuleMatrix::from_pbm(b"P4 3 2\n\xA0\x60").unwrap();
+        assert_eq!(plain, raw);
+        assert!(plain.get(0, 0) && !plain.get(1, 0) && plain.get(2, 1));
+
+        assert!(matches!(
+            ModuleMatrix::from_pbm(b"P4 3 2\n\xA0"),
+            Err(MatrixParseError::TruncatedPbm { found: 3, .. })
+        ));
+        assert!(ModuleMatrix::from_pbm(b"P2 3 2\n").is_err());
+
+        assert!(matches!(
+            ModuleMatrix::from_pbm(b"P1 3 2\n1 0 1\n0 2 1\n"),
+            Err(MatrixParseError::InvalidPbmPixel { pixel: 4, ch: '2' })
+        ));
+        assert!(matches!(
+            ModuleMatrix::from_pbm(b"P1 3 2\n1 0 1\n"),
+            Err(MatrixParseError::TruncatedPbm { found: 3, .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_pbm_huge_header() {
+        // rejected before allocating 10^10 modules
+        assert!(matches!(
+            ModuleMatrix::from_pbm(b"P4 100000 100000\n"),
+            Err(MatrixParseError::TruncatedPbm { found: 0, .. })
+        ));
+        assert!(matches!(
+            ModuleMatrix::from_pbm(b"P1 100000 100000\n1 0 1\n"),
+            Err(MatrixParseError::TruncatedPbm { found: 3, .. })
+        ));
+        let overflowing = format!("P4 {} 2\n", usize::MAX);
+        assert!(matches!(
+            ModuleMatrix::from_pbm(overflowing.as_bytes()),
+            Err(MatrixParseError::InvalidPbmHeader)
+        ));
+    }
+}
@@ -0,0 +1,490 @@
+use image::GenericImageView;
+
+use crate::{
+    codewords::{self, BlockLayout, ModuleBit},
+    decode::Version,
+    error::{DecodeError, Result},
+    format::{bch_remainder, EcLevel, MAX_BCH_ERRORS},
+    img,
+    matrix::ModuleMatrix,
+    qr::{self, get_mask_fn, DetectorParams, Output},
+    rs,
+    segment::{self, BitReader, Bitstream, HeaderSpec, Message, Mode},
+    util::Rect,
+    viz::Visualizer,
+};
+
+const FINDER_NUM_ELEMS: usize = 7;
+/// The finder, its separator and the format info take up the top left 9x9 modules
+const FUNCTION_CORNER_NUM_ELEMS: usize = 9;
+pub const MAX_VERSION: u8 = 4;
+const FORMAT_GENERATOR: u32 = 0b101_0011_0111;
+const FORMAT_XOR_MASK: u32 = 0b100_0100_0100_0101;
+const FORMAT_NUM_BITS: usize = 15;
+
+/// Micro QR only uses 4 of the QR mask patterns, indexed by the 2 bit mask reference
+const MASK_PATTERNS: [u8; 4] = [0b001, 0b100, 0b110, 0b111];
+
+/// Version, ec level and number of data codewords, indexed by the symbol number
+/// from the format info. M1 only detects errors, so it has no ec level.
+const SYMBOLS: [(u8, Option<EcLevel>, usize); 8] = [
+    (1, None, 3),
+    (2, Some(EcLevel::L), 5),
+    (2, Some(EcLevel::M), 4),
+    (3, Some(EcLevel::L), 11),
+    (3, Some(EcLevel::M), 9),
+    (4, Some(EcLevel::L), 16),
+    (4, Some(EcLevel::M), 14),
+    (4, Some(EcLevel::Q), 10),
+];
+
+/// Total codewords indexed by version
+const NUM_CODEWORDS: [usize; 5] = [0, 5, 10, 17, 24];
+
+const M1_MODES: &[(u32, Mode)] = &[(0, Mode::Numeric)];
+const M2_MODES: &[(u32, Mode)] = &[(0, Mode::Numeric), (1, Mode::Alphanumeric)];
+const M3_M4_MODES: &[(u32, Mode)] = &[
+    (0, Mode::Numeric),
+    (1, Mode::Alphanumeric),
+    (2, Mode::Byte),
+    (3, Mode::Kanji),
+];
+
+pub fn version_to_size(version: u8) -> usize {
+    9 + 2 * version as usize
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MicroFormat {
+    /// 1 to 4, for M1 to M4
+    pub version: u8,
+    pub ec_level: Option<EcLevel>,
+    /// The 2 bit Micro QR mask reference, see [`MicroFormat::qr_mask`]
+    pub mask: u8,
+}
+
+impl MicroFormat {
+    fn symbol_number(&self) -> u8 {
+        SYMBOLS
+            .iter()
+            .position(|(version, ec_level, _)| {
+                *version == self.version && *ec_level == self.ec_level
+            })
+            .expect("Only valid combinations are constructed") as u8
+    }
+
+    /// The 15 bit format word, as it is placed in the symbol (already xor-ed with the mask)
+    pub fn encode(&self) -> u32 {
+        let data = ((self.symbol_number() as u32) << 2 | self.mask as u32) << 10;
+        (data | bch_remainder(data, FORMAT_GENERATOR)) ^ FORMAT_XOR_MASK
+    }
+
+    /// Finds the nearest valid format word, returns it with its hamming distance to `bits`
+    pub fn decode(bits: u32) -> Option<(Self, u32)> {
+        SYMBOLS
+            .iter()
+            .flat_map(|&(version, ec_level, _)| {
+                (0..4).map(move |mask| MicroFormat {
+                    version,
+                    ec_level,
+                    mask,
+                })
+            })
+            .map(|format| (format, (format.encode() ^ bits).count_ones()))
+            .min_by_key(|(_, distance)| *distance)
+            .filter(|(_, distance)| *distance <= MAX_BCH_ERRORS)
+    }
+
+    /// The equivalent QR mask pattern reference, for [`get_mask_fn`]
+    pub fn qr_mask(&self) -> u8 {
+        MASK_PATTERNS[self.mask as usize]
+    }
+
+    pub fn size(&self) -> usize {
+        version_to_size(self.version)
+    }
+
+    pub fn num_data_codewords(&self) -> usize {
+        SYMBOLS[self.symbol_number() as usize].2
+    }
+
+    pub fn num_ec_codewords(&self) -> usize {
+        NUM_CODEWORDS[self.version as usize] - self.num_data_codewords()
+    }
+
+    /// A single block, with the 4 bit data codeword of M1 and M3 counted as a whole one
+    pub fn block_layout(&self) -> BlockLayout {
+        BlockLayout::new(
+            NUM_CODEWORDS[self.version as usize],
+            1,
+            self.num_ec_codewords(),
+        )
+    }
+
+    /// Positions of the data and ec modules, in placement order
+    pub fn data_positions(&self) -> Vec<(usize, usize)> {
+        let size = self.size();
+        codewords::placement_order(size, size, None, is_function_module)
+    }
+
+    /// Which codeword and bit each data module holds
+    pub fn module_map(&self) -> Vec<ModuleBit> {
+        codewords::module_map(
+            &self.data_positions(),
+            &self.block_layout(),
+            self.num_data_bits(),
+        )
+    }
+
+    /// M1 and M3 end their data with a 4 bit codeword
+    pub fn num_data_bits(&self) -> usize {
+        let bits = self.num_data_codewords() * 8;
+        if self.version % 2 == 1 {
+            bits - 4
+        } else {
+            bits
+        }
+    }
+
+    pub fn header_spec(&self) -> HeaderSpec {
+        let (modes, count_bits) = match self.version {
+            1 => (M1_MODES, [3, 0, 0, 0]),
+            2 => (M2_MODES, [4, 3, 0, 0]),
+            3 => (M3_M4_MODES, [5, 4, 4, 3]),
+            _ => (M3_M4_MODES, [6, 5, 5, 4]),
+        };
+        HeaderSpec {
+            mode_bits: self.version as usize - 1,
+            modes,
+            count_bits,
+            terminator_bits: 2 * self.version as usize + 1,
+        }
+    }
+}
+
+/// The single copy of the format info around the finder, bit 0 first
+fn format_positions() -> impl Iterator<Item = (usize, usize)> {
+    (0..FORMAT_NUM_BITS).map(|i| match i {
+        0..=7 => (8, i + 1),
+        _ => (15 - i, 8),
+    })
+}
+
+fn is_function_module(x: usize, y: usize) -> bool {
+    x == 0 || y == 0 || (x < FUNCTION_CORNER_NUM_ELEMS && y < FUNCTION_CORNER_NUM_ELEMS)
+}
+
+/// Counts the modules of a Micro QR symbol along one of its timing patterns,
+/// which run from the separator right of (or below) the finder to the last module.
+fn count_timing_modules(
+    start_px: u32,
+    end_px: u32,
+    elem_size: f32,
+    quiet_zone_run: f32,
+    get_pix_val: impl Fn(u32) -> bool,
+) -> Option<(usize, f32)> {
+    if start_px >= end_px {
+        // the finder is at the edge of the image
+        return None;
+    }
+    let mut num_dark_runs = 0;
+    let mut run_start = start_px;
+    let mut last_dark_end = start_px as f32;
+    let mut last_is_white = get_pix_val(start_px);
+
+    for px in start_px..=end_px {
+        let is_white = if px == end_px {
+            !last_is_white
+        } else {
+            get_pix_val(px)
+        };
+        if is_white == last_is_white {
+            continue;
+        }
+        let run_len = (px - run_start) as f32;
+        if last_is_white && run_len > quiet_zone_run * elem_size {
+            // the quiet zone after the last timing module
+            break;
+        }
+        if !last_is_white {
+            num_dark_runs += 1;
+            last_dark_end = px as f32;
+        }
+        run_start = px;
+        last_is_white = is_white;
+    }
+
+    (num_dark_runs > 0).then_some((FINDER_NUM_ELEMS + 2 * num_dark_runs, last_dark_end))
+}
+
+pub struct MicroCode {
+    pub bounds: Rect,
+    elem_width: f32,
+    elem_height: f32,
+    params: DetectorParams,
+}
+
+impl MicroCode {
+    pub fn new(img: &image::DynamicImage, visualizer: Option<&mut dyn Visualizer>) -> Result<Self> {
+        let params = DetectorParams::default();
+        let finders = qr::find_patterns(img, &params, visualizer)?;
+        if finders.len() != 1 {
+            return Err(DecodeError::FinderNotFound {
+                expected: 1,
+                found: finders.len(),
+            });
+        }
+        Self::from_finder(img, &finders[0], &params)
+    }
+
+    /// Uses the timing patterns going right and down from the finder to find the symbol size
+    pub fn from_finder(
+        img: &image::DynamicImage,
+        finder: &Rect,
+        params: &DetectorParams,
+    ) -> Result<Self> {
+        let (width, height) = img.dimensions();
+        let estimated_elem_width = finder.width() / FINDER_NUM_ELEMS as f32;
+        let estimated_elem_height = finder.height() / FINDER_NUM_ELEMS as f32;
+        let left = finder.left();
+        let top = finder.top();
+
+        // the timing patterns are on the outer edge, in line with the finder
+        let timing_row_center_px = (top + estimated_elem_height / 2.0) as u32;
+        let timing_col_center_px = (left + estimated_elem_width / 2.0) as u32;
+
+        let (horiz_size, right) = count_timing_modules(
+            (left + (FINDER_NUM_ELEMS as f32 + 1.0) * estimated_elem_width) as u32,
+            width,
+            estimated_elem_width,
+            params.quiet_zone_run,
+            |x| {
+                img::is_white(
+                    &img.get_pixel(x, timing_row_center_px),
+                    params.white_threshold,
+                )
+            },
+        )
+        .ok_or(DecodeError::TimingNotFound { horizontal: true })?;
+        let (vert_size, bottom) = count_timing_modules(
+            (top + (FINDER_NUM_ELEMS as f32 + 1.0) * estimated_elem_height) as u32,
+            height,
+            estimated_elem_height,
+            params.quiet_zone_run,
+            |y| {
+                img::is_white(
+                    &img.get_pixel(timing_col_center_px, y),
+                    params.white_threshold,
+                )
+            },
+        )
+        .ok_or(DecodeError::TimingNotFound { horizontal: false })?;
+
+        if horiz_size != vert_size
+            || !(version_to_size(1)..=version_to_size(MAX_VERSION)).contains(&horiz_size)
+        {
+            return Err(DecodeError::InvalidSize {
+                width: horiz_size,
+                height: vert_size,
+            });
+        }
+
+        let bounds = Rect::from_corners(left, top, right, bottom);
+        Ok(Self {
+            elem_width: bounds.width() / horiz_size as f32,
+            elem_height: bounds.height() / vert_size as f32,
+            bounds,
+            params: params.clone(),
+        })
+    }
+
+    pub fn idx_to_module(&self, x: usize, y: usize) -> Rect {
+        qr::idx_to_module(&self.bounds, self.elem_width, self.elem_height, x, y)
+    }
+
+    pub fn size(&self) -> usize {
+        (self.bounds.width() / self.elem_width).round() as usize
+    }
+
+    pub fn sample(&self, img: &image::DynamicImage) -> ModuleMatrix {
+        let size = self.size();
+        let mut matrix = ModuleMatrix::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                let white_ratio =
+                    img::white_ratio(img, &self.idx_to_module(x, y), self.params.white_threshold);
+                matrix.set_sampled(x, y, white_ratio, self.params.module_white_ratio);
+            }
+        }
+        matrix
+    }
+
+    pub fn read_symbol(&self, img: &image::DynamicImage) -> Result<MicroSymbol> {
+        MicroSymbol::new(self.sample(img))?.check_version(&self.params)
+    }
+}
+
+/// The sampled modules of a [`MicroCode`], with the format info read from them
+pub struct MicroSymbol {
+    pub matrix: ModuleMatrix,
+    pub format: MicroFormat,
+    /// Number of bits corrected while decoding the format info
+    pub format_errors: u32,
+}
+
+impl MicroSymbol {
+    pub fn new(matrix: ModuleMatrix) -> Result<Self> {
+        let bits = format_positions()
+            .enumerate()
+            .filter(|(_, (x, y))| matrix.get(*x, *y))
+            .fold(0, |word, (i, _)| word | 1 << i);
+        let (format, format_errors) = MicroFormat::decode(bits)
+            .ok_or(DecodeError::FormatUnreadable { copies: vec![bits] })?;
+
+        let (width, height) = (matrix.width(), matrix.height());
+        let size_version = (1..=MAX_VERSION).find(|&version| version_to_size(version) == width);
+        let Some(size_version) = size_version.filter(|_| width == height) else {
+            return Err(DecodeError::InvalidSize { width, height });
+        };
+        if size_version != format.version {
+            return Err(DecodeError::VersionMismatch {
+                from_size: Version::Micro(size_version),
+                from_info: Version::Micro(format.version),
+            });
+        }
+
+        Ok(Self {
+            matrix,
+            format,
+            format_errors,
+        })
+    }
+
+    /// Rejects the symbol if its version isn't one of `params.micro_versions`
+    pub fn check_version(self, params: &DetectorParams) -> Result<Self> {
+        let versions = &params.micro_versions;
+        if !versions.contains(&self.format.version) {
+            return Err(DecodeError::VersionOutOfRange {
+                version: self.format.version,
+                min: *versions.start(),
+                max: *versions.end(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Unmasked data bits in placement order, black is 1
+    pub fn bit_iter(&self) -> impl Iterator<Item = Output> + '_ {
+        let mask_fn = get_mask_fn(self.format.qr_mask()).expect("All micro masks exist in QR");
+        self.format
+            .data_positions()
+            .into_iter()
+            .map(move |(x, y)| Output {
+                x: x as isize,
+                y: y as isize,
+                bit: self.matrix.get(x, y) != mask_fn(x as u32, y as u32),
+            })
+    }
+
+    /// Data codewords followed by ec codewords. A 4 bit data codeword ends up in the high nibble.
+    pub fn codewords(&self) -> Vec<u8> {
+        let num_data_bits = self.format.num_data_bits();
+        let num_ec_bits = self.format.num_ec_codewords() * 8;
+        let bits: Vec<bool> = self.bit_iter().map(|output| output.bit).collect();
+
+        let mut result = pack_bits(&bits[..num_data_bits]);
+        result.extend(pack_bits(&bits[num_data_bits..num_data_bits + num_ec_bits]));
+        result
+    }
+
+    /// The codewords with errors corrected, and how many codewords were corrected
+    fn corrected_codewords(&self) -> Result<(Vec<u8>, usize)> {
+        let mut codewords = self.codewords();
+        let num_ec = self.format.num_ec_codewords();
+        let corrected = if self.format.ec_level.is_none() {
+            if rs::syndromes(&codewords, num_ec).iter().any(|&s| s != 0) {
+                // M1 symbols can only detect errors
+                return Err(DecodeError::TooManyErrors {
+                    corrected: 1,
+                    capacity: 0,
+                });
+            }
+            0
+        } else {
+            rs::correct(&mut codewords, num_ec)?
+        };
+        Ok((codewords, corrected))
+    }
+
+    pub fn decode(&self) -> Result<Message> {
+        let (codewords, corrected) = self.corrected_codewords()?;
+        let mut reader = BitReader::new(&codewords, self.format.num_data_bits());
+        let segments = segment::parse_segments(&mut reader, &self.format.header_spec())?;
+        Ok(Message {
+            segments,
+            corrected,
+        })
+    }
+
+    /// Every field of the error corrected data codewords, see [`segment::annotate_bitstream`]
+    pub fn bitstream(&self) -> Result<Bitstream> {
+        let (codewords, _) = self.corrected_codewords()?;
+        Ok(segment::annotate_bitstream(
+            &codewords,
+            self.format.num_data_bits(),
+            &self.format.header_spec(),
+        ))
+    }
+}
+
+fn pack_bits(bits: &[bool]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|chunk| {
+            chunk
+                .iter()
+                .enumerate()
+                .fold(0, |byte, (i, &bit)| byte | (bit as u8) << (7 - i))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_micro_format_round_trip() {
+        let format = MicroFormat {
+            version: 3,
+            ec_level: Some(EcLevel::M),
+            mask: 0b10,
+        };
+        assert_eq!(
+            MicroFormat::decode(format.encode() ^ 0b101),
+            Some((format, 2))
+        );
+        assert_eq!(format.num_data_bits(), 68);
+        assert_eq!(format.num_ec_codewords(), 8);
+    }
+
+    #[test]
+    fn test_version_mismatch_message() {
+        let err = DecodeError::VersionMismatch {
+            from_size: Version::Micro(3),
+            from_info: Version::Micro(2),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Format info says M2, but the symbol size says M3"
+        );
+    }
+
+    #[test]
+    fn test_decode_micro_image() {
+        let img = image::open("test-data/micro-m2.png").unwrap().grayscale();
+        let code = MicroCode::new(&img, None).unwrap();
+        let symbol = code.read_symbol(&img).unwrap();
+        assert_eq!(symbol.format.version, 2);
+        assert_eq!(symbol.decode().unwrap().text(), "01234567");
+    }
+}
@@ -1,22 +1,50 @@
-use std::iter::Enumerate;
+use std::ops::RangeInclusive;
 
-use anyhow::{anyhow, Result};
 use approx::relative_eq;
 use image::GenericImageView;
 
 use crate::{
+    codewords::{self, ModuleBit},
+    encode::{self, Penalty},
+    error::{DecodeError, Result},
+    format::{self, EcLevel, FormatInfo},
     img::{self, ToVert},
+    matrix::ModuleMatrix,
+    micro, rmqr,
+    segment::{self, BitReader, Bitstream, HeaderSpec, Message},
     util::{GridPoint, IteratorExt, Rect},
+    version::{self, MIN_VERSION_WITH_INFO},
     viz::Visualizer,
 };
 
 pub type MaskFn = fn(u32, u32) -> bool;
 
-/// 010 -> black white black
+/// The condition [`get_mask_fn`] inverts a module on, for showing to people
+pub fn mask_formula(mask: u8) -> Option<&'static str> {
+    match mask {
+        0b000 => Some("(x + y) mod 2 = 0"),
+        0b001 => Some("y mod 2 = 0"),
+        0b010 => Some("x mod 3 = 0"),
+        0b011 => Some("(x + y) mod 3 = 0"),
+        0b100 => Some("(y div 2 + x div 3) mod 2 = 0"),
+        0b101 => Some("(x y) mod 2 + (x y) mod 3 = 0"),
+        0b110 => Some("((x y) mod 2 + (x y) mod 3) mod 2 = 0"),
+        0b111 => Some("((x + y) mod 2 + (x y) mod 3) mod 2 = 0"),
+        _ => None,
+    }
+}
+
+/// Mask pattern reference from the format info -> modules to invert, x is the column
 pub fn get_mask_fn(mask: u8) -> Option<MaskFn> {
     match mask {
-        0b000 => Some(|x: u32, _: u32| -> bool { x % 3 == 0 }),
-        0b010 => Some(|x: u32, y: u32| -> bool { (x + y) % 2 == 0 }),
+        0b000 => Some(|x: u32, y: u32| -> bool { (x + y).is_multiple_of(2) }),
+        0b001 => Some(|_: u32, y: u32| -> bool { y.is_multiple_of(2) }),
+        0b010 => Some(|x: u32, _: u32| -> bool { x.is_multiple_of(3) }),
+        0b011 => Some(|x: u32, y: u32| -> bool { (x + y).is_multiple_of(3) }),
+        0b100 => Some(|x: u32, y: u32| -> bool { (y / 2 + x / 3).is_multiple_of(2) }),
+        0b101 => Some(|x: u32, y: u32| -> bool { (x * y) % 2 + (x * y) % 3 == 0 }),
+        0b110 => Some(|x: u32, y: u32| -> bool { ((x * y) % 2 + (x * y) % 3).is_multiple_of(2) }),
+        0b111 => Some(|x: u32, y: u32| -> bool { ((x + y) % 2 + (x * y) % 3).is_multiple_of(2) }),
         _ => None,
     }
 }
@@ -25,6 +53,72 @@ const FINDER_NUM_ELEMS: usize = 7;
 const TIMER_PATTERN_OFFSET: usize = FINDER_NUM_ELEMS - 1;
 const TIMER_PATTERN_START: usize = FINDER_NUM_ELEMS;
 const FORMAT_PATTERN_OFFSET: usize = FINDER_NUM_ELEMS + 1;
+/// Number of timing modules next to a finder used to size the modules inside it
+const TIMING_SIZE_SPAN: usize = 4;
+
+/// The knobs for finding and sampling a [`Code`], the defaults suit clean images
+#[derive(Debug, Clone, PartialEq)]
+pub struct DetectorParams {
+    /// How far each run of a finder can be off its share of the 1:1:3:1:1 ratio,
+    /// relative to that share
+    pub finder_ratio_tolerance: f32,
+    /// Pixels between a horizontal and a vertical finder candidate to count as crossing
+    pub finder_cross_distance: f32,
+    /// Pixels between the centres of crossings to count as the same finder
+    pub finder_merge_distance: f32,
+    /// Pixels lighter than this read as white. [`crate::decode`] binarizes the image
+    /// first, so this only matters for images that are read as they are.
+    pub white_threshold: u8,
+    /// Share of a module's pixels that have to be white for it to read as white
+    pub module_white_ratio: f32,
+    /// How far from where the grid puts it an alignment pattern is searched, in modules
+    pub alignment_search_radius: f32,
+    pub alignment_search_step: f32,
+    /// Number of the 25 modules that have to match for an alignment pattern to be found
+    pub alignment_min_matches: usize,
+    /// Each search can reach patterns further off than the last, from their found neighbours
+    pub max_alignment_searches: usize,
+    /// The QR versions a grid can be sized to, and a symbol can have
+    pub versions: RangeInclusive<u8>,
+    /// The Micro QR versions a symbol can have, 1 (M1) to 4 (M4)
+    pub micro_versions: RangeInclusive<u8>,
+    /// The rMQR version indicators a symbol can have, 0 (R7x43) to 31 (R17x139)
+    pub rmqr_versions: RangeInclusive<u8>,
+    /// A light run this many modules long along the edge of a Micro QR or rMQR
+    /// symbol is the quiet zone after it
+    pub quiet_zone_run: f32,
+    /// How far the measured width of an rMQR symbol can be off the nearest version,
+    /// relative to its width
+    pub rmqr_width_tolerance: f32,
+    /// How far the measured height of an rMQR symbol can be off the nearest version,
+    /// in modules
+    pub rmqr_height_tolerance: f32,
+    /// Also read the symbol transposed when its format info isn't clean
+    pub try_mirrored: bool,
+}
+
+impl Default for DetectorParams {
+    fn default() -> Self {
+        Self {
+            finder_ratio_tolerance: 0.2,
+            finder_cross_distance: 1.0,
+            finder_merge_distance: 10.0,
+            white_threshold: 128,
+            module_white_ratio: 0.5,
+            alignment_search_radius: 2.0,
+            alignment_search_step: 0.25,
+            alignment_min_matches: 23,
+            max_alignment_searches: 4,
+            versions: version::MIN_VERSION..=version::MAX_VERSION,
+            micro_versions: 1..=micro::MAX_VERSION,
+            rmqr_versions: 0..=rmqr::MAX_VERSION,
+            quiet_zone_run: 1.5,
+            rmqr_width_tolerance: 0.1,
+            rmqr_height_tolerance: 1.0,
+            try_mirrored: true,
+        }
+    }
+}
 
 fn find_elem_size(
     timing_x: u32,
@@ -45,7 +139,7 @@ fn find_elem_size(
         timing_x += 1;
     }
 
-    return size / num_elems as f32;
+    size / num_elems as f32
 }
 
 /// check timing patterns, count number of alternating black/white
@@ -55,6 +149,7 @@ fn find_elem_sizes(
     img: &image::DynamicImage,
     finder_width: f32,
     finder_height: f32,
+    white_threshold: u8,
 ) -> (f32, f32) {
     let estimated_elem_width = finder_width / FINDER_NUM_ELEMS as f32;
     let estimated_elem_height = finder_height / FINDER_NUM_ELEMS as f32;
@@ -75,17 +170,17 @@ fn find_elem_sizes(
     let elem_width = find_elem_size(
         timing_col_center_px,
         timing_iter_row_end,
-        |x| img::is_white(&img.get_pixel(x, timing_row_center_px)),
+        |x| img::is_white(&img.get_pixel(x, timing_row_center_px), white_threshold),
         bounds.width(),
     );
     let elem_height = find_elem_size(
         timing_row_center_px,
         timing_iter_col_end,
-        |y| img::is_white(&img.get_pixel(timing_col_center_px, y)),
+        |y| img::is_white(&img.get_pixel(timing_col_center_px, y), white_threshold),
         bounds.height(),
     );
 
-    return (elem_width, elem_height);
+    (elem_width, elem_height)
 }
 
 pub fn idx_to_module(bounds: &Rect, elem_width: f32, elem_height: f32, x: usize, y: usize) -> Rect {
@@ -101,12 +196,151 @@ pub struct Code {
     pub bounds: Rect,
     elem_width: f32,
     elem_height: f32,
-    alignment_positions: Vec<GridPoint>,
+    /// Left edge of every column and the right edge of the last, in pixels
+    col_edges: Vec<f32>,
+    /// Top edge of every row and the bottom edge of the last, in pixels
+    row_edges: Vec<f32>,
+    /// The finder that was missing from the image and placed from the other two
+    pub inferred_finder: Option<Rect>,
+    local_correction: Option<LocalCorrection>,
+    params: DetectorParams,
+}
+
+/// The offset of the found alignment pattern closest to centre `i`, `j`
+fn nearest_offset(
+    found: &[Option<(f32, f32)>],
+    num_centres: usize,
+    i: usize,
+    j: usize,
+) -> (f32, f32) {
+    found
+        .iter()
+        .enumerate()
+        .filter_map(|(idx, offset)| {
+            let (oi, oj) = (idx % num_centres, idx / num_centres);
+            offset.map(|offset| (oi.abs_diff(i) + oj.abs_diff(j), offset))
+        })
+        .min_by_key(|(distance, _)| *distance)
+        .map_or((0.0, 0.0), |(_, offset)| offset)
+}
+
+/// How far, in pixels, the alignment patterns were found from where the grid put
+/// them. Modules in between are shifted by interpolating the nearest four.
+struct LocalCorrection {
+    /// Module index of the alignment pattern centres, the same along either axis
+    centres: Vec<usize>,
+    /// Offset at each pair of centres, row by row
+    offsets: Vec<(f32, f32)>,
+}
+
+impl LocalCorrection {
+    fn offset_at(&self, x: usize, y: usize) -> (f32, f32) {
+        let (i, tx) = self.cell(x);
+        let (j, ty) = self.cell(y);
+        let at = |i: usize, j: usize| self.offsets[j * self.centres.len() + i];
+        let lerp =
+            |a: (f32, f32), b: (f32, f32), t: f32| (a.0 + (b.0 - a.0) * t, a.1 + (b.1 - a.1) * t);
+        let top = lerp(at(i, j), at(i + 1, j), tx);
+        let bottom = lerp(at(i, j + 1), at(i + 1, j + 1), tx);
+        lerp(top, bottom, ty)
+    }
+
+    /// The pair of centres around `idx`, and how far along it is between them.
+    /// Outside the outer centres the offset stays that of the outer one.
+    fn cell(&self, idx: usize) -> (usize, f32) {
+        let last_cell = self.centres.len() - 2;
+        let i = self.centres[1..=last_cell]
+            .iter()
+            .take_while(|&&centre| centre <= idx)
+            .count();
+        let (start, end) = (self.centres[i] as f32, self.centres[i + 1] as f32);
+        (i, ((idx as f32 - start) / (end - start)).clamp(0.0, 1.0))
+    }
+}
+
+/// Positions of the data and ec modules of a symbol, in placement order
+pub fn data_positions(version: u8) -> Vec<(usize, usize)> {
+    let size = version::version_to_size(version);
+    let alignment_patterns = version::alignment_pattern_positions(version);
+    codewords::placement_order(size, size, Some(TIMER_PATTERN_OFFSET), |x, y| {
+        is_function_module(version, &alignment_patterns, x, y)
+    })
+}
+
+/// The finders with their separators and format info, the timing patterns,
+/// the alignment patterns and the version info
+fn is_function_module(version: u8, alignment_patterns: &[GridPoint], x: usize, y: usize) -> bool {
+    let size = version::version_to_size(version);
+    let (near, far) = (FORMAT_PATTERN_OFFSET + 1, size - FORMAT_PATTERN_OFFSET);
+    let in_finder = ((x < near || x >= far) && y < near) || (x < near && y >= far);
+    let in_timing = x == TIMER_PATTERN_OFFSET || y == TIMER_PATTERN_OFFSET;
+    let in_alignment = alignment_patterns.iter().any(|tl| {
+        (tl.x..tl.x + ALIGNMENT_PATTERN_NUM_ELEMS).contains(&x)
+            && (tl.y..tl.y + ALIGNMENT_PATTERN_NUM_ELEMS).contains(&y)
+    });
+    // the 6x3 blocks next to the top right and bottom left finders
+    let in_version_info = version >= MIN_VERSION_WITH_INFO
+        && (((size - 11..size - 8).contains(&x) && y < 6)
+            || ((size - 11..size - 8).contains(&y) && x < 6));
+    in_finder || in_timing || in_alignment || in_version_info
+}
+
+/// Which block, codeword and bit each data module of a symbol holds
+pub fn module_map(version: u8, ec_level: EcLevel) -> Vec<ModuleBit> {
+    let layout = version::block_layout(version, ec_level);
+    codewords::module_map(
+        &data_positions(version),
+        &layout,
+        layout.num_data_codewords() * 8,
+    )
+}
+
+/// The sampled modules of a [`Code`], or the ones built by [`crate::encode::encode`],
+/// with the format and version info read from them
+pub struct Symbol {
+    pub matrix: ModuleMatrix,
+    pub format: FormatInfo,
+    /// Number of bits corrected while decoding the format info
+    pub format_errors: u32,
+    pub version: u8,
+    /// The symbol was mirrored, and `matrix` has been transposed back
+    pub mirrored: bool,
 }
 
 impl Code {
-    pub fn new(img: &image::DynamicImage, mut visualizer: Option<&mut Visualizer>) -> Result<Self> {
-        let finders = find_patterns(&img, visualizer.as_deref_mut())?;
+    /// Finds the finders and places the grid with the default [`DetectorParams`]
+    pub fn new(
+        img: &image::DynamicImage,
+        mut visualizer: Option<&mut dyn Visualizer>,
+    ) -> Result<Self> {
+        let params = DetectorParams::default();
+        let finders = find_patterns(img, &params, visualizer.as_deref_mut())?;
+        Self::from_finders(img, &finders, &params, visualizer)
+    }
+
+    /// Places a uniform grid of `size` modules over `bounds`, for a symbol that was
+    /// located without looking for its finders
+    pub fn from_bounds(bounds: Rect, size: usize, params: &DetectorParams) -> Self {
+        let elem_width = bounds.width() / size as f32;
+        let elem_height = bounds.height() / size as f32;
+        Self {
+            col_edges: uniform_edges(bounds.left(), elem_width, size),
+            row_edges: uniform_edges(bounds.top(), elem_height, size),
+            bounds,
+            elem_width,
+            elem_height,
+            inferred_finder: None,
+            local_correction: None,
+            params: params.clone(),
+        }
+    }
+
+    pub fn from_finders(
+        img: &image::DynamicImage,
+        finders: &[Rect],
+        params: &DetectorParams,
+        mut visualizer: Option<&mut dyn Visualizer>,
+    ) -> Result<Self> {
         let mut finder_width = 0.0;
         let mut finder_height = 0.0;
         let mut top: f32 = f32::MAX;
@@ -114,9 +348,28 @@ impl Code {
         let mut left: f32 = f32::MAX;
         let mut right: f32 = 0.0;
 
-        if finders.len() != 3 {
-            return Err(anyhow!("Expected 3 finders, got {}", finders.len()));
-        }
+        let inferred_finder = match finders {
+            [a, b] => Some(
+                infer_missing_finder(img, a, b, params.white_threshold).ok_or(
+                    DecodeError::FinderNotFound {
+                        expected: 3,
+                        found: 2,
+                    },
+                )?,
+            ),
+            [_, _, _] => None,
+            _ => {
+                return Err(DecodeError::FinderNotFound {
+                    expected: 3,
+                    found: finders.len(),
+                })
+            }
+        };
+        let finders: Vec<Rect> = finders
+            .iter()
+            .chain(inferred_finder.iter())
+            .cloned()
+            .collect();
 
         for finder_rect in finders.iter() {
             if let Some(vis) = visualizer.as_deref_mut() {
@@ -134,84 +387,522 @@ impl Code {
 
         let qr_rect = Rect::from_corners(left, top, right, bottom);
 
-        let (elem_width, elem_height) = find_elem_sizes(&qr_rect, img, finder_width, finder_height);
-
-        let alignment_positions =
-            AlignmentPatternIter::new(elem_width, elem_height, qr_rect.clone(), img)
-                .map(|pattern| GridPoint {
-                    x: pattern.x,
-                    y: pattern.y,
-                })
-                .collect();
+        let (mut elem_width, mut elem_height) = find_elem_sizes(
+            &qr_rect,
+            img,
+            finder_width,
+            finder_height,
+            params.white_threshold,
+        );
+
+        // the timing patterns run into the missing finder, so their count can be
+        // off by one, snap to the nearest valid size instead
+        if inferred_finder.is_some() {
+            let estimated_size =
+                (qr_rect.width() / elem_width + qr_rect.height() / elem_height) / 2.0;
+            let version = ((estimated_size - version::version_to_size(0) as f32) / 4.0)
+                .round()
+                .clamp(
+                    *params.versions.start() as f32,
+                    *params.versions.end() as f32,
+                );
+            let size = version::version_to_size(version as u8) as f32;
+            elem_width = qr_rect.width() / size;
+            elem_height = qr_rect.height() / size;
+        }
 
-        Ok(Self {
+        let num_cols = (qr_rect.width() / elem_width).round() as usize;
+        let num_rows = (qr_rect.height() / elem_height).round() as usize;
+        let col_edges = uniform_edges(qr_rect.left(), elem_width, num_cols);
+        let row_edges = uniform_edges(qr_rect.top(), elem_height, num_rows);
+        let mut code = Self {
             bounds: qr_rect,
             elem_width,
             elem_height,
-            alignment_positions,
-        })
+            col_edges,
+            row_edges,
+            inferred_finder,
+            local_correction: None,
+            params: params.clone(),
+        };
+        code.refine_grid(img, &finders);
+        code.correct_locally(img);
+        Ok(code)
+    }
+
+    /// Looks for the alignment patterns around where the grid puts them, and shifts
+    /// the modules around each by the offset it was found at. This follows distortion
+    /// that the timing patterns along the top and left edges can't see. Patterns
+    /// that weren't found take the offset of the nearest found one (or a finder, which
+    /// is in place), and the search is repeated from there while that finds more.
+    fn correct_locally(&mut self, img: &image::DynamicImage) {
+        let Some(version) = version::size_to_version(self.num_horiz_elems()) else {
+            return;
+        };
+        let centres = version::alignment_pattern_centres(version);
+        if centres.is_empty() {
+            return;
+        }
+
+        let num_centres = centres.len();
+        let last = num_centres - 1;
+        // the top left, top right and bottom left corners of the centre grid
+        let is_finder = |i: usize, j: usize| [(0, 0), (last, 0), (0, last)].contains(&(i, j));
+        let centre_offset = ALIGNMENT_PATTERN_NUM_ELEMS / 2;
+        let mut found: Vec<Option<(f32, f32)>> = (0..num_centres * num_centres)
+            .map(|idx| is_finder(idx % num_centres, idx / num_centres).then_some((0.0, 0.0)))
+            .collect();
+        let mut num_found = 0;
+
+        for _ in 0..self.params.max_alignment_searches {
+            let patterns: Vec<AlignmentPattern> =
+                AlignmentPatternIter::for_code(self, img).collect();
+            for pattern in patterns.iter() {
+                let i = centres.iter().position(|&c| c == pattern.x + centre_offset);
+                let j = centres.iter().position(|&c| c == pattern.y + centre_offset);
+                if let (Some(i), Some(j)) = (i, j) {
+                    let expected = self.grid_module(centres[i], centres[j]);
+                    found[j * num_centres + i] = Some((
+                        pattern.module.cx() - expected.cx(),
+                        pattern.module.cy() - expected.cy(),
+                    ));
+                }
+            }
+
+            let offsets = (0..num_centres * num_centres)
+                .map(|idx| {
+                    let (i, j) = (idx % num_centres, idx / num_centres);
+                    found[idx].unwrap_or_else(|| nearest_offset(&found, num_centres, i, j))
+                })
+                .collect();
+            self.local_correction = Some(LocalCorrection {
+                centres: centres.clone(),
+                offsets,
+            });
+
+            if patterns.len() == num_found {
+                break;
+            }
+            num_found = patterns.len();
+        }
     }
 
-    #[allow(dead_code)]
-    pub fn horiz_timing_iter(&self) -> HorizTimingIter {
+    /// Places the module edges between the finders on the transitions of the timing
+    /// patterns, so scaling that isn't uniform across the symbol doesn't add up
+    /// towards the far edge. The grid is only replaced when both timing patterns
+    /// agree on a valid size.
+    fn refine_grid(&mut self, img: &image::DynamicImage, finders: &[Rect]) {
+        let closest_to = |x: f32, y: f32| {
+            finders
+                .iter()
+                .min_by(|a, b| {
+                    let distance = |f: &Rect| (f.cx() - x).powi(2) + (f.cy() - y).powi(2);
+                    distance(a).total_cmp(&distance(b))
+                })
+                .expect("There are finders")
+        };
+        let (left, top, right, bottom) = self.bounds.to_corners();
+        let top_left = closest_to(left, top);
+        let top_right = closest_to(right, top);
+        let bottom_left = closest_to(left, bottom);
+
+        // the timing patterns are in line with the inner row and column of the top left finder
+        let inner_offset = (FINDER_NUM_ELEMS as f32 - 0.5) / FINDER_NUM_ELEMS as f32;
+        let timing_row_px = (top_left.top() + inner_offset * top_left.height()) as u32;
+        let timing_col_px = (top_left.left() + inner_offset * top_left.width()) as u32;
+
+        // the finders are solid along those, so scanning between their centres
+        // doesn't depend on how precisely their edges were found
+        let horiz_transitions =
+            timing_transitions(top_left.cx() as u32, top_right.cx() as u32, |x| {
+                img::is_white(
+                    &img.get_pixel(x, timing_row_px),
+                    self.params.white_threshold,
+                )
+            });
+        let vert_transitions =
+            timing_transitions(top_left.cy() as u32, bottom_left.cy() as u32, |y| {
+                img::is_white(
+                    &img.get_pixel(timing_col_px, y),
+                    self.params.white_threshold,
+                )
+            });
+
+        if horiz_transitions.len() != vert_transitions.len() {
+            return;
+        }
+        let size = horiz_transitions.len() + 2 * FINDER_NUM_ELEMS - 1;
+        if !version::size_to_version(size).is_some_and(|v| self.params.versions.contains(&v)) {
+            return;
+        }
+        self.col_edges = refined_edges(&horiz_transitions);
+        self.row_edges = refined_edges(&vert_transitions);
+        self.elem_width = self.bounds.width() / size as f32;
+        self.elem_height = self.bounds.height() / size as f32;
+    }
+
+    pub fn horiz_timing_iter(&self) -> HorizTimingIter<'_> {
         HorizTimingIter::new(self)
     }
-    #[allow(dead_code)]
-    pub fn vert_timing_iter(&self) -> VertTimingIter {
+    pub fn vert_timing_iter(&self) -> VertTimingIter<'_> {
         VertTimingIter::new(self)
     }
-    #[allow(dead_code)]
-    pub fn horiz_format_iter(&self) -> HorizFormatIter {
+    pub fn horiz_format_iter(&self) -> HorizFormatIter<'_> {
         HorizFormatIter::new(self)
     }
-    #[allow(dead_code)]
-    pub fn vert_format_iter(&self) -> VertFormatIter {
+    pub fn vert_format_iter(&self) -> VertFormatIter<'_> {
         VertFormatIter::new(self)
     }
-    #[allow(dead_code)]
-    pub fn bit_iter<'a>(&'a self, img: &'a image::DynamicImage) -> Result<DataBitIter> {
-        let mut mask_val = 0;
-        for (i, module) in self
-            .horiz_format_iter()
-            .skip(2)
-            .take_or_err(3)?
-            .iter()
+
+    /// Checks that both timing patterns alternate, starting from the white separator
+    pub fn check_timing(&self, img: &image::DynamicImage) -> Result<()> {
+        let horiz = self
+            .horiz_timing_iter()
+            .enumerate()
+            .map(|(i, module)| (TIMER_PATTERN_START + i, TIMER_PATTERN_OFFSET, i, module));
+        let vert = self
+            .vert_timing_iter()
             .enumerate()
-        {
-            let bit = img::is_white_module(img, &module);
-            if bit {
-                mask_val |= 1 << (i);
+            .map(|(i, module)| (TIMER_PATTERN_OFFSET, TIMER_PATTERN_START + i, i, module));
+        for (x, y, i, module) in horiz.chain(vert) {
+            if self.is_white_module(img, &module) != i.is_multiple_of(2) {
+                return Err(DecodeError::TimingMismatch { x, y });
             }
         }
-        let mask_fn = get_mask_fn(mask_val).ok_or(anyhow!("No mask fn found {mask_val:#05b}"))?;
+        Ok(())
+    }
 
-        Ok(DataBitIter::new(
-            self,
-            mask_fn,
-            img,
-            &self.alignment_positions,
-        ))
+    /// Samples every module of the grid from the image
+    pub fn sample(&self, img: &image::DynamicImage) -> ModuleMatrix {
+        let mut matrix = ModuleMatrix::new(self.num_horiz_elems(), self.num_vert_elems());
+        for y in 0..matrix.height() {
+            for x in 0..matrix.width() {
+                let white_ratio =
+                    img::white_ratio(img, &self.idx_to_module(x, y), self.params.white_threshold);
+                matrix.set_sampled(x, y, white_ratio, self.params.module_white_ratio);
+            }
+        }
+        matrix
+    }
+
+    pub fn is_white_module(&self, img: &image::DynamicImage, module: &Rect) -> bool {
+        img::white_ratio(img, module, self.params.white_threshold) > self.params.module_white_ratio
+    }
+
+    /// Samples the grid and reads its format and version info. If those can't be
+    /// decoded cleanly, the transposed matrix is tried too, in case the symbol is
+    /// mirrored, and the orientation needing fewer format corrections wins.
+    pub fn read_symbol(&self, img: &image::DynamicImage) -> Result<Symbol> {
+        self.read_matrix(self.sample(img))
     }
 
-    #[allow(dead_code)]
-    pub fn data_iter<'a>(&'a self, img: &'a image::DynamicImage) -> Result<DataByteIter> {
-        DataByteIter::new(self.bit_iter(img)?)
+    /// Reads the format and version info from modules that were sampled some other
+    /// way than [`Code::sample`], as [`Code::read_symbol`] does
+    pub fn read_matrix(&self, matrix: ModuleMatrix) -> Result<Symbol> {
+        let symbol = Symbol::from_matrix(matrix, self.params.try_mirrored)
+            .and_then(|symbol| self.check_version(symbol));
+        match (symbol, &self.inferred_finder) {
+            (Err(err), Some(finder)) => Err(DecodeError::InferredFinderMisplaced {
+                x: finder.cx(),
+                y: finder.cy(),
+                source: Box::new(err),
+            }),
+            (symbol, _) => symbol,
+        }
+    }
+
+    fn check_version(&self, symbol: Symbol) -> Result<Symbol> {
+        if !self.params.versions.contains(&symbol.version) {
+            return Err(DecodeError::VersionOutOfRange {
+                version: symbol.version,
+                min: *self.params.versions.start(),
+                max: *self.params.versions.end(),
+            });
+        }
+        Ok(symbol)
     }
 
-    #[allow(dead_code)]
     pub fn idx_to_module(&self, x: usize, y: usize) -> Rect {
-        idx_to_module(&self.bounds, self.elem_width, self.elem_height, x, y)
+        let mut module = self.grid_module(x, y);
+        if let Some(correction) = &self.local_correction {
+            let (dx, dy) = correction.offset_at(x, y);
+            module.left += dx;
+            module.right += dx;
+            module.top += dy;
+            module.bottom += dy;
+        }
+        module
+    }
+
+    /// The module as placed by the timing patterns, before local correction
+    fn grid_module(&self, x: usize, y: usize) -> Rect {
+        Rect::from_corners(
+            self.col_edges[x],
+            self.row_edges[y],
+            self.col_edges[x + 1],
+            self.row_edges[y + 1],
+        )
     }
 
-    #[allow(dead_code)]
     pub fn num_horiz_elems(&self) -> usize {
-        (self.bounds.width() / self.elem_width) as usize
+        self.col_edges.len() - 1
     }
 
-    #[allow(dead_code)]
     pub fn num_vert_elems(&self) -> usize {
-        (self.bounds.height() / self.elem_height) as usize
+        self.row_edges.len() - 1
+    }
+}
+
+impl Symbol {
+    /// Reads the format and version info of modules from anywhere, sampled from an
+    /// image or built by hand. With `try_mirrored`, the transposed matrix is read too
+    /// when the format info isn't clean, and the orientation needing fewer format
+    /// corrections wins.
+    pub fn from_matrix(matrix: ModuleMatrix, try_mirrored: bool) -> Result<Self> {
+        let symbol = Symbol::new(matrix.clone(), false);
+        if !try_mirrored || matches!(symbol, Ok(ref symbol) if symbol.format_errors == 0) {
+            return symbol;
+        }
+
+        // a mirrored format word reads bit-reversed, which can still be within
+        // correction distance of a valid word, so a successful decode isn't enough
+        match (symbol, Symbol::new(matrix.transpose(), true)) {
+            (Ok(symbol), Ok(mirrored)) if mirrored.format_errors < symbol.format_errors => {
+                Ok(mirrored)
+            }
+            (Ok(symbol), _) => Ok(symbol),
+            (Err(_), Ok(mirrored)) => Ok(mirrored),
+            (Err(err), Err(_)) => Err(err),
+        }
+    }
+
+    fn new(matrix: ModuleMatrix, mirrored: bool) -> Result<Self> {
+        let (format, format_errors) = format::read_format_info(&matrix)?;
+        let version = format::read_version(&matrix)?;
+        Ok(Self {
+            matrix,
+            format,
+            format_errors,
+            version,
+            mirrored,
+        })
+    }
+
+    /// Scores the mask this symbol was generated with, which may not be the best one
+    pub fn penalty(&self) -> Penalty {
+        encode::penalty(&self.matrix)
+    }
+
+    /// Maps a position in `matrix` back to the grid sampled from the image
+    pub fn grid_position(&self, x: usize, y: usize) -> (usize, usize) {
+        if self.mirrored {
+            (y, x)
+        } else {
+            (x, y)
+        }
+    }
+
+    pub fn bit_iter(&self) -> Result<DataBitIter<'_>> {
+        let mask_fn = get_mask_fn(self.format.mask).ok_or(DecodeError::InvalidMask {
+            mask: self.format.mask,
+        })?;
+
+        Ok(DataBitIter::new(&self.matrix, mask_fn, self.version))
+    }
+
+    pub fn data_iter(&self) -> Result<DataByteIter<'_>> {
+        Ok(DataByteIter::new(self.bit_iter()?))
+    }
+
+    /// The error corrected data codewords, and how many codewords were corrected
+    fn data_codewords(&self) -> Result<(Vec<u8>, usize)> {
+        let codewords: Vec<u8> = self.data_iter()?.collect();
+        version::block_layout(self.version, self.format.ec_level).correct(&codewords)
+    }
+
+    /// Deinterleaves and error corrects the codewords, then parses the segments
+    pub fn decode(&self) -> Result<Message> {
+        let (data, corrected) = self.data_codewords()?;
+        let mut reader = BitReader::new(&data, data.len() * 8);
+        let segments = segment::parse_segments(&mut reader, &HeaderSpec::qr(self.version))?;
+        Ok(Message {
+            segments,
+            corrected,
+        })
+    }
+
+    /// Every field of the error corrected data codewords, see [`segment::annotate_bitstream`]
+    pub fn bitstream(&self) -> Result<Bitstream> {
+        let (data, _) = self.data_codewords()?;
+        Ok(segment::annotate_bitstream(
+            &data,
+            data.len() * 8,
+            &HeaderSpec::qr(self.version),
+        ))
+    }
+}
+
+fn uniform_edges(start: f32, elem_size: f32, num_elems: usize) -> Vec<f32> {
+    (0..=num_elems)
+        .map(|i| start + i as f32 * elem_size)
+        .collect()
+}
+
+/// Pixel positions where the colour changes, scanning from `start` to `end`
+fn timing_transitions(start: u32, end: u32, get_pix_val: impl Fn(u32) -> bool) -> Vec<f32> {
+    (start + 1..end)
+        .filter(|&px| get_pix_val(px) != get_pix_val(px - 1))
+        .map(|px| px as f32)
+        .collect()
+}
+
+/// Scanning along a timing pattern from one finder to the other crosses every module
+/// edge from the separator to the far finder. Those edges are taken from
+/// `transitions`, the ones inside the finders continue with the module size of the
+/// nearest timing modules, as the measured finder bounds are less precise.
+fn refined_edges(transitions: &[f32]) -> Vec<f32> {
+    let n = transitions.len() - 1;
+    let span = TIMING_SIZE_SPAN.min(n);
+    let first_size = (transitions[span] - transitions[0]) / span as f32;
+    let last_size = (transitions[n] - transitions[n - span]) / span as f32;
+
+    let before = (1..=FINDER_NUM_ELEMS)
+        .rev()
+        .map(|i| transitions[0] - i as f32 * first_size);
+    let after = (1..=FINDER_NUM_ELEMS).map(|i| transitions[n] + i as f32 * last_size);
+    before
+        .chain(transitions.iter().copied())
+        .chain(after)
+        .collect()
+}
+
+/// Places the third finder from the other two. In a pair along one side of the
+/// symbol either one can be the corner finder, with the third on either side of
+/// it, and a diagonal pair leaves either of the other two corners. The timing
+/// patterns run from the corner finder to the other two, so the placement where
+/// both of them alternate is taken, or the one of an upright symbol if none fits.
+/// Placements that put the finder outside the image are left out, None if that is
+/// all of them.
+fn infer_missing_finder(
+    img: &image::DynamicImage,
+    a: &Rect,
+    b: &Rect,
+    white_threshold: u8,
+) -> Option<Rect> {
+    let width = (a.width() + b.width()) / 2.0;
+    let height = (a.height() + b.height()) / 2.0;
+    // modules aren't always square in pixels
+    let aspect = height / width;
+    let dx = (b.cx() - a.cx()).abs();
+    let dy = (b.cy() - a.cy()).abs();
+    let at = |cx: f32, cy: f32| Rect::from_center_and_size(cx, cy, width, height);
+
+    // (corner finder, missing finder), upright first
+    let candidates: Vec<(Rect, Rect)> = if dy < height / 2.0 {
+        let (left, right) = if a.cx() < b.cx() { (a, b) } else { (b, a) };
+        let side = dx * aspect;
+        vec![
+            (left.clone(), at(left.cx(), left.cy() + side)),
+            (left.clone(), at(left.cx(), left.cy() - side)),
+            (right.clone(), at(right.cx(), right.cy() + side)),
+            (right.clone(), at(right.cx(), right.cy() - side)),
+        ]
+    } else if dx < width / 2.0 {
+        let (top, bottom) = if a.cy() < b.cy() { (a, b) } else { (b, a) };
+        let side = dy / aspect;
+        vec![
+            (top.clone(), at(top.cx() + side, top.cy())),
+            (top.clone(), at(top.cx() - side, top.cy())),
+            (bottom.clone(), at(bottom.cx() + side, bottom.cy())),
+            (bottom.clone(), at(bottom.cx() - side, bottom.cy())),
+        ]
+    } else {
+        let (top, bottom) = if a.cy() < b.cy() { (a, b) } else { (b, a) };
+        [at(bottom.cx(), top.cy()), at(top.cx(), bottom.cy())]
+            .into_iter()
+            .map(|corner| (corner.clone(), corner))
+            .collect()
+    };
+    let (img_width, img_height) = img.dimensions();
+    let candidates: Vec<(Rect, Rect)> = candidates
+        .into_iter()
+        .filter(|(_, missing)| {
+            missing.left() >= 0.0
+                && missing.top() >= 0.0
+                && missing.right() <= img_width as f32
+                && missing.bottom() <= img_height as f32
+        })
+        .collect();
+
+    let fits = |(corner, missing): &(Rect, Rect)| {
+        let others: Vec<&Rect> = [a, b, missing]
+            .into_iter()
+            .filter(|&finder| finder != corner)
+            .collect();
+        match others[..] {
+            [first, second] => {
+                timing_alternates(img, corner, first, second, white_threshold)
+                    && timing_alternates(img, corner, second, first, white_threshold)
+            }
+            _ => false,
+        }
+    };
+    let index = candidates.iter().position(fits).unwrap_or(0);
+    candidates
+        .into_iter()
+        .nth(index)
+        .map(|(_, missing)| missing)
+}
+
+/// Whether the timing pattern from the `corner` finder to `other` alternates with
+/// every module. It runs along the inner row or column of the corner finder, the
+/// one towards `third`. The far end may be missing, as when `other` was inferred.
+fn timing_alternates(
+    img: &image::DynamicImage,
+    corner: &Rect,
+    other: &Rect,
+    third: &Rect,
+    white_threshold: u8,
+) -> bool {
+    let inner_offset = (FINDER_NUM_ELEMS / 2) as f32;
+    let horizontal = (other.cx() - corner.cx()).abs() > (other.cy() - corner.cy()).abs();
+    let (start, end, line, module_size) = if horizontal {
+        let module_height = corner.height() / FINDER_NUM_ELEMS as f32;
+        let y = corner.cy() + inner_offset * module_height * (third.cy() - corner.cy()).signum();
+        let (start, end) = (corner.cx().min(other.cx()), corner.cx().max(other.cx()));
+        (start, end, y, corner.width() / FINDER_NUM_ELEMS as f32)
+    } else {
+        let module_width = corner.width() / FINDER_NUM_ELEMS as f32;
+        let x = corner.cx() + inner_offset * module_width * (third.cx() - corner.cx()).signum();
+        let (start, end) = (corner.cy().min(other.cy()), corner.cy().max(other.cy()));
+        (start, end, x, corner.height() / FINDER_NUM_ELEMS as f32)
+    };
+    let (width, height) = img.dimensions();
+    let (line_len, extent) = if horizontal {
+        (width, height)
+    } else {
+        (height, width)
+    };
+    if start < 0.0 || end >= line_len as f32 || line < 0.0 || line >= extent as f32 {
+        return false;
     }
+
+    let transitions = timing_transitions(start as u32, end as u32, |px| {
+        let (x, y) = if horizontal {
+            (px, line as u32)
+        } else {
+            (line as u32, px)
+        };
+        img::is_white(&img.get_pixel(x, y), white_threshold)
+    });
+    // from the separator after the corner finder to the one before the other finder
+    let expected =
+        (((end - start) / module_size).round() as usize).saturating_sub(FINDER_NUM_ELEMS - 1);
+    expected > 0
+        && (expected - 1..=expected).contains(&transitions.len())
+        && transitions
+            .windows(2)
+            .all(|pair| ((pair[1] - pair[0]) / module_size - 1.0).abs() < 0.5)
 }
 
 pub struct HorizTimingIter<'a> {
@@ -219,7 +910,6 @@ pub struct HorizTimingIter<'a> {
     x: usize,
 }
 impl<'a> HorizTimingIter<'a> {
-    #[allow(dead_code)]
     fn new(code: &'a Code) -> Self {
         Self {
             code,
@@ -245,7 +935,6 @@ pub struct VertTimingIter<'a> {
     y: usize,
 }
 impl<'a> VertTimingIter<'a> {
-    #[allow(dead_code)]
     fn new(code: &'a Code) -> Self {
         Self {
             code,
@@ -290,7 +979,7 @@ impl Iterator for HorizFormatIter<'_> {
             self.x += 1;
             let second_half_start = self.code.num_horiz_elems() - FINDER_NUM_ELEMS - 1;
 
-            if self.x >= FINDER_NUM_ELEMS + 1 && self.x < second_half_start {
+            if self.x > FINDER_NUM_ELEMS && self.x < second_half_start {
                 self.x = second_half_start;
             }
 
@@ -342,212 +1031,24 @@ impl Iterator for VertFormatIter<'_> {
 
 /// black is 1, white is 0 (different from masks)
 pub struct DataBitIter<'a> {
-    code: &'a Code,
-    x: isize,
-    y: isize,
-    moving_vertically: bool,
-    movement_direction: isize,
+    matrix: &'a ModuleMatrix,
+    positions: std::vec::IntoIter<(usize, usize)>,
     mask_fn: MaskFn,
-    img: &'a image::DynamicImage,
-    alignment_patterns: &'a [GridPoint],
 }
 
 impl<'a> DataBitIter<'a> {
-    fn new(
-        code: &'a Code,
-        mask_fn: MaskFn,
-        img: &'a image::DynamicImage,
-        alignment_patterns: &'a [GridPoint],
-    ) -> Self {
+    fn new(matrix: &'a ModuleMatrix, mask_fn: MaskFn, version: u8) -> Self {
         Self {
-            code,
-            x: code.num_horiz_elems() as isize - 2,
-            y: code.num_vert_elems() as isize,
-            moving_vertically: true,
-            movement_direction: -1,
+            matrix,
+            positions: data_positions(version).into_iter(),
             mask_fn,
-            img,
-            alignment_patterns,
         }
     }
-
-    fn do_zig_zag(&mut self) -> bool {
-        if self.moving_vertically {
-            self.x += 1;
-            self.y += self.movement_direction as isize;
-        } else {
-            self.x -= 1;
-        }
-
-        self.moving_vertically = !self.moving_vertically;
-
-        return !self.moving_vertically;
-    }
-
-    fn do_continue(&mut self, last_move_vertical: bool) {
-        if last_move_vertical {
-            self.y += self.movement_direction;
-        } else {
-            self.x -= 1;
-        }
-    }
-
-    fn do_turn_around(&mut self) {
-        self.x -= 2;
-        self.y -= self.movement_direction;
-        self.movement_direction *= -1;
-        self.moving_vertically = false;
-    }
-}
-
-enum IterationAction {
-    ContinueZigZag,
-    ContinueStraight,
-    TurnAround,
-    Finish,
-    Yield,
-}
-
-struct IterationActionMachine<'a> {
-    x: isize,
-    y: isize,
-    #[allow(dead_code)]
-    width: isize,
-    height: isize,
-
-    x_right_of_right_finder: bool,
-    y_above_top_finder: bool,
-    x_left_of_left_finder: bool,
-    y_below_bottom_finder: bool,
-    alignment_patterns: &'a [GridPoint],
-}
-
-impl<'a> IterationActionMachine<'a> {
-    fn new(iter: &'a DataBitIter) -> Self {
-        let x = iter.x;
-        let y = iter.y;
-        let finder_num_elems = FINDER_NUM_ELEMS as isize;
-        let width = iter.code.num_horiz_elems() as isize;
-        let height = iter.code.num_vert_elems() as isize;
-
-        let y_above_top_finder = y < finder_num_elems + 2;
-        let x_right_of_right_finder = x > width - finder_num_elems - 1;
-        let x_left_of_left_finder = x < finder_num_elems + 2;
-        let y_below_bottom_finder = y >= height - finder_num_elems - 1;
-
-        return Self {
-            x,
-            y,
-            width,
-            height,
-            x_right_of_right_finder,
-            y_above_top_finder,
-            x_left_of_left_finder,
-            y_below_bottom_finder,
-            alignment_patterns: iter.alignment_patterns,
-        };
-    }
-    fn is_finished(&self) -> bool {
-        self.x < 0
-    }
-    fn is_out_of_bounds_y(&self) -> bool {
-        self.y >= self.height || self.y < 0
-    }
-    fn in_tr_finder(&self) -> bool {
-        self.x_right_of_right_finder && self.y_above_top_finder
-    }
-    fn in_tl_finder(&self) -> bool {
-        self.x_left_of_left_finder && self.y_above_top_finder
-    }
-    fn in_bl_finder(&self) -> bool {
-        self.x_left_of_left_finder && self.y_below_bottom_finder
-    }
-    fn is_in_timing_pattern(&self) -> bool {
-        self.x == TIMER_PATTERN_OFFSET as isize || self.y == TIMER_PATTERN_OFFSET as isize
-    }
-    fn is_in_alignment_pattern(&self) -> bool {
-        for tl in self.alignment_patterns {
-            let x_in_alignment =
-                self.x >= tl.x as isize && self.x < (tl.x + ALIGNMENT_PATTERN_NUM_ELEMS) as isize;
-            let y_in_alignment =
-                self.y >= tl.y as isize && self.y < (tl.y + ALIGNMENT_PATTERN_NUM_ELEMS) as isize;
-
-            if x_in_alignment && y_in_alignment {
-                return true;
-            }
-        }
-        return false;
-    }
-}
-
-impl DataBitIter<'_> {
-    fn update_pos(&mut self) -> bool {
-        let mut last_move_vert = self.do_zig_zag();
-        loop {
-            let next_action = self.check_next_action();
-            match next_action {
-                IterationAction::ContinueZigZag => last_move_vert = self.do_zig_zag(),
-                IterationAction::ContinueStraight => self.do_continue(last_move_vert),
-                IterationAction::TurnAround => {
-                    self.do_turn_around();
-                    last_move_vert = false;
-                }
-                IterationAction::Finish => return true,
-                IterationAction::Yield => return false,
-            }
-        }
-    }
-
-    fn check_next_action(&self) -> IterationAction {
-        let action_machine = IterationActionMachine::new(self);
-
-        if action_machine.is_finished() {
-            return IterationAction::Finish;
-        }
-
-        if action_machine.is_out_of_bounds_y() {
-            return IterationAction::TurnAround;
-        }
-
-        if action_machine.in_tr_finder() {
-            return IterationAction::TurnAround;
-        }
-
-        if action_machine.in_tl_finder() {
-            if self.movement_direction == -1 {
-                return IterationAction::TurnAround;
-            } else {
-                return IterationAction::ContinueZigZag;
-            }
-        }
-
-        if action_machine.in_bl_finder() {
-            if self.movement_direction == 1 {
-                return IterationAction::TurnAround;
-            } else {
-                return IterationAction::ContinueZigZag;
-            }
-        }
-
-        if action_machine.is_in_timing_pattern() {
-            return IterationAction::ContinueStraight;
-        }
-
-        if action_machine.is_in_alignment_pattern() {
-            return IterationAction::ContinueZigZag;
-        }
-
-        return IterationAction::Yield;
-    }
 }
 
 pub struct Output {
-    #[allow(dead_code)]
-    pub module: Rect,
     pub bit: bool,
-    #[allow(dead_code)]
     pub x: isize,
-    #[allow(dead_code)]
     pub y: isize,
 }
 
@@ -555,57 +1056,24 @@ impl Iterator for DataBitIter<'_> {
     type Item = Output;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.update_pos() {
-            return None;
-        }
-
-        let module = self.code.idx_to_module(self.x as usize, self.y as usize);
-        let is_dark = !img::is_white_module(self.img, &module);
-        return Some(Output {
-            module,
-            x: self.x,
-            y: self.y,
-            bit: is_dark != (self.mask_fn)(self.x as u32, self.y as u32),
-        });
+        let (x, y) = self.positions.next()?;
+        let is_dark = self.matrix.get(x, y);
+        Some(Output {
+            x: x as isize,
+            y: y as isize,
+            bit: is_dark != (self.mask_fn)(x as u32, y as u32),
+        })
     }
 }
 
+/// Groups the data bits into codewords, in placement order (blocks are still interleaved)
 pub struct DataByteIter<'a> {
-    iter: Enumerate<DataBitIter<'a>>,
-    #[allow(dead_code)]
-    pub encoding: u8,
-    #[allow(dead_code)]
-    pub length: u8,
-    num_bytes_read: u8,
+    iter: DataBitIter<'a>,
 }
-const NUM_ENCODING_BITS: usize = 4;
-const NUM_LENGTH_BITS: usize = 8;
 
 impl<'a> DataByteIter<'a> {
-    fn new(iter: DataBitIter<'a>) -> Result<Self> {
-        let mut iter = iter.enumerate();
-        let mut encoding: u8 = 0b0000;
-        for (i, item) in iter.take_or_err(NUM_ENCODING_BITS)?.iter() {
-            let bit = item.bit;
-            if bit {
-                encoding |= 1 << (NUM_ENCODING_BITS - 1 - i);
-            }
-        }
-
-        let mut length: u8 = 0b0000;
-        for (i, item) in iter.take_or_err(NUM_LENGTH_BITS)?.iter() {
-            let bit = item.bit;
-            if bit {
-                length |= 1 << (NUM_LENGTH_BITS + NUM_ENCODING_BITS - 1 - i);
-            }
-        }
-
-        Ok(Self {
-            iter,
-            encoding,
-            length,
-            num_bytes_read: 0,
-        })
+    fn new(iter: DataBitIter<'a>) -> Self {
+        Self { iter }
     }
 }
 
@@ -613,90 +1081,109 @@ impl Iterator for DataByteIter<'_> {
     type Item = u8;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.num_bytes_read >= self.length {
-            return None;
-        }
-        if let Ok(i) = self.iter.take_or_err(8) {
-            let mut next_byte: u8 = 0;
-            for (i, output) in i.iter() {
-                let bit_idx = (i - NUM_ENCODING_BITS - NUM_LENGTH_BITS) % 8;
-                if output.bit {
-                    next_byte |= 1 << (7 - bit_idx);
-                }
-
-                if bit_idx == 7 {
-                    let ret = next_byte;
-                    self.num_bytes_read += 1;
-                    return Some(ret);
-                }
-            }
-        }
-
-        return None;
+        // the remainder bits at the end don't make up a full codeword
+        let bits = self.iter.take_or_err(8).ok()?;
+        Some(
+            bits.iter()
+                .fold(0, |byte, output| (byte << 1) | output.bit as u8),
+        )
     }
 }
 
 const ALIGNMENT_PATTERN_NUM_ELEMS: usize = 5;
 
+/// Searches for the alignment patterns of a code around the positions given by its
+/// version, and yields the ones that were found
 pub struct AlignmentPatternIter<'a> {
-    elem_width: f32,
-    elem_height: f32,
-    grid_width: usize,
-    grid_height: usize,
-
-    qr_bounds: Rect,
-
+    code: &'a Code,
     img: &'a image::DynamicImage,
-
-    // top, left
-    x: usize,
-    y: usize,
+    positions: std::vec::IntoIter<GridPoint>,
 }
 
 impl<'a> AlignmentPatternIter<'a> {
     pub fn for_code(code: &'a Code, img: &'a image::DynamicImage) -> AlignmentPatternIter<'a> {
-        AlignmentPatternIter::new(code.elem_width, code.elem_height, code.bounds.clone(), img)
-    }
-    pub fn new(
-        elem_width: f32,
-        elem_height: f32,
-        qr_bounds: Rect,
-        img: &'a image::DynamicImage,
-    ) -> Self {
-        let grid_width = (qr_bounds.width() / elem_width) as usize;
-        let grid_height = (qr_bounds.height() / elem_height) as usize;
+        let positions = version::size_to_version(code.num_horiz_elems())
+            .filter(|_| code.num_horiz_elems() == code.num_vert_elems())
+            .map(version::alignment_pattern_positions)
+            .unwrap_or_default();
         Self {
-            elem_width,
-            elem_height,
-            grid_width,
-            grid_height,
-            qr_bounds,
+            code,
             img,
-            x: 0,
-            y: 0,
+            positions: positions.into_iter(),
         }
     }
 
-    fn is_alignment_element(&self) -> bool {
-        for y_off in 0..ALIGNMENT_PATTERN_NUM_ELEMS {
-            for x_off in 0..ALIGNMENT_PATTERN_NUM_ELEMS {
-                let x = self.x + x_off;
-                let y = self.y + y_off;
-                let is_white = img::is_white_module(
-                    self.img,
-                    &idx_to_module(&self.qr_bounds, self.elem_width, self.elem_height, x, y),
-                );
-                let should_be_white = ((y_off == 1 || y_off == 3)
-                    && (x_off > 0 && x_off < ALIGNMENT_PATTERN_NUM_ELEMS - 1))
-                    || ((x_off == 1 || x_off == 3)
-                        && (y_off > 0 && y_off < ALIGNMENT_PATTERN_NUM_ELEMS - 1));
-
-                if is_white != should_be_white {
+    /// Number of modules of the pattern at `top_left` with the expected colour,
+    /// sampled at their centres shifted by `dx`, `dy` modules
+    fn count_matches(&self, top_left: &GridPoint, dx: f32, dy: f32) -> usize {
+        let (width, height) = self.img.dimensions();
+        let centre = (ALIGNMENT_PATTERN_NUM_ELEMS / 2) as isize;
+        (0..ALIGNMENT_PATTERN_NUM_ELEMS)
+            .flat_map(|y_off| (0..ALIGNMENT_PATTERN_NUM_ELEMS).map(move |x_off| (x_off, y_off)))
+            .filter(|&(x_off, y_off)| {
+                let module = self
+                    .code
+                    .idx_to_module(top_left.x + x_off, top_left.y + y_off);
+                let px = module.cx() + dx * module.width();
+                let py = module.cy() + dy * module.height();
+                if px < 0.0 || py < 0.0 || px >= width as f32 || py >= height as f32 {
                     return false;
                 }
+                let ring = (x_off as isize - centre)
+                    .abs()
+                    .max((y_off as isize - centre).abs());
+                img::is_white(
+                    &self.img.get_pixel(px as u32, py as u32),
+                    self.code.params.white_threshold,
+                ) == (ring == 1)
+            })
+            .count()
+    }
+
+    /// Tries every offset within the search radius, the pattern is in the middle of
+    /// the offsets that match best
+    fn locate(&self, top_left: &GridPoint) -> Option<AlignmentPattern> {
+        let params = &self.code.params;
+        let num_steps = (params.alignment_search_radius / params.alignment_search_step) as i32;
+        let mut best = 0;
+        let mut best_offsets = vec![];
+        for y_step in -num_steps..=num_steps {
+            for x_step in -num_steps..=num_steps {
+                let dx = x_step as f32 * params.alignment_search_step;
+                let dy = y_step as f32 * params.alignment_search_step;
+                let matches = self.count_matches(top_left, dx, dy);
+                if matches > best {
+                    best = matches;
+                    best_offsets.clear();
+                }
+                if matches == best {
+                    best_offsets.push((dx, dy));
+                }
             }
         }
-        return true;
+        if best < params.alignment_min_matches {
+            return None;
+        }
+
+        let num_offsets = best_offsets.len() as f32;
+        let dx = best_offsets.iter().map(|(dx, _)| dx).sum::<f32>() / num_offsets;
+        let dy = best_offsets.iter().map(|(_, dy)| dy).sum::<f32>() / num_offsets;
+        let last = ALIGNMENT_PATTERN_NUM_ELEMS - 1;
+        let first_module = self.code.idx_to_module(top_left.x, top_left.y);
+        let last_module = self
+            .code
+            .idx_to_module(top_left.x + last, top_left.y + last);
+        let module = Rect::from_corners(
+            first_module.left() + dx * first_module.width(),
+            first_module.top() + dy * first_module.height(),
+            last_module.right() + dx * last_module.width(),
+            last_module.bottom() + dy * last_module.height(),
+        );
+        Some(AlignmentPattern {
+            module,
+            x: top_left.x,
+            y: top_left.y,
+        })
     }
 }
 
@@ -711,37 +1198,9 @@ impl Iterator for AlignmentPatternIter<'_> {
 
     fn next(&mut self) -> Option<Self::Item> {
         loop {
-            if self.y >= self.grid_height - ALIGNMENT_PATTERN_NUM_ELEMS {
-                return None;
-            }
-
-            let found_align = if self.is_alignment_element() {
-                let mut module = idx_to_module(
-                    &self.qr_bounds,
-                    self.elem_width,
-                    self.elem_height,
-                    self.x,
-                    self.y,
-                );
-                module.bottom += self.elem_height * (ALIGNMENT_PATTERN_NUM_ELEMS - 1) as f32;
-                module.right += self.elem_width * (ALIGNMENT_PATTERN_NUM_ELEMS - 1) as f32;
-                Some(AlignmentPattern {
-                    x: self.x,
-                    y: self.y,
-                    module,
-                })
-            } else {
-                None
-            };
-            self.x += 1;
-            self.x += 1;
-            if self.x >= self.grid_width - ALIGNMENT_PATTERN_NUM_ELEMS {
-                self.x = 0;
-                self.y += 1;
-            }
-
-            if found_align.is_some() {
-                return found_align;
+            let top_left = self.positions.next()?;
+            if let Some(pattern) = self.locate(&top_left) {
+                return Some(pattern);
             }
         }
     }
@@ -784,6 +1243,7 @@ fn run_length_encode<T: PartialEq>(iter: &mut impl Iterator<Item = T>) -> Vec<Rl
 }
 
 #[cfg(test)]
+#[allow(clippy::items_after_test_module)]
 mod tests {
     use super::*;
 
@@ -811,6 +1271,109 @@ mod tests {
         ];
         assert_eq!(run_length_encode(&mut input.into_iter()), expected);
     }
+
+    #[test]
+    fn test_read_symbol_mirrored() {
+        let img = image::open("test-data/hello-world.png")
+            .unwrap()
+            .grayscale();
+        // rotating and flipping transposes the image
+        let img = img.rotate90().fliph();
+        let code = Code::new(&img, None).unwrap();
+        let symbol = code.read_symbol(&img).unwrap();
+        assert!(symbol.mirrored);
+        assert_eq!(symbol.decode().unwrap().text(), "hello world");
+    }
+
+    #[test]
+    fn test_read_symbol_missing_finder() {
+        let img = image::open("test-data/hello-world.png")
+            .unwrap()
+            .grayscale();
+        let finders = find_patterns(&img, &DetectorParams::default(), None).unwrap();
+        assert_eq!(finders.len(), 3);
+
+        for finder in finders.iter() {
+            // paint over the finder and its separator
+            let mut damaged = img.to_luma8();
+            let margin = finder.width() / FINDER_NUM_ELEMS as f32;
+            let (left, top, right, bottom) = finder.to_corners();
+            for y in (top - margin) as u32..(bottom + margin) as u32 {
+                for x in (left - margin) as u32..(right + margin) as u32 {
+                    damaged.put_pixel(x, y, image::Luma([255]));
+                }
+            }
+            let damaged = image::DynamicImage::ImageLuma8(damaged);
+
+            let code = Code::new(&damaged, None).unwrap();
+            assert!(code.inferred_finder.is_some());
+            let symbol = code.read_symbol(&damaged).unwrap();
+            assert_eq!(symbol.decode().unwrap().text(), "hello world");
+        }
+    }
+
+    #[test]
+    fn test_infer_missing_finder_rotated() {
+        let img = image::open("test-data/hello-world.png")
+            .unwrap()
+            .grayscale();
+        // a quarter turn puts the corner finder top right, and each of the others
+        // can only be placed right from the timing patterns
+        let params = DetectorParams::default();
+        for img in [img.rotate90(), img.rotate180(), img.rotate270()] {
+            let finders = find_patterns(&img, &params, None).unwrap();
+            assert_eq!(finders.len(), 3);
+            for (i, missing) in finders.iter().enumerate() {
+                let mut found = finders.clone();
+                found.remove(i);
+                let inferred =
+                    infer_missing_finder(&img, &found[0], &found[1], params.white_threshold)
+                        .unwrap();
+                let module_size = missing.width() / FINDER_NUM_ELEMS as f32;
+                assert!((inferred.cx() - missing.cx()).abs() < module_size);
+                assert!((inferred.cy() - missing.cy()).abs() < module_size);
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_symbol_uneven_scaling() {
+        let img = image::open("test-data/v5.png").unwrap().to_luma8();
+        let (width, height) = img.dimensions();
+        // modules shrink by up to 5% towards the bottom right, which puts the far
+        // edge several modules off for a single average module size
+        let warp = |v: u32, size: u32| (size as f32 * (v as f32 / size as f32).powf(1.05)) as u32;
+        let warped = image::GrayImage::from_fn(width, height, |x, y| {
+            *img.get_pixel(warp(x, width), warp(y, height))
+        });
+        let img = image::DynamicImage::ImageLuma8(warped);
+
+        let code = Code::new(&img, None).unwrap();
+        let message = code.read_symbol(&img).unwrap().decode().unwrap();
+        assert!(message.text().starts_with("Version 25 QR Code"));
+        assert_eq!(message.corrected, 0);
+    }
+
+    #[test]
+    fn test_read_symbol_local_distortion() {
+        let img = image::open("test-data/v5.png").unwrap().to_luma8();
+        let (width, height) = img.dimensions();
+        // the timing patterns barely move, while the bottom right corner drifts
+        // about 3 modules, as on a curved surface
+        let warped = image::GrayImage::from_fn(width, height, |x, y| {
+            let (fx, fy) = (x as f32 / width as f32, y as f32 / height as f32);
+            let drift = 0.03 * fx * fy;
+            let (sx, sy) = ((fx + drift) * width as f32, (fy + drift) * height as f32);
+            img.get_pixel_checked(sx as u32, sy as u32)
+                .copied()
+                .unwrap_or(image::Luma([255]))
+        });
+        let img = image::DynamicImage::ImageLuma8(warped);
+
+        let code = Code::new(&img, None).unwrap();
+        let message = code.read_symbol(&img).unwrap().decode().unwrap();
+        assert!(message.text().starts_with("Version 25 QR Code"));
+    }
 }
 
 struct FinderCandidate1D {
@@ -818,14 +1381,14 @@ struct FinderCandidate1D {
     length: f32,
 }
 
-fn is_almost_same(a: u32, b: u32) -> bool {
+fn is_almost_same(a: u32, b: u32, tolerance: f32) -> bool {
     let af = a as f32;
     let bf = b as f32;
 
-    (af / bf - 1.0).abs() < 0.2
+    (af / bf - 1.0).abs() < tolerance
 }
 
-fn find_candidates(rle: &[RleItem]) -> Vec<FinderCandidate1D> {
+fn find_candidates(rle: &[RleItem], tolerance: f32) -> Vec<FinderCandidate1D> {
     let mut res = vec![];
     if rle.len() < 5 {
         return res;
@@ -836,16 +1399,16 @@ fn find_candidates(rle: &[RleItem]) -> Vec<FinderCandidate1D> {
     for i in 0..end {
         // TODO: Allow for errors
         let reference = rle[i].len;
-        if !is_almost_same(rle[i + 1].len, reference) {
+        if !is_almost_same(rle[i + 1].len, reference, tolerance) {
             continue;
         }
-        if !is_almost_same(rle[i + 2].len, reference * 3) {
+        if !is_almost_same(rle[i + 2].len, reference * 3, tolerance) {
             continue;
         }
-        if !is_almost_same(rle[i + 3].len, reference) {
+        if !is_almost_same(rle[i + 3].len, reference, tolerance) {
             continue;
         }
-        if !is_almost_same(rle[i + 4].len, reference) {
+        if !is_almost_same(rle[i + 4].len, reference, tolerance) {
             continue;
         }
         let length = (rle[i + 4].start + rle[i + 4].len - rle[i].start) as f32;
@@ -859,11 +1422,11 @@ fn find_candidates(rle: &[RleItem]) -> Vec<FinderCandidate1D> {
     res
 }
 
-fn add_rect_to_bucket(buckets: &mut Vec<Vec<Rect>>, rect: Rect) {
+fn add_rect_to_bucket(buckets: &mut Vec<Vec<Rect>>, rect: Rect, distance: f32) {
     for bucket in buckets.iter_mut() {
         for bucket_rect in bucket.iter_mut() {
-            if relative_eq!(bucket_rect.cx(), rect.cx(), epsilon = 10.0)
-                && relative_eq!(bucket_rect.cy(), rect.cy(), epsilon = 10.0)
+            if relative_eq!(bucket_rect.cx(), rect.cx(), epsilon = distance)
+                && relative_eq!(bucket_rect.cy(), rect.cy(), epsilon = distance)
             {
                 bucket.push(rect);
                 return;
@@ -882,7 +1445,8 @@ fn add_rect_to_bucket(buckets: &mut Vec<Vec<Rect>>, rect: Rect) {
 /// to cluster and find 3 points at the end.
 pub fn find_patterns(
     img: &image::DynamicImage,
-    mut visualizer: Option<&mut Visualizer>,
+    params: &DetectorParams,
+    mut visualizer: Option<&mut (dyn Visualizer + '_)>,
 ) -> Result<Vec<Rect>> {
     let (width, height) = img.dimensions();
 
@@ -890,13 +1454,8 @@ pub fn find_patterns(
     let mut horizontal_candidates: Vec<Rect> = Vec::new();
 
     for x in 0..width {
-        let encoding = run_length_encode(&mut img.to_vert(x));
-        // print!("encoding = [");
-        // for rle in encoding.iter() {
-        //     print!("{:?}, ", rle.len);
-        // }
-        // println!("]");
-        let candidates = find_candidates(&encoding);
+        let encoding = run_length_encode(&mut img.to_vert(x, params.white_threshold));
+        let candidates = find_candidates(&encoding, params.finder_ratio_tolerance);
         for FinderCandidate1D { center, length } in candidates {
             let cx = x as f32 + 0.5;
             horizontal_candidates.push(Rect::from_center_and_size(cx, center, length, 0.0));
@@ -909,8 +1468,8 @@ pub fn find_patterns(
     let mut vertical_candidates: Vec<Rect> = Vec::new();
 
     for y in 0..height {
-        let encoding = run_length_encode(&mut img.to_horiz(y));
-        let candidates = find_candidates(&encoding);
+        let encoding = run_length_encode(&mut img.to_horiz(y, params.white_threshold));
+        let candidates = find_candidates(&encoding, params.finder_ratio_tolerance);
         for FinderCandidate1D { center, length } in candidates {
             let cy = y as f32 + 0.5;
             vertical_candidates.push(Rect::from_center_and_size(center, cy, 0.0, length));
@@ -924,15 +1483,15 @@ pub fn find_patterns(
 
     for v in vertical_candidates.iter() {
         for h in horizontal_candidates.iter() {
-            if relative_eq!(v.cx(), h.cx(), epsilon = 1.0)
-                && relative_eq!(v.cy(), h.cy(), epsilon = 1.0)
+            if relative_eq!(v.cx(), h.cx(), epsilon = params.finder_cross_distance)
+                && relative_eq!(v.cy(), h.cy(), epsilon = params.finder_cross_distance)
             {
                 let cx = (v.cx() + h.cx()) / 2.0;
                 let cy = (v.cy() + h.cy()) / 2.0;
                 let width = h.width();
                 let height = v.height();
                 let combined = Rect::from_center_and_size(cx, cy, width, height);
-                add_rect_to_bucket(&mut buckets, combined);
+                add_rect_to_bucket(&mut buckets, combined, params.finder_merge_distance);
             }
         }
     }
@@ -0,0 +1,228 @@
+use std::io::Write;
+
+use image::Rgb;
+use xml::{
+    writer::{Result, XmlEvent},
+    EmitterConfig,
+};
+
+use crate::matrix::ModuleMatrix;
+
+/// The quiet zone the spec asks for around a QR code, in modules
+const DEFAULT_QUIET_ZONE: u32 = 4;
+const DEFAULT_MODULE_SIZE: u32 = 8;
+
+/// How the modules of a symbol are drawn, shared by all the renderers. Sizes are
+/// in pixels for PNG, and in user units for SVG.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    module_size: u32,
+    quiet_zone: u32,
+    dark: Rgb<u8>,
+    light: Rgb<u8>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            module_size: DEFAULT_MODULE_SIZE,
+            quiet_zone: DEFAULT_QUIET_ZONE,
+            dark: Rgb([0, 0, 0]),
+            light: Rgb([255, 255, 255]),
+        }
+    }
+}
+
+impl RenderOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn module_size(mut self, module_size: u32) -> Self {
+        self.module_size = module_size;
+        self
+    }
+
+    /// Light modules around the symbol, readers need a few to find its edge
+    pub fn quiet_zone(mut self, quiet_zone: u32) -> Self {
+        self.quiet_zone = quiet_zone;
+        self
+    }
+
+    pub fn dark(mut self, dark: Rgb<u8>) -> Self {
+        self.dark = dark;
+        self
+    }
+
+    pub fn light(mut self, light: Rgb<u8>) -> Self {
+        self.light = light;
+        self
+    }
+
+    /// Modules across and down, quiet zone included
+    pub(crate) fn size(&self, matrix: &ModuleMatrix) -> (u32, u32) {
+        (
+            matrix.width() as u32 + 2 * self.quiet_zone,
+            matrix.height() as u32 + 2 * self.quiet_zone,
+        )
+    }
+
+    /// Whether the module at `x`, `y` counted from the edge of the quiet zone is dark
+    fn is_dark(&self, matrix: &ModuleMatrix, x: u32, y: u32) -> bool {
+        let x = x.checked_sub(self.quiet_zone).map(|x| x as usize);
+        let y = y.checked_sub(self.quiet_zone).map(|y| y as usize);
+        match (x, y) {
+            (Some(x), Some(y)) if x < matrix.width() && y < matrix.height() => matrix.get(x, y),
+            _ => false,
+        }
+    }
+}
+
+pub fn to_image(matrix: &ModuleMatrix, options: &RenderOptions) -> image::RgbImage {
+    let (width, height) = options.size(matrix);
+    let scale = options.module_size;
+    image::RgbImage::from_fn(width * scale, height * scale, |x, y| {
+        if options.is_dark(matrix, x / scale, y / scale) {
+            options.dark
+        } else {
+            options.light
+        }
+    })
+}
+
+/// Writes an SVG with the background as one rect and all dark modules as one path,
+/// each horizontal run of them a single subpath. The view box is in modules, so
+/// it scales without blurring.
+pub fn write_svg<W: Write>(matrix: &ModuleMatrix, options: &RenderOptions, sink: W) -> Result<()> {
+    let (width, height) = options.size(matrix);
+    let mut writer = EmitterConfig::new()
+        .perform_indent(true)
+        .create_writer(sink);
+    writer.write(
+        XmlEvent::start_element("svg")
+            .attr("xmlns", "http://www.w3.org/2000/svg")
+            .attr("width", &(width * options.module_size).to_string())
+            .attr("height", &(height * options.module_size).to_string())
+            .attr("viewBox", &format!("0 0 {width} {height}"))
+            .attr("shape-rendering", "crispEdges"),
+    )?;
+    writer.write(
+        XmlEvent::start_element("rect")
+            .attr("width", "100%")
+            .attr("height", "100%")
+            .attr("fill", &hex_color(options.light)),
+    )?;
+    writer.write(XmlEvent::end_element())?;
+    writer.write(
+        XmlEvent::start_element("path")
+            .attr("d", &dark_path(matrix, options))
+            .attr("fill", &hex_color(options.dark)),
+    )?;
+    writer.write(XmlEvent::end_element())?;
+    writer.write(XmlEvent::end_element())?;
+    Ok(())
+}
+
+/// The path data of the dark modules, in modules from the edge of the quiet zone
+pub(crate) fn dark_path(matrix: &ModuleMatrix, options: &RenderOptions) -> String {
+    let (width, height) = options.size(matrix);
+    let mut path = String::new();
+    for y in 0..height {
+        let mut x = 0;
+        while x < width {
+            if !options.is_dark(matrix, x, y) {
+                x += 1;
+                continue;
+            }
+            let start = x;
+            while x < width && options.is_dark(matrix, x, y) {
+                x += 1;
+            }
+            let len = x - start;
+            path.push_str(&format!("M{start},{y}h{len}v1h-{len}z"));
+        }
+    }
+    path
+}
+
+pub fn to_svg(matrix: &ModuleMatrix, options: &RenderOptions) -> String {
+    let mut svg = Vec::new();
+    write_svg(matrix, options, &mut svg).expect("Writing to a Vec can't fail");
+    String::from_utf8(svg).expect("The writer emits UTF-8")
+}
+
+/// Draws two rows of modules per line with half blocks, dark modules as ink.
+/// Terminals with a light text on a dark background need the colours swapped,
+/// `light` and `dark` only decide which of the two is drawn with ink.
+pub fn to_unicode(matrix: &ModuleMatrix, options: &RenderOptions) -> String {
+    let (width, height) = options.size(matrix);
+    // a light module is ink when the light colour is darker than the dark one
+    let luma = |Rgb([r, g, b]): Rgb<u8>| r as u32 + g as u32 + b as u32;
+    let swapped = luma(options.light) < luma(options.dark);
+    // the row below an odd number of them is light too
+    let ink = |x, y| (y < height && options.is_dark(matrix, x, y)) != swapped;
+
+    let mut text = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            text.push(match (ink(x, y), ink(x, y + 1)) {
+                (true, true) => '█',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (false, false) => ' ',
+            });
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn hex_color(Rgb([r, g, b]): Rgb<u8>) -> String {
+    format!("#{r:02x}{g:02x}{b:02x}")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checker(size: usize) -> ModuleMatrix {
+        let mut matrix = ModuleMatrix::new(size, size);
+        for y in 0..size {
+            for x in 0..size {
+                matrix.set(x, y, (x + y) % 2 == 0);
+            }
+        }
+        matrix
+    }
+
+    #[test]
+    fn test_to_image() {
+        let options = RenderOptions::new().module_size(3).quiet_zone(1);
+        let img = to_image(&checker(3), &options);
+        assert_eq!(img.dimensions(), (15, 15));
+        assert_eq!(*img.get_pixel(2, 2), options.light);
+        assert_eq!(*img.get_pixel(3, 3), options.dark);
+        assert_eq!(*img.get_pixel(5, 5), options.dark);
+        assert_eq!(*img.get_pixel(6, 3), options.light);
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let mut matrix = ModuleMatrix::new(3, 1);
+        matrix.set(0, 0, true);
+        matrix.set(1, 0, true);
+        let svg = to_svg(&matrix, &RenderOptions::new().quiet_zone(2));
+        assert_eq!(svg.matches("<path").count(), 1);
+        assert!(svg.contains(r#"d="M2,2h2v1h-2z""#));
+        assert!(svg.contains(r#"viewBox="0 0 7 5""#));
+    }
+
+    #[test]
+    fn test_to_unicode() {
+        let options = RenderOptions::new().quiet_zone(0);
+        assert_eq!(to_unicode(&checker(3), &options), "▀▄▀\n▀ ▀\n");
+
+        let inverted = options.dark(Rgb([255, 255, 255])).light(Rgb([0, 0, 0]));
+        assert_eq!(to_unicode(&checker(3), &inverted), "▄▀▄\n▄█▄\n");
+    }
+}
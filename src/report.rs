@@ -0,0 +1,326 @@
+use std::{
+    fmt::Display,
+    fs, io,
+    path::Path,
+    time::{Duration, Instant},
+};
+
+use maud::{html, Markup, PreEscaped, DOCTYPE};
+
+use crate::{
+    matrix::ModuleMatrix,
+    render::{self, RenderOptions},
+    segment::Message,
+    util::Rect,
+    viz::Background,
+};
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; }
+.figures { display: flex; flex-wrap: wrap; gap: 2em; margin: 1em 0; }
+figure { margin: 0; }
+figure svg { width: 480px; height: auto; border: 1px solid #ccc; }
+label { margin-right: 1em; font-weight: bold; }
+table { border-collapse: collapse; margin-bottom: 1em; }
+th, td { border: 1px solid #ccc; padding: 0.2em 0.6em; text-align: left; }
+.error { color: #b00; font-weight: bold; }
+";
+
+/// The parts of the overlay the report can show and hide on their own
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layer {
+    Finders,
+    Timing,
+    Format,
+    Alignment,
+    DataOrder,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 5] = [
+        Self::Finders,
+        Self::Timing,
+        Self::Format,
+        Self::Alignment,
+        Self::DataOrder,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Finders => "Finders",
+            Self::Timing => "Timing",
+            Self::Format => "Format",
+            Self::Alignment => "Alignment",
+            Self::DataOrder => "Data order",
+        }
+    }
+
+    /// The same colours the debug SVG draws them in
+    fn color(&self) -> &'static str {
+        match self {
+            Self::Finders => "yellow",
+            Self::Timing => "red",
+            Self::Format => "purple",
+            Self::Alignment => "blue",
+            Self::DataOrder => "orange",
+        }
+    }
+
+    fn id(&self) -> String {
+        format!("layer-{}", self.name().to_lowercase().replace(' ', "-"))
+    }
+}
+
+/// An outline on the overlay, with an optional label in the middle and tooltip
+struct Mark {
+    layer: Layer,
+    rect: Rect,
+    label: Option<String>,
+    title: Option<String>,
+}
+
+/// Collects what the decoder saw at each step, and writes it all to one HTML page:
+/// the image with each layer of the overlay toggled by a checkbox, the modules
+/// read off it, the format info, the segments and how long each step took.
+pub struct Report {
+    title: String,
+    /// Size of the image, in pixels
+    size: (u32, u32),
+    background: Option<Background>,
+    marks: Vec<Mark>,
+    matrix: Option<ModuleMatrix>,
+    info: Vec<(String, String)>,
+    message: Option<Message>,
+    timings: Vec<(String, Duration)>,
+    error: Option<String>,
+}
+
+impl Report {
+    pub fn new(title: impl Into<String>, width: u32, height: u32) -> Self {
+        Self {
+            title: title.into(),
+            size: (width, height),
+            background: None,
+            marks: Vec::new(),
+            matrix: None,
+            info: Vec::new(),
+            message: None,
+            timings: Vec::new(),
+            error: None,
+        }
+    }
+
+    /// The image the overlay is drawn on
+    pub fn background(&mut self, background: Background) {
+        self.background = Some(background);
+    }
+
+    pub fn rect(&mut self, layer: Layer, rect: &Rect) {
+        self.marks.push(Mark {
+            layer,
+            rect: rect.clone(),
+            label: None,
+            title: None,
+        });
+    }
+
+    pub fn labelled_rect(
+        &mut self,
+        layer: Layer,
+        rect: &Rect,
+        label: impl Into<String>,
+        title: Option<String>,
+    ) {
+        self.marks.push(Mark {
+            layer,
+            rect: rect.clone(),
+            label: Some(label.into()),
+            title,
+        });
+    }
+
+    /// A row of the symbol table, e.g. the version or mask
+    pub fn info(&mut self, key: impl Into<String>, value: impl Display) {
+        self.info.push((key.into(), value.to_string()));
+    }
+
+    pub fn matrix(&mut self, matrix: &ModuleMatrix) {
+        self.matrix = Some(matrix.clone());
+    }
+
+    pub fn message(&mut self, message: &Message) {
+        self.message = Some(message.clone());
+    }
+
+    /// Where decoding stopped, the report still shows everything up to it
+    pub fn error(&mut self, error: impl Display) {
+        self.error = Some(error.to_string());
+    }
+
+    pub fn timing(&mut self, stage: impl Into<String>, duration: Duration) {
+        self.timings.push((stage.into(), duration));
+    }
+
+    /// Runs `step` and adds how long it took as `stage`
+    pub fn time<T>(&mut self, stage: impl Into<String>, step: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = step();
+        self.timing(stage, start.elapsed());
+        result
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        fs::write(path, self.to_html().into_string())
+    }
+
+    pub fn to_html(&self) -> Markup {
+        let toggles: String = Layer::ALL
+            .iter()
+            .map(|layer| {
+                let id = layer.id();
+                format!("#{id}:not(:checked) ~ .figures .{id} {{ display: none; }}\n")
+            })
+            .collect();
+        html! {
+            (DOCTYPE)
+            html {
+                head {
+                    meta charset="utf-8";
+                    title { (self.title) }
+                    style { (PreEscaped(STYLE)) (PreEscaped(toggles)) }
+                }
+                body {
+                    h1 { (self.title) }
+                    @if let Some(error) = &self.error {
+                        p.error { (error) }
+                    }
+                    @for layer in Layer::ALL {
+                        input type="checkbox" id=(layer.id()) checked;
+                        label for=(layer.id()) style={ "color: " (layer.color()) } { (layer.name()) }
+                    }
+                    div.figures {
+                        figure {
+                            (self.overlay())
+                            figcaption { "Image" }
+                        }
+                        @if let Some(matrix) = &self.matrix {
+                            figure {
+                                (modules(matrix))
+                                figcaption { "Modules" }
+                            }
+                        }
+                    }
+                    (self.tables())
+                }
+            }
+        }
+    }
+
+    fn overlay(&self) -> Markup {
+        let (width, height) = self.size;
+        let stroke_width = width as f32 / 500.0;
+        html! {
+            svg xmlns="http://www.w3.org/2000/svg" viewBox={ "0 0 " (width) " " (height) } {
+                @if let Some(background) = &self.background {
+                    image href=(background.href()) width=(width) height=(height) {}
+                }
+                @for layer in Layer::ALL {
+                    g class=(layer.id()) stroke=(layer.color()) fill="none" stroke-width=(stroke_width) {
+                        @for mark in self.marks.iter().filter(|mark| mark.layer == layer) {
+                            rect x=(mark.rect.left) y=(mark.rect.top)
+                                width=(mark.rect.width()) height=(mark.rect.height()) {
+                                @if let Some(title) = &mark.title {
+                                    title { (title) }
+                                }
+                            }
+                            @if let Some(label) = &mark.label {
+                                text x=(mark.rect.cx()) y=(mark.rect.cy())
+                                    font-size=(mark.rect.height() / 2.0) stroke="none" fill="red"
+                                    text-anchor="middle" dominant-baseline="central" { (label) }
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    fn tables(&self) -> Markup {
+        html! {
+            h2 { "Symbol" }
+            table {
+                @for (key, value) in &self.info {
+                    tr { th { (key) } td { (value) } }
+                }
+            }
+            @if let Some(message) = &self.message {
+                h2 { "Segments" }
+                table {
+                    tr { th { "Mode" } th { "Count" } th { "Data" } }
+                    @for segment in &message.segments {
+                        tr {
+                            td { (format!("{:?}", segment.mode)) }
+                            td { (segment.count) }
+                            td { code { (String::from_utf8_lossy(&segment.data)) } }
+                        }
+                    }
+                }
+                p { "Codewords fixed by error correction: " (message.corrected) }
+                p { "Text: " code { (message.text()) } }
+            }
+            h2 { "Timings" }
+            table {
+                @for (stage, duration) in &self.timings {
+                    tr {
+                        th { (stage) }
+                        td { (format!("{:.3} ms", duration.as_secs_f64() * 1000.0)) }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// The modules as read, with a one module margin
+fn modules(matrix: &ModuleMatrix) -> Markup {
+    let options = RenderOptions::new().quiet_zone(1);
+    let (width, height) = options.size(matrix);
+    html! {
+        svg xmlns="http://www.w3.org/2000/svg" viewBox={ "0 0 " (width) " " (height) }
+            shape-rendering="crispEdges" {
+            rect width="100%" height="100%" fill="white" {}
+            path d=(render::dark_path(matrix, &options)) fill="black" {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_html() {
+        let mut report = Report::new("QR <1>", 100, 50);
+        report.rect(Layer::Finders, &Rect::from_corners(0.0, 0.0, 7.0, 7.0));
+        report.labelled_rect(
+            Layer::DataOrder,
+            &Rect::from_corners(8.0, 8.0, 9.0, 9.0),
+            "0",
+            Some("(8, 8) bit 0".into()),
+        );
+        report.info("version", 1);
+        report.time("find patterns", || ());
+        report.error("Expected 3 finders, found 2");
+
+        let html = report.to_html().into_string();
+        assert!(html.contains("<title>QR &lt;1&gt;</title>"));
+        for layer in Layer::ALL {
+            assert!(html.contains(&format!(r#"<g class="{}""#, layer.id())));
+        }
+        assert!(html.contains("#layer-data-order:not(:checked) ~ .figures .layer-data-order"));
+        assert!(html.contains("<title>(8, 8) bit 0</title>"));
+        assert!(html.contains("<th>version</th><td>1</td>"));
+        assert!(html.contains("find patterns"));
+        assert!(html.contains("Expected 3 finders"));
+    }
+}
@@ -0,0 +1,549 @@
+use image::GenericImageView;
+
+use crate::{
+    codewords::{self, BlockLayout, ModuleBit},
+    decode::Version,
+    error::{DecodeError, Result},
+    format::{bch_remainder, EcLevel, MAX_BCH_ERRORS},
+    img,
+    matrix::ModuleMatrix,
+    qr::{self, get_mask_fn, DetectorParams, Output},
+    segment::{self, BitReader, Bitstream, HeaderSpec, Message, Mode},
+    util::Rect,
+};
+
+const FINDER_NUM_ELEMS: usize = 7;
+const SUB_FINDER_NUM_ELEMS: usize = 5;
+const FORMAT_GENERATOR: u32 = 0b1_1111_0010_0101;
+/// The two copies of the format info are masked differently
+const FORMAT_XOR_MASK_FINDER: u32 = 0b01_1111_1010_1011_0010;
+const FORMAT_XOR_MASK_SUB_FINDER: u32 = 0b10_0000_1010_0111_1011;
+const FORMAT_NUM_BITS: usize = 18;
+/// The highest version indicator, R17x139
+pub const MAX_VERSION: u8 = VERSIONS.len() as u8 - 1;
+/// rMQR has a single mask pattern, the same as QR mask 100
+pub const MASK_PATTERN: u8 = 0b100;
+
+const RMQR_MODES: &[(u32, Mode)] = &[
+    (0b001, Mode::Numeric),
+    (0b010, Mode::Alphanumeric),
+    (0b011, Mode::Byte),
+    (0b100, Mode::Kanji),
+    (0b111, Mode::Eci),
+];
+
+struct RmqrVersion {
+    height: usize,
+    width: usize,
+    /// Character count widths for numeric, alphanumeric, byte and kanji
+    count_bits: [usize; 4],
+    /// (ec codewords per block, number of blocks) for M and H
+    blocks: [(usize, usize); 2],
+}
+
+const fn v(
+    height: usize,
+    width: usize,
+    count_bits: [usize; 4],
+    m: (usize, usize),
+    h: (usize, usize),
+) -> RmqrVersion {
+    RmqrVersion {
+        height,
+        width,
+        count_bits,
+        blocks: [m, h],
+    }
+}
+
+/// Indexed by the 5 bit version indicator, R7x43 to R17x139
+#[rustfmt::skip]
+const VERSIONS: [RmqrVersion; 32] = [
+    v(7, 43, [4, 3, 3, 2], (7, 1), (10, 1)),
+    v(7, 59, [5, 5, 4, 3], (9, 1), (14, 1)),
+    v(7, 77, [6, 5, 5, 4], (12, 1), (22, 1)),
+    v(7, 99, [7, 6, 5, 5], (16, 1), (30, 1)),
+    v(7, 139, [7, 6, 6, 5], (24, 1), (22, 2)),
+    v(9, 43, [5, 5, 4, 3], (9, 1), (14, 1)),
+    v(9, 59, [6, 5, 5, 4], (12, 1), (22, 1)),
+    v(9, 77, [7, 6, 5, 5], (18, 1), (16, 2)),
+    v(9, 99, [7, 6, 6, 5], (24, 1), (22, 2)),
+    v(9, 139, [8, 7, 6, 6], (18, 2), (22, 3)),
+    v(11, 27, [4, 4, 3, 2], (8, 1), (10, 1)),
+    v(11, 43, [6, 5, 5, 4], (12, 1), (20, 1)),
+    v(11, 59, [7, 6, 5, 5], (16, 1), (16, 2)),
+    v(11, 77, [7, 6, 6, 5], (24, 1), (22, 2)),
+    v(11, 99, [8, 7, 6, 6], (16, 2), (30, 2)),
+    v(11, 139, [8, 7, 7, 6], (24, 2), (30, 3)),
+    v(13, 27, [5, 5, 4, 3], (9, 1), (14, 1)),
+    v(13, 43, [6, 6, 5, 5], (14, 1), (28, 1)),
+    v(13, 59, [7, 6, 6, 5], (22, 1), (20, 2)),
+    v(13, 77, [7, 7, 6, 5], (16, 2), (28, 2)),
+    v(13, 99, [8, 7, 7, 6], (20, 2), (26, 3)),
+    v(13, 139, [8, 8, 7, 7], (20, 3), (28, 4)),
+    v(15, 43, [7, 6, 6, 5], (18, 1), (18, 2)),
+    v(15, 59, [7, 7, 6, 5], (26, 1), (24, 2)),
+    v(15, 77, [8, 7, 7, 6], (18, 2), (24, 3)),
+    v(15, 99, [8, 7, 7, 6], (24, 2), (22, 4)),
+    v(15, 139, [9, 8, 7, 7], (24, 3), (26, 5)),
+    v(17, 43, [7, 6, 6, 5], (22, 1), (20, 2)),
+    v(17, 59, [8, 7, 6, 6], (16, 2), (30, 2)),
+    v(17, 77, [8, 7, 7, 6], (22, 2), (28, 3)),
+    v(17, 99, [8, 8, 7, 6], (20, 3), (26, 4)),
+    v(17, 139, [9, 8, 8, 7], (20, 4), (26, 6)),
+];
+
+/// Whether there is an rMQR version of `width` by `height` modules
+pub fn is_valid_size(width: usize, height: usize) -> bool {
+    VERSIONS
+        .iter()
+        .any(|version| version.width == width && version.height == height)
+}
+
+/// Columns of the alignment patterns, and the vertical timing patterns running between them
+fn alignment_pattern_columns(width: usize) -> &'static [usize] {
+    match width {
+        43 => &[21],
+        59 => &[19, 39],
+        77 => &[25, 51],
+        99 => &[23, 49, 75],
+        139 => &[27, 55, 83, 111],
+        _ => &[],
+    }
+}
+
+pub fn is_function_module(width: usize, height: usize, x: usize, y: usize) -> bool {
+    // timing patterns on all edges and under every alignment pattern
+    if y == 0 || y == height - 1 || x == 0 || x == width - 1 {
+        return true;
+    }
+    if alignment_pattern_columns(width)
+        .iter()
+        .any(|&cx| x == cx || (x + 1 >= cx && x <= cx + 1 && (y <= 2 || y >= height - 3)))
+    {
+        return true;
+    }
+    // finder, its separator and the format info next to it
+    let separator_len = FINDER_NUM_ELEMS + 1;
+    if x < separator_len && y < separator_len {
+        return true;
+    }
+    if (8..11).contains(&x) && (1..6).contains(&y) || x == 11 && (1..4).contains(&y) {
+        return true;
+    }
+    // sub finder and the format info next to it
+    if x >= width - SUB_FINDER_NUM_ELEMS && y >= height - SUB_FINDER_NUM_ELEMS {
+        return true;
+    }
+    if x >= width - 8 && x < width - 5 && y >= height - 6 {
+        return true;
+    }
+    if x >= width - 5 && x < width - 2 && y == height - 6 {
+        return true;
+    }
+    // corner finder patterns
+    let in_tr_corner = x >= width - 2 && y < 2;
+    let in_bl_corner = x < 3 && y == height - 1 || height >= 11 && x < 2 && y == height - 2;
+    in_tr_corner || in_bl_corner
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RmqrFormat {
+    /// The version indicator, 0 (R7x43) to 31 (R17x139)
+    pub version: u8,
+    /// M or H
+    pub ec_level: EcLevel,
+}
+
+impl RmqrFormat {
+    fn info(&self) -> &'static RmqrVersion {
+        &VERSIONS[self.version as usize]
+    }
+
+    /// The 18 bit format word before it is xor-ed with the mask of either copy
+    fn bch_word(&self) -> u32 {
+        let ec_bit = u32::from(self.ec_level == EcLevel::H);
+        let data = ((ec_bit << 5) | self.version as u32) << 12;
+        data | bch_remainder(data, FORMAT_GENERATOR)
+    }
+
+    /// Finds the nearest valid format word, returns it with its hamming distance to `bits`
+    pub fn decode(bits: u32, xor_mask: u32) -> Option<(Self, u32)> {
+        [EcLevel::M, EcLevel::H]
+            .into_iter()
+            .flat_map(|ec_level| (0..32).map(move |version| RmqrFormat { version, ec_level }))
+            .map(|format| (format, (format.bch_word() ^ xor_mask ^ bits).count_ones()))
+            .min_by_key(|(_, distance)| *distance)
+            .filter(|(_, distance)| *distance <= MAX_BCH_ERRORS)
+    }
+
+    pub fn width(&self) -> usize {
+        self.info().width
+    }
+
+    pub fn height(&self) -> usize {
+        self.info().height
+    }
+
+    pub fn name(&self) -> String {
+        format!("R{}x{}", self.height(), self.width())
+    }
+
+    pub fn num_codewords(&self) -> usize {
+        let (width, height) = (self.width(), self.height());
+        let num_data_modules = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .filter(|&(x, y)| !is_function_module(width, height, x, y))
+            .count();
+        num_data_modules / 8
+    }
+
+    pub fn block_layout(&self) -> BlockLayout {
+        let (ec_len, num_blocks) = match self.ec_level {
+            EcLevel::H => self.info().blocks[1],
+            _ => self.info().blocks[0],
+        };
+        BlockLayout::new(self.num_codewords(), num_blocks, ec_len)
+    }
+
+    /// Positions of the data and ec modules, in placement order. The rightmost
+    /// column is a timing pattern, so the first column pair starts left of it.
+    pub fn data_positions(&self) -> Vec<(usize, usize)> {
+        let (width, height) = (self.width(), self.height());
+        codewords::placement_order(width, height, Some(width - 1), |x, y| {
+            is_function_module(width, height, x, y)
+        })
+    }
+
+    /// Which block, codeword and bit each data module holds
+    pub fn module_map(&self) -> Vec<ModuleBit> {
+        let layout = self.block_layout();
+        codewords::module_map(
+            &self.data_positions(),
+            &layout,
+            layout.num_data_codewords() * 8,
+        )
+    }
+
+    pub fn header_spec(&self) -> HeaderSpec {
+        HeaderSpec {
+            mode_bits: 3,
+            modes: RMQR_MODES,
+            count_bits: self.info().count_bits,
+            terminator_bits: 3,
+        }
+    }
+}
+
+/// Positions of the format info right of the finder, bit 0 first
+fn format_positions_finder() -> impl Iterator<Item = (usize, usize)> {
+    (0..FORMAT_NUM_BITS).map(|i| (8 + i / 5, 1 + i % 5))
+}
+
+/// Positions of the format info left of and above the sub finder, bit 0 first
+fn format_positions_sub_finder(
+    width: usize,
+    height: usize,
+) -> impl Iterator<Item = (usize, usize)> {
+    (0..FORMAT_NUM_BITS).map(move |i| match i {
+        0..=14 => (width - 8 + i / 5, height - 6 + i % 5),
+        _ => (width - 20 + i, height - 6),
+    })
+}
+
+/// Finds where the symbol ends along a row or column starting inside it, which is
+/// the last dark run before the quiet zone. No white run inside is longer than a module.
+/// None if the start is already past the edge of the image.
+fn find_symbol_end(
+    start_px: u32,
+    end_px: u32,
+    elem_size: f32,
+    quiet_zone_run: f32,
+    get_pix_val: impl Fn(u32) -> bool,
+) -> Option<f32> {
+    if start_px >= end_px {
+        // the finder is at the edge of the image
+        return None;
+    }
+    let mut last_dark_end = start_px as f32;
+    let mut run_start = start_px;
+    let mut last_is_white = get_pix_val(start_px);
+
+    for px in start_px..=end_px {
+        let is_white = if px == end_px {
+            !last_is_white
+        } else {
+            get_pix_val(px)
+        };
+        if is_white == last_is_white {
+            continue;
+        }
+        if last_is_white && (px - run_start) as f32 > quiet_zone_run * elem_size {
+            break;
+        }
+        if !last_is_white {
+            last_dark_end = px as f32;
+        }
+        run_start = px;
+        last_is_white = is_white;
+    }
+    Some(last_dark_end)
+}
+
+pub struct RmqrCode {
+    pub bounds: Rect,
+    elem_width: f32,
+    elem_height: f32,
+    width: usize,
+    height: usize,
+    params: DetectorParams,
+}
+
+impl RmqrCode {
+    /// Follows the top row and the left column from the finder to the quiet zone,
+    /// and snaps the measured size to the nearest rMQR version
+    pub fn from_finder(
+        img: &image::DynamicImage,
+        finder: &Rect,
+        params: &DetectorParams,
+    ) -> Result<Self> {
+        let (img_width, img_height) = img.dimensions();
+        let estimated_elem_width = finder.width() / FINDER_NUM_ELEMS as f32;
+        let estimated_elem_height = finder.height() / FINDER_NUM_ELEMS as f32;
+        let left = finder.left();
+        let top = finder.top();
+
+        let top_row_center_px = (top + estimated_elem_height / 2.0) as u32;
+        let left_col_center_px = (left + estimated_elem_width / 2.0) as u32;
+        let right = find_symbol_end(
+            (left + FINDER_NUM_ELEMS as f32 * estimated_elem_width) as u32,
+            img_width,
+            estimated_elem_width,
+            params.quiet_zone_run,
+            |x| img::is_white(&img.get_pixel(x, top_row_center_px), params.white_threshold),
+        )
+        .ok_or(DecodeError::TimingNotFound { horizontal: true })?;
+        let bottom = find_symbol_end(
+            (top + (FINDER_NUM_ELEMS as f32 - 0.5) * estimated_elem_height) as u32,
+            img_height,
+            estimated_elem_height,
+            params.quiet_zone_run,
+            |y| {
+                img::is_white(
+                    &img.get_pixel(left_col_center_px, y),
+                    params.white_threshold,
+                )
+            },
+        )
+        .ok_or(DecodeError::TimingNotFound { horizontal: false })?;
+
+        let measured_width = (right - left) / estimated_elem_width;
+        let measured_height = (bottom - top) / estimated_elem_height;
+        let version = VERSIONS
+            .iter()
+            .min_by(|a, b| {
+                let error = |v: &RmqrVersion| {
+                    (v.width as f32 - measured_width).abs()
+                        + (v.height as f32 - measured_height).abs()
+                };
+                error(a).total_cmp(&error(b))
+            })
+            .expect("There are rMQR versions");
+        if (version.width as f32 - measured_width).abs()
+            > version.width as f32 * params.rmqr_width_tolerance
+            || (version.height as f32 - measured_height).abs() > params.rmqr_height_tolerance
+        {
+            return Err(DecodeError::InvalidSize {
+                width: measured_width.round() as usize,
+                height: measured_height.round() as usize,
+            });
+        }
+
+        let bounds = Rect::from_corners(left, top, right, bottom);
+        Ok(Self {
+            elem_width: bounds.width() / version.width as f32,
+            elem_height: bounds.height() / version.height as f32,
+            width: version.width,
+            height: version.height,
+            bounds,
+            params: params.clone(),
+        })
+    }
+
+    pub fn idx_to_module(&self, x: usize, y: usize) -> Rect {
+        qr::idx_to_module(&self.bounds, self.elem_width, self.elem_height, x, y)
+    }
+
+    pub fn sample(&self, img: &image::DynamicImage) -> ModuleMatrix {
+        let mut matrix = ModuleMatrix::new(self.width, self.height);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let white_ratio =
+                    img::white_ratio(img, &self.idx_to_module(x, y), self.params.white_threshold);
+                matrix.set_sampled(x, y, white_ratio, self.params.module_white_ratio);
+            }
+        }
+        matrix
+    }
+
+    pub fn read_symbol(&self, img: &image::DynamicImage) -> Result<RmqrSymbol> {
+        RmqrSymbol::new(self.sample(img))?.check_version(&self.params)
+    }
+}
+
+/// The sampled modules of an [`RmqrCode`], with the format info read from them
+pub struct RmqrSymbol {
+    pub matrix: ModuleMatrix,
+    pub format: RmqrFormat,
+    /// Number of bits corrected while decoding the format info
+    pub format_errors: u32,
+}
+
+impl RmqrSymbol {
+    pub fn new(matrix: ModuleMatrix) -> Result<Self> {
+        let read = |positions: &mut dyn Iterator<Item = (usize, usize)>| {
+            positions
+                .enumerate()
+                .filter(|(_, (x, y))| matrix.get(*x, *y))
+                .fold(0, |word, (i, _)| word | 1 << i)
+        };
+        let finder_bits = read(&mut format_positions_finder());
+        let sub_finder_bits = read(&mut format_positions_sub_finder(
+            matrix.width(),
+            matrix.height(),
+        ));
+
+        let (format, format_errors) = [
+            RmqrFormat::decode(finder_bits, FORMAT_XOR_MASK_FINDER),
+            RmqrFormat::decode(sub_finder_bits, FORMAT_XOR_MASK_SUB_FINDER),
+        ]
+        .into_iter()
+        .flatten()
+        .min_by_key(|(_, distance)| *distance)
+        .ok_or(DecodeError::FormatUnreadable {
+            copies: vec![finder_bits, sub_finder_bits],
+        })?;
+
+        let (width, height) = (matrix.width(), matrix.height());
+        if !is_valid_size(width, height) {
+            return Err(DecodeError::InvalidSize { width, height });
+        }
+        if width != format.width() || height != format.height() {
+            return Err(DecodeError::VersionMismatch {
+                from_size: Version::Rmqr { width, height },
+                from_info: Version::Rmqr {
+                    width: format.width(),
+                    height: format.height(),
+                },
+            });
+        }
+
+        Ok(Self {
+            matrix,
+            format,
+            format_errors,
+        })
+    }
+
+    /// Rejects the symbol if its version isn't one of `params.rmqr_versions`
+    pub fn check_version(self, params: &DetectorParams) -> Result<Self> {
+        let versions = &params.rmqr_versions;
+        if !versions.contains(&self.format.version) {
+            return Err(DecodeError::VersionOutOfRange {
+                version: self.format.version,
+                min: *versions.start(),
+                max: *versions.end(),
+            });
+        }
+        Ok(self)
+    }
+
+    /// Unmasked data bits in placement order, black is 1
+    pub fn bit_iter(&self) -> impl Iterator<Item = Output> + '_ {
+        let mask_fn = get_mask_fn(MASK_PATTERN).expect("rMQR mask exists in QR");
+        self.format
+            .data_positions()
+            .into_iter()
+            .map(move |(x, y)| Output {
+                x: x as isize,
+                y: y as isize,
+                bit: self.matrix.get(x, y) != mask_fn(x as u32, y as u32),
+            })
+    }
+
+    /// The interleaved codewords, without the remainder bits
+    pub fn codewords(&self) -> Vec<u8> {
+        let bits: Vec<Output> = self.bit_iter().collect();
+        bits.chunks_exact(8)
+            .take(self.format.num_codewords())
+            .map(|chunk| {
+                chunk
+                    .iter()
+                    .fold(0, |byte, output| (byte << 1) | output.bit as u8)
+            })
+            .collect()
+    }
+
+    pub fn decode(&self) -> Result<Message> {
+        let (data, corrected) = self.format.block_layout().correct(&self.codewords())?;
+        let mut reader = BitReader::new(&data, data.len() * 8);
+        let segments = segment::parse_segments(&mut reader, &self.format.header_spec())?;
+        Ok(Message {
+            segments,
+            corrected,
+        })
+    }
+
+    /// Every field of the error corrected data codewords, see [`segment::annotate_bitstream`]
+    pub fn bitstream(&self) -> Result<Bitstream> {
+        let (data, _) = self.format.block_layout().correct(&self.codewords())?;
+        Ok(segment::annotate_bitstream(
+            &data,
+            data.len() * 8,
+            &self.format.header_spec(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_num_codewords() {
+        let codewords = |version| {
+            RmqrFormat {
+                version,
+                ec_level: EcLevel::M,
+            }
+            .num_codewords()
+        };
+        assert_eq!(codewords(0), 13);
+        assert_eq!(codewords(4), 68);
+        assert_eq!(codewords(10), 15);
+        assert_eq!(codewords(31), 232);
+    }
+
+    #[test]
+    fn test_find_symbol_end_at_edge() {
+        let end = find_symbol_end(40, 40, 4.0, 1.5, |_| unreachable!("Nothing to read"));
+        assert_eq!(end, None);
+        // dark up to 20, then the quiet zone
+        let end = find_symbol_end(10, 40, 4.0, 1.5, |px| px >= 20);
+        assert_eq!(end, Some(20.0));
+    }
+
+    #[test]
+    fn test_decode_rmqr_image() {
+        let img = image::open("test-data/rmqr-r11x43.png")
+            .unwrap()
+            .grayscale();
+        let finders = qr::find_patterns(&img, &qr::DetectorParams::default(), None).unwrap();
+        assert_eq!(finders.len(), 1);
+        let code =
+            RmqrCode::from_finder(&img, &finders[0], &qr::DetectorParams::default()).unwrap();
+        let symbol = code.read_symbol(&img).unwrap();
+        assert_eq!(symbol.format.name(), "R11x43");
+        assert_eq!(symbol.decode().unwrap().text(), "qrious rMQR");
+    }
+}
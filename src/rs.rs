@@ -0,0 +1,237 @@
+use crate::error::{DecodeError, Result};
+
+/// x^8 + x^4 + x^3 + x^2 + 1, the field polynomial used by QR codes
+const PRIMITIVE_POLY: u16 = 0x11D;
+
+struct Tables {
+    exp: [u8; 512],
+    log: [u8; 256],
+}
+
+const fn build_tables() -> Tables {
+    let mut exp = [0u8; 512];
+    let mut log = [0u8; 256];
+    let mut value: u16 = 1;
+    let mut i = 0;
+    while i < 255 {
+        exp[i] = value as u8;
+        exp[i + 255] = value as u8;
+        log[value as usize] = i as u8;
+        value <<= 1;
+        if value & 0x100 != 0 {
+            value ^= PRIMITIVE_POLY;
+        }
+        i += 1;
+    }
+    Tables { exp, log }
+}
+
+const TABLES: Tables = build_tables();
+
+fn mul(a: u8, b: u8) -> u8 {
+    if a == 0 || b == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[a as usize] as usize + TABLES.log[b as usize] as usize]
+}
+
+fn div(a: u8, b: u8) -> u8 {
+    if a == 0 {
+        return 0;
+    }
+    TABLES.exp[TABLES.log[a as usize] as usize + 255 - TABLES.log[b as usize] as usize]
+}
+
+/// alpha^power
+fn exp(power: usize) -> u8 {
+    TABLES.exp[power % 255]
+}
+
+/// Evaluates a polynomial stored lowest degree first
+fn eval(poly: &[u8], x: u8) -> u8 {
+    poly.iter().rev().fold(0, |acc, &coef| mul(acc, x) ^ coef)
+}
+
+/// Generator polynomial (x - a^0)(x - a^1)...(x - a^(n-1)), highest degree first
+fn generator(num_ec: usize) -> Vec<u8> {
+    let mut result = vec![1u8];
+    for i in 0..num_ec {
+        let mut next = vec![0u8; result.len() + 1];
+        for (j, &coef) in result.iter().enumerate() {
+            next[j] ^= coef;
+            next[j + 1] ^= mul(coef, exp(i));
+        }
+        result = next;
+    }
+    result
+}
+
+/// The error correction codewords to append to `data`
+pub fn ec_codewords(data: &[u8], num_ec: usize) -> Vec<u8> {
+    let generator = generator(num_ec);
+    let mut remainder = vec![0u8; num_ec];
+    for &byte in data {
+        let factor = byte ^ remainder[0];
+        remainder.rotate_left(1);
+        remainder[num_ec - 1] = 0;
+        for (rem, &coef) in remainder.iter_mut().zip(generator.iter().skip(1)) {
+            *rem ^= mul(coef, factor);
+        }
+    }
+    remainder
+}
+
+/// The syndromes S_0..S_(n-1), all zero when there is no error
+pub fn syndromes(codewords: &[u8], num_ec: usize) -> Vec<u8> {
+    (0..num_ec)
+        .map(|i| {
+            let x = exp(i);
+            codewords.iter().fold(0, |acc, &c| mul(acc, x) ^ c)
+        })
+        .collect()
+}
+
+/// Berlekamp-Massey, finds the error locator polynomial (lowest degree first)
+fn error_locator(syndromes: &[u8]) -> Vec<u8> {
+    let mut locator = vec![1u8];
+    let mut prev = vec![1u8];
+    let mut num_errors = 0;
+    let mut shift = 1;
+    let mut prev_discrepancy = 1u8;
+
+    for n in 0..syndromes.len() {
+        let mut discrepancy = syndromes[n];
+        for i in 1..=num_errors.min(locator.len() - 1) {
+            discrepancy ^= mul(locator[i], syndromes[n - i]);
+        }
+
+        if discrepancy == 0 {
+            shift += 1;
+            continue;
+        }
+
+        let factor = div(discrepancy, prev_discrepancy);
+        let mut next = locator.clone();
+        next.resize(next.len().max(prev.len() + shift), 0);
+        for (i, &coef) in prev.iter().enumerate() {
+            next[i + shift] ^= mul(factor, coef);
+        }
+
+        if 2 * num_errors <= n {
+            num_errors = n + 1 - num_errors;
+            prev = locator;
+            prev_discrepancy = discrepancy;
+            shift = 1;
+        } else {
+            shift += 1;
+        }
+        locator = next;
+    }
+
+    while locator.len() > 1 && locator.last() == Some(&0) {
+        locator.pop();
+    }
+    locator
+}
+
+/// Corrects `codewords` (data followed by `num_ec` ec codewords) in place,
+/// and returns the number of codewords that were fixed
+pub fn correct(codewords: &mut [u8], num_ec: usize) -> Result<usize> {
+    let syndromes = syndromes(codewords, num_ec);
+    if syndromes.iter().all(|&s| s == 0) {
+        return Ok(0);
+    }
+
+    let capacity = num_ec / 2;
+    let too_many_errors = |corrected| DecodeError::TooManyErrors {
+        corrected,
+        capacity,
+    };
+    let locator = error_locator(&syndromes);
+    let num_errors = locator.len() - 1;
+    if num_errors > capacity {
+        return Err(too_many_errors(num_errors));
+    }
+
+    // Chien search, codeword i has the locator a^(n - 1 - i)
+    let n = codewords.len();
+    let positions: Vec<usize> = (0..n)
+        .filter(|&i| eval(&locator, exp(255 - (n - 1 - i) % 255)) == 0)
+        .collect();
+    // the locator doesn't have all its roots in the block when there are more errors
+    if positions.len() != num_errors {
+        return Err(too_many_errors(capacity + 1));
+    }
+
+    // Forney, with the evaluator omega = S(x) * locator(x) mod x^num_ec
+    let mut evaluator = vec![0u8; num_ec];
+    for (i, &s) in syndromes.iter().enumerate() {
+        for (j, &l) in locator.iter().enumerate() {
+            if i + j < num_ec {
+                evaluator[i + j] ^= mul(s, l);
+            }
+        }
+    }
+    let derivative: Vec<u8> = locator
+        .iter()
+        .enumerate()
+        .skip(1)
+        .map(|(i, &coef)| if i % 2 == 1 { coef } else { 0 })
+        .collect();
+
+    for &i in positions.iter() {
+        let x = exp(n - 1 - i);
+        let x_inv = div(1, x);
+        let magnitude = mul(x, div(eval(&evaluator, x_inv), eval(&derivative, x_inv)));
+        codewords[i] ^= magnitude;
+    }
+
+    if syndromes_nonzero(codewords, num_ec) {
+        return Err(too_many_errors(capacity + 1));
+    }
+    Ok(num_errors)
+}
+
+fn syndromes_nonzero(codewords: &[u8], num_ec: usize) -> bool {
+    syndromes(codewords, num_ec).iter().any(|&s| s != 0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ec_codewords() {
+        // "HELLO WORLD" 1-M from the nayuki/thonky walkthroughs
+        let data = [
+            32, 91, 11, 120, 209, 114, 220, 77, 67, 64, 236, 17, 236, 17, 236, 17,
+        ];
+        assert_eq!(
+            ec_codewords(&data, 10),
+            vec![196, 35, 39, 119, 235, 215, 231, 226, 93, 23]
+        );
+    }
+
+    #[test]
+    fn test_correct() {
+        let data = b"qrious reed solomon";
+        let mut codewords = data.to_vec();
+        codewords.extend(ec_codewords(data, 12));
+
+        let mut corrupted = codewords.clone();
+        for i in [0, 5, 9, 20, 25, 30] {
+            corrupted[i] ^= 0x5A;
+        }
+        assert_eq!(correct(&mut corrupted, 12).unwrap(), 6);
+        assert_eq!(corrupted, codewords);
+
+        corrupted[1] ^= 1;
+        for i in [3, 7, 11, 13, 17, 19] {
+            corrupted[i] = !corrupted[i];
+        }
+        assert!(matches!(
+            correct(&mut corrupted, 12),
+            Err(DecodeError::TooManyErrors { capacity: 6, .. })
+        ));
+    }
+}
@@ -0,0 +1,849 @@
+use std::fmt::{self, Display};
+
+use encoding_rs::SHIFT_JIS;
+
+use crate::error::{DecodeError, Result};
+
+const ALPHANUMERIC_CHARS: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Numeric,
+    Alphanumeric,
+    Byte,
+    Kanji,
+    Eci,
+}
+
+/// A run of data in one mode. `data` holds ascii for numeric and alphanumeric,
+/// raw bytes for byte, Shift JIS for kanji and the assignment number for ECI.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Segment {
+    pub mode: Mode,
+    pub count: usize,
+    pub data: Vec<u8>,
+}
+
+/// The decoded content of a symbol
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub segments: Vec<Segment>,
+    /// Number of codewords fixed by error correction
+    pub corrected: usize,
+}
+
+impl Message {
+    /// The data as text. Kanji segments are decoded from Shift JIS, the bytes of
+    /// the others from UTF-8, as a character may be split across byte segments.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        let mut bytes = vec![];
+        for segment in self.segments.iter() {
+            match segment.mode {
+                Mode::Eci => {}
+                Mode::Kanji => {
+                    text.push_str(&String::from_utf8_lossy(&bytes));
+                    bytes.clear();
+                    let (kanji, _) = SHIFT_JIS.decode_without_bom_handling(&segment.data);
+                    text.push_str(&kanji);
+                }
+                _ => bytes.extend_from_slice(&segment.data),
+            }
+        }
+        text.push_str(&String::from_utf8_lossy(&bytes));
+        text
+    }
+}
+
+/// How segment headers are laid out, which differs between symbol types and versions
+pub struct HeaderSpec {
+    /// Width of the mode indicator, M1 has none as it only supports numeric
+    pub mode_bits: usize,
+    pub modes: &'static [(u32, Mode)],
+    /// Width of the character count for numeric, alphanumeric, byte and kanji
+    pub count_bits: [usize; 4],
+    pub terminator_bits: usize,
+}
+
+const QR_MODES: &[(u32, Mode)] = &[
+    (0b0001, Mode::Numeric),
+    (0b0010, Mode::Alphanumeric),
+    (0b0100, Mode::Byte),
+    (0b1000, Mode::Kanji),
+    (0b0111, Mode::Eci),
+];
+
+impl HeaderSpec {
+    pub fn qr(version: u8) -> Self {
+        let count_bits = match version {
+            1..=9 => [10, 9, 8, 8],
+            10..=26 => [12, 11, 16, 10],
+            _ => [14, 13, 16, 12],
+        };
+        Self {
+            mode_bits: 4,
+            modes: QR_MODES,
+            count_bits,
+            terminator_bits: 4,
+        }
+    }
+
+    pub fn count_bits(&self, mode: Mode) -> usize {
+        match mode {
+            Mode::Numeric => self.count_bits[0],
+            Mode::Alphanumeric => self.count_bits[1],
+            Mode::Byte => self.count_bits[2],
+            Mode::Kanji => self.count_bits[3],
+            Mode::Eci => 0,
+        }
+    }
+
+    /// The mode indicator, if the symbol type supports `mode` at all
+    pub fn indicator(&self, mode: Mode) -> Option<u32> {
+        self.modes
+            .iter()
+            .find(|(_, m)| *m == mode)
+            .map(|(bits, _)| *bits)
+    }
+
+    /// Bits taken by a segment of `count` characters, header included
+    pub fn segment_len(&self, mode: Mode, count: usize) -> usize {
+        let data_len = match mode {
+            Mode::Numeric => 10 * (count / 3) + [0, 4, 7][count % 3],
+            Mode::Alphanumeric => 11 * (count / 2) + 6 * (count % 2),
+            Mode::Byte => 8 * count,
+            Mode::Kanji => 13 * count,
+            // the widest assignment number, the count is the number of ECI designators
+            Mode::Eci => 24 * count,
+        };
+        self.mode_bits + self.count_bits(mode) + data_len
+    }
+}
+
+/// What a run of bits in the data codewords is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Mode,
+    Count,
+    Data,
+    Terminator,
+    /// Zeroes up to the end of the codeword after the terminator
+    Padding,
+    /// The alternating 0xEC and 0x11 codewords filling the rest of the symbol
+    PadCodeword,
+}
+
+/// A run of bits of the data codewords, and what the parser made of it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    pub kind: FieldKind,
+    /// Offset of the first bit into the data codewords
+    pub start: usize,
+    pub len: usize,
+    pub value: u32,
+    pub meaning: String,
+}
+
+impl Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let range = format!("{}..{}", self.start, self.start + self.len);
+        let bits = format!("{:0width$b}", self.value, width = self.len);
+        let kind = format!("{:?}", self.kind);
+        write!(f, "{range:>11}  {bits:>24}  {kind:<11}  {}", self.meaning)
+    }
+}
+
+/// Every field of the data codewords, up to where parsing stopped
+#[derive(Debug)]
+pub struct Bitstream {
+    pub fields: Vec<Field>,
+    pub error: Option<DecodeError>,
+}
+
+/// Reads big endian bit fields from a codeword slice
+pub struct BitReader<'a> {
+    data: &'a [u8],
+    len: usize,
+    pos: usize,
+    /// What was read so far, if asked to keep track
+    fields: Option<Vec<Field>>,
+}
+
+impl<'a> BitReader<'a> {
+    /// Only the first `len` bits are readable, for symbols whose last data codeword is 4 bits
+    pub fn new(data: &'a [u8], len: usize) -> Self {
+        Self {
+            data,
+            len: len.min(data.len() * 8),
+            pos: 0,
+            fields: None,
+        }
+    }
+
+    /// Labels the bits read since `start`, if fields are being kept track of
+    fn note(
+        &mut self,
+        kind: FieldKind,
+        start: usize,
+        value: u32,
+        meaning: impl FnOnce() -> String,
+    ) {
+        let len = self.pos - start;
+        if let Some(fields) = &mut self.fields {
+            fields.push(Field {
+                kind,
+                start,
+                len,
+                value,
+                meaning: meaning(),
+            });
+        }
+    }
+
+    pub fn remaining(&self) -> usize {
+        self.len - self.pos
+    }
+
+    pub fn peek(&self, n: usize) -> Option<u32> {
+        if n > self.remaining() {
+            return None;
+        }
+        let mut result = 0;
+        for i in self.pos..self.pos + n {
+            let bit = (self.data[i / 8] >> (7 - i % 8)) & 1;
+            result = (result << 1) | bit as u32;
+        }
+        Some(result)
+    }
+
+    pub fn read(&mut self, n: usize) -> Result<u32> {
+        let result = self.peek(n).ok_or(DecodeError::TruncatedData {
+            needed: n,
+            remaining: self.remaining(),
+        })?;
+        self.pos += n;
+        Ok(result)
+    }
+}
+
+/// Writes big endian bit fields, the counterpart of [`BitReader`]
+#[derive(Debug, Clone, Default)]
+pub struct BitWriter {
+    data: Vec<u8>,
+    len: usize,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Appends the low `n` bits of `value`, highest first
+    pub fn write(&mut self, value: u32, n: usize) {
+        for i in (0..n).rev() {
+            if self.len.is_multiple_of(8) {
+                self.data.push(0);
+            }
+            let bit = (value >> i) & 1;
+            *self.data.last_mut().expect("A byte was pushed") |= (bit as u8) << (7 - self.len % 8);
+            self.len += 1;
+        }
+    }
+
+    /// The written bits, with the last byte padded with zeroes
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+}
+
+/// Writes the segments with their headers, the inverse of [`parse_segments`].
+/// Numeric and alphanumeric data has to be valid for its mode, and fit its count.
+pub fn write_segments(writer: &mut BitWriter, segments: &[Segment], spec: &HeaderSpec) {
+    for segment in segments {
+        let indicator = spec
+            .indicator(segment.mode)
+            .expect("The mode is supported by the symbol type");
+        writer.write(indicator, spec.mode_bits);
+        if segment.mode == Mode::Eci {
+            write_eci(writer, segment);
+            continue;
+        }
+
+        writer.write(segment.count as u32, spec.count_bits(segment.mode));
+        match segment.mode {
+            Mode::Numeric => write_numeric(writer, &segment.data),
+            Mode::Alphanumeric => write_alphanumeric(writer, &segment.data),
+            Mode::Byte => segment
+                .data
+                .iter()
+                .for_each(|&byte| writer.write(byte as u32, 8)),
+            Mode::Kanji => write_kanji(writer, &segment.data),
+            Mode::Eci => unreachable!(),
+        }
+    }
+}
+
+fn write_eci(writer: &mut BitWriter, segment: &Segment) {
+    let assignment: u32 = std::str::from_utf8(&segment.data)
+        .ok()
+        .and_then(|number| number.parse().ok())
+        .expect("ECI segments hold their assignment number");
+    match assignment {
+        0..=0x7F => writer.write(assignment, 8),
+        0x80..=0x3FFF => writer.write(0x8000 | assignment, 16),
+        _ => writer.write(0xC0_0000 | assignment, 24),
+    }
+}
+
+fn write_numeric(writer: &mut BitWriter, digits: &[u8]) {
+    for group in digits.chunks(3) {
+        let value = group
+            .iter()
+            .fold(0, |value, digit| value * 10 + (digit - b'0') as u32);
+        writer.write(value, [0, 4, 7, 10][group.len()]);
+    }
+}
+
+fn write_alphanumeric(writer: &mut BitWriter, chars: &[u8]) {
+    let value_of = |c: &u8| {
+        ALPHANUMERIC_CHARS
+            .iter()
+            .position(|a| a == c)
+            .expect("Alphanumeric data only has alphanumeric chars") as u32
+    };
+    for pair in chars.chunks(2) {
+        match pair {
+            [a, b] => writer.write(value_of(a) * 45 + value_of(b), 11),
+            [a] => writer.write(value_of(a), 6),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn write_kanji(writer: &mut BitWriter, sjis: &[u8]) {
+    for pair in sjis.chunks_exact(2) {
+        let value = (pair[0] as u32) << 8 | pair[1] as u32;
+        let compacted = if value <= 0x9FFC {
+            value - 0x8140
+        } else {
+            value - 0xC140
+        };
+        writer.write((compacted >> 8) * 0xC0 + (compacted & 0xFF), 13);
+    }
+}
+
+pub fn is_numeric(byte: u8) -> bool {
+    byte.is_ascii_digit()
+}
+
+pub fn is_alphanumeric(byte: u8) -> bool {
+    ALPHANUMERIC_CHARS.contains(&byte)
+}
+
+/// A Shift JIS double byte character in the ranges kanji mode holds
+pub fn is_kanji(high: u8, low: u8) -> bool {
+    let value = (high as u32) << 8 | low as u32;
+    ((0x8140..=0x9FFC).contains(&value) || (0xE040..=0xEBBF).contains(&value))
+        && (0x40..=0xFC).contains(&low)
+        && low != 0x7F
+}
+
+/// Index of the first byte of `data` that `mode` can't hold. Kanji are byte pairs,
+/// and ECI segments don't hold data at all.
+pub fn first_invalid(data: &[u8], mode: Mode) -> Option<usize> {
+    match mode {
+        Mode::Numeric => data.iter().position(|&byte| !is_numeric(byte)),
+        Mode::Alphanumeric => data.iter().position(|&byte| !is_alphanumeric(byte)),
+        Mode::Byte => None,
+        Mode::Kanji => (0..data.len())
+            .step_by(2)
+            .find(|&i| i + 1 == data.len() || !is_kanji(data[i], data[i + 1])),
+        Mode::Eci => Some(0),
+    }
+}
+
+/// Characters packed together, a segment is cheapest when cut at a multiple of it
+fn group_len(mode: Mode) -> usize {
+    match mode {
+        Mode::Numeric => 3,
+        Mode::Alphanumeric => 2,
+        _ => 1,
+    }
+}
+
+/// Bits taken by the character after `count` others in the same segment
+fn char_len(mode: Mode, count: usize) -> usize {
+    match (mode, count % group_len(mode)) {
+        (Mode::Numeric, 0) => 4,
+        (Mode::Numeric, _) => 3,
+        (Mode::Alphanumeric, 0) => 6,
+        (Mode::Alphanumeric, _) => 5,
+        (Mode::Kanji, _) => 13,
+        _ => 8,
+    }
+}
+
+/// All of `data` in `mode`, split where a segment would overflow its character count
+pub fn split_segments(data: &[u8], mode: Mode, spec: &HeaderSpec) -> Vec<Segment> {
+    let bytes_per_char = if mode == Mode::Kanji { 2 } else { 1 };
+    let max_count = (1 << spec.count_bits(mode)) - 1;
+    let chunk_len = max_count / group_len(mode) * group_len(mode) * bytes_per_char;
+    data.chunks(chunk_len)
+        .map(|chunk| Segment {
+            mode,
+            count: chunk.len() / bytes_per_char,
+            data: chunk.to_vec(),
+        })
+        .collect()
+}
+
+/// Splits `data` into the numeric, alphanumeric and byte segments that take the
+/// fewest bits with the character counts of `spec`. Kanji is left out, as it would
+/// change what the bytes decode to.
+///
+/// Each state is a mode, and how many characters into a group of it the last
+/// segment is, so the cost of every step is exact. A character either continues
+/// the segment of its state or starts a new one after the cheapest state so far.
+pub fn optimal_segments(data: &[u8], spec: &HeaderSpec) -> Vec<Segment> {
+    let states: Vec<(Mode, usize)> = [Mode::Numeric, Mode::Alphanumeric, Mode::Byte]
+        .into_iter()
+        .flat_map(|mode| (0..group_len(mode)).map(move |filled| (mode, filled)))
+        .collect();
+    let fits = |mode: Mode, byte: u8| first_invalid(&[byte], mode).is_none();
+
+    // bits taken so far ending in each state, and the state each came from
+    let mut costs: Vec<Option<usize>> = vec![None; states.len()];
+    let mut parents: Vec<Vec<usize>> = Vec::with_capacity(data.len());
+    for (i, &byte) in data.iter().enumerate() {
+        let cheapest = (0..states.len())
+            .filter_map(|s| costs[s].map(|cost| (cost, s)))
+            .min();
+        let mut next_costs = vec![None; states.len()];
+        let mut next_parents = vec![0; states.len()];
+        for (s, &(mode, filled)) in states.iter().enumerate() {
+            if !fits(mode, byte) {
+                continue;
+            }
+            let before = (filled + group_len(mode) - 1) % group_len(mode);
+            let prev = states.iter().position(|&state| state == (mode, before));
+            let continued = prev
+                .filter(|_| i > 0)
+                .and_then(|prev| Some((costs[prev]? + char_len(mode, before), prev)));
+            let started = (filled == 1 % group_len(mode)).then(|| {
+                let (cost, prev) = cheapest.unwrap_or((0, 0));
+                let header = spec.mode_bits + spec.count_bits(mode);
+                (cost + header + char_len(mode, 0), prev)
+            });
+            if let Some((cost, prev)) = continued.into_iter().chain(started).min() {
+                next_costs[s] = Some(cost);
+                next_parents[s] = prev;
+            }
+        }
+        costs = next_costs;
+        parents.push(next_parents);
+    }
+
+    let Some((_, mut state)) = (0..states.len())
+        .filter_map(|s| costs[s].map(|cost| (cost, s)))
+        .min()
+    else {
+        return Vec::new();
+    };
+    let mut modes = vec![Mode::Byte; data.len()];
+    for i in (0..data.len()).rev() {
+        modes[i] = states[state].0;
+        state = parents[i][state];
+    }
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    for run in modes.chunk_by(|a, b| a == b) {
+        let end = start + run.len();
+        segments.extend(split_segments(&data[start..end], run[0], spec));
+        start = end;
+    }
+    segments
+}
+
+/// The terminator is all zeroes, and may be cut short (or left out) when the symbol is full
+fn at_terminator(reader: &BitReader, spec: &HeaderSpec) -> bool {
+    let len = spec.terminator_bits.min(reader.remaining());
+    len == 0 || reader.peek(len) == Some(0) || reader.remaining() < spec.mode_bits
+}
+
+pub fn parse_segments(reader: &mut BitReader, spec: &HeaderSpec) -> Result<Vec<Segment>> {
+    let mut segments = vec![];
+    while !at_terminator(reader, spec) {
+        let start = reader.pos;
+        let indicator = reader.read(spec.mode_bits)?;
+        let mode = spec
+            .modes
+            .iter()
+            .find(|(bits, _)| *bits == indicator)
+            .map(|(_, mode)| *mode)
+            .ok_or(DecodeError::UnsupportedMode { indicator })?;
+        if spec.mode_bits > 0 {
+            reader.note(FieldKind::Mode, start, indicator, || format!("{mode:?}"));
+        }
+
+        if mode == Mode::Eci {
+            segments.push(read_eci(reader)?);
+            continue;
+        }
+
+        let start = reader.pos;
+        let count = reader.read(spec.count_bits(mode))? as usize;
+        reader.note(FieldKind::Count, start, count as u32, || {
+            let unit = if mode == Mode::Byte {
+                "bytes"
+            } else {
+                "characters"
+            };
+            format!("{count} {unit}")
+        });
+        let data = match mode {
+            Mode::Numeric => read_numeric(reader, count)?,
+            Mode::Alphanumeric => read_alphanumeric(reader, count)?,
+            Mode::Byte => read_bytes(reader, count)?,
+            Mode::Kanji => read_kanji(reader, count)?,
+            Mode::Eci => unreachable!(),
+        };
+        segments.push(Segment { mode, count, data });
+    }
+    Ok(segments)
+}
+
+/// Parses the data codewords like [`parse_segments`], keeping every field read along
+/// the way, then the terminator and padding after the last segment. On an error,
+/// the fields stop right before it.
+pub fn annotate_bitstream(data: &[u8], len: usize, spec: &HeaderSpec) -> Bitstream {
+    let mut reader = BitReader::new(data, len);
+    reader.fields = Some(vec![]);
+    let error = parse_segments(&mut reader, spec)
+        .and_then(|_| read_padding(&mut reader, spec))
+        .err();
+    Bitstream {
+        fields: reader.fields.unwrap_or_default(),
+        error,
+    }
+}
+
+/// The terminator, zeroes to the next codeword and the pad codewords after it
+fn read_padding(reader: &mut BitReader, spec: &HeaderSpec) -> Result<()> {
+    let zeroes_or = |value: u32, what: &str| {
+        if value == 0 {
+            what.to_string()
+        } else {
+            format!("{what}, but not all zeroes")
+        }
+    };
+    let len = spec.terminator_bits.min(reader.remaining());
+    if len > 0 {
+        let start = reader.pos;
+        let value = reader.read(len)?;
+        reader.note(FieldKind::Terminator, start, value, || {
+            zeroes_or(value, "end of data")
+        });
+    }
+    let len = ((8 - reader.pos % 8) % 8).min(reader.remaining());
+    if len > 0 {
+        let start = reader.pos;
+        let value = reader.read(len)?;
+        reader.note(FieldKind::Padding, start, value, || {
+            zeroes_or(value, "fill to the codeword boundary")
+        });
+    }
+    for expected in [0xEC, 0x11].into_iter().cycle() {
+        let start = reader.pos;
+        match reader.remaining() {
+            0 => break,
+            // the 4 bit last data codeword of M1 and M3 is padded with zeroes
+            len @ 1..=7 => {
+                let value = reader.read(len)?;
+                reader.note(FieldKind::Padding, start, value, || {
+                    zeroes_or(value, "fill the short codeword")
+                });
+            }
+            _ => {
+                let value = reader.read(8)?;
+                reader.note(FieldKind::PadCodeword, start, value, || {
+                    if value == expected {
+                        format!("{value:#04X}")
+                    } else {
+                        format!("{value:#04X}, expected {expected:#04X}")
+                    }
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_eci(reader: &mut BitReader) -> Result<Segment> {
+    let start = reader.pos;
+    let first = reader.read(8)?;
+    let assignment = if first & 0x80 == 0 {
+        first
+    } else if first & 0xC0 == 0x80 {
+        (first & 0x3F) << 8 | reader.read(8)?
+    } else {
+        (first & 0x1F) << 16 | reader.read(16)?
+    };
+    reader.note(FieldKind::Data, start, assignment, || {
+        format!("ECI assignment {assignment}")
+    });
+    Ok(Segment {
+        mode: Mode::Eci,
+        count: 1,
+        data: assignment.to_string().into_bytes(),
+    })
+}
+
+/// Groups of 3 digits in 10 bits, a trailing 2 in 7 bits or 1 in 4 bits
+fn read_numeric(reader: &mut BitReader, count: usize) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(count);
+    let mut left = count;
+    while left > 0 {
+        let (digits, bits) = match left {
+            1 => (1, 4),
+            2 => (2, 7),
+            _ => (3, 10),
+        };
+        let start = reader.pos;
+        let value = reader.read(bits)?;
+        if value >= 10u32.pow(digits) {
+            return Err(DecodeError::InvalidData {
+                mode: Mode::Numeric,
+                value,
+            });
+        }
+        let group = format!("{value:0width$}", width = digits as usize);
+        reader.note(FieldKind::Data, start, value, || format!("{group:?}"));
+        result.extend(group.bytes());
+        left -= digits as usize;
+    }
+    Ok(result)
+}
+
+/// Pairs of characters in 11 bits, a trailing one in 6 bits
+fn read_alphanumeric(reader: &mut BitReader, count: usize) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(count);
+    let mut left = count;
+    while left > 0 {
+        let start = reader.pos;
+        let (value, group) = if left == 1 {
+            let value = reader.read(6)?;
+            (value, vec![alphanumeric_char(value)?])
+        } else {
+            let value = reader.read(11)?;
+            let pair = vec![
+                alphanumeric_char(value / 45)?,
+                alphanumeric_char(value % 45)?,
+            ];
+            (value, pair)
+        };
+        reader.note(FieldKind::Data, start, value, || {
+            format!("{:?}", String::from_utf8_lossy(&group))
+        });
+        left -= group.len();
+        result.extend(group);
+    }
+    Ok(result)
+}
+
+fn alphanumeric_char(value: u32) -> Result<u8> {
+    ALPHANUMERIC_CHARS
+        .get(value as usize)
+        .copied()
+        .ok_or(DecodeError::InvalidData {
+            mode: Mode::Alphanumeric,
+            value,
+        })
+}
+
+fn read_bytes(reader: &mut BitReader, count: usize) -> Result<Vec<u8>> {
+    (0..count)
+        .map(|_| {
+            let start = reader.pos;
+            let byte = reader.read(8)? as u8;
+            reader.note(FieldKind::Data, start, byte as u32, || {
+                format!("{byte:#04x} '{}'", byte.escape_ascii())
+            });
+            Ok(byte)
+        })
+        .collect()
+}
+
+/// Each character is 13 bits, compacted from its 2 byte Shift JIS value
+fn read_kanji(reader: &mut BitReader, count: usize) -> Result<Vec<u8>> {
+    let mut result = Vec::with_capacity(count * 2);
+    for _ in 0..count {
+        let start = reader.pos;
+        let value = reader.read(13)?;
+        let compacted = ((value / 0xC0) << 8) | (value % 0xC0);
+        let sjis = if compacted + 0x8140 <= 0x9FFC {
+            compacted + 0x8140
+        } else {
+            compacted + 0xC140
+        };
+        reader.note(FieldKind::Data, start, value, || {
+            let bytes = [(sjis >> 8) as u8, sjis as u8];
+            let (kanji, _) = SHIFT_JIS.decode_without_bom_handling(&bytes);
+            format!("{kanji:?} (Shift JIS {sjis:#06x})")
+        });
+        result.push((sjis >> 8) as u8);
+        result.push(sjis as u8);
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_numeric_segment() {
+        // 0001 0000001000 0000001100 0101011001 1000011 0000, "01234567" from the spec
+        let data = [0x10, 0x20, 0x0C, 0x56, 0x61, 0x80];
+        let mut reader = BitReader::new(&data, data.len() * 8);
+        let segments = parse_segments(&mut reader, &HeaderSpec::qr(1)).unwrap();
+        assert_eq!(
+            segments,
+            vec![Segment {
+                mode: Mode::Numeric,
+                count: 8,
+                data: b"01234567".to_vec()
+            }]
+        );
+    }
+
+    #[test]
+    fn test_write_segments() {
+        let segments = vec![
+            Segment {
+                mode: Mode::Numeric,
+                count: 8,
+                data: b"01234567".to_vec(),
+            },
+            Segment {
+                mode: Mode::Alphanumeric,
+                count: 5,
+                data: b"AC-42".to_vec(),
+            },
+            Segment {
+                mode: Mode::Kanji,
+                count: 2,
+                data: vec![0x93, 0x5F, 0xE4, 0xAA],
+            },
+        ];
+        let spec = HeaderSpec::qr(1);
+        let mut writer = BitWriter::new();
+        write_segments(&mut writer, &segments, &spec);
+        assert_eq!(
+            writer.len(),
+            segments
+                .iter()
+                .map(|segment| spec.segment_len(segment.mode, segment.count))
+                .sum::<usize>()
+        );
+
+        let len = writer.len();
+        let data = writer.into_bytes();
+        let mut reader = BitReader::new(&data, len);
+        assert_eq!(parse_segments(&mut reader, &spec).unwrap(), segments);
+
+        let message = Message {
+            segments,
+            corrected: 0,
+        };
+        assert_eq!(message.text(), "01234567AC-42点茗");
+    }
+
+    #[test]
+    fn test_annotate_bitstream() {
+        // "01234567" in 1-M, followed by 10 pad codewords
+        let mut data = vec![0x10, 0x20, 0x0C, 0x56, 0x61, 0x80];
+        data.extend([0xEC, 0x11].iter().cycle().take(10));
+        let bitstream = annotate_bitstream(&data, data.len() * 8, &HeaderSpec::qr(1));
+        assert!(bitstream.error.is_none());
+
+        let fields: Vec<(FieldKind, usize, usize)> = bitstream
+            .fields
+            .iter()
+            .map(|field| (field.kind, field.start, field.len))
+            .collect();
+        assert_eq!(
+            fields[..8],
+            [
+                (FieldKind::Mode, 0, 4),
+                (FieldKind::Count, 4, 10),
+                (FieldKind::Data, 14, 10),
+                (FieldKind::Data, 24, 10),
+                (FieldKind::Data, 34, 7),
+                (FieldKind::Terminator, 41, 4),
+                (FieldKind::Padding, 45, 3),
+                (FieldKind::PadCodeword, 48, 8),
+            ]
+        );
+        assert_eq!(fields.len(), 17);
+        assert_eq!(bitstream.fields[4].meaning, r#""67""#);
+        assert_eq!(
+            bitstream.fields[2].to_string(),
+            r#"     14..24                0000001100  Data         "012""#
+        );
+
+        // a stray bit in the last pad codeword
+        data[15] ^= 1;
+        let bitstream = annotate_bitstream(&data, data.len() * 8, &HeaderSpec::qr(1));
+        assert_eq!(
+            bitstream.fields.last().unwrap().meaning,
+            "0x10, expected 0x11"
+        );
+    }
+
+    #[test]
+    fn test_optimal_segments() {
+        let data = b"HTTPS://EXAMPLE.COM/12345678901234";
+        let spec = HeaderSpec::qr(1);
+        let segments = optimal_segments(data, &spec);
+        let modes: Vec<Mode> = segments.iter().map(|segment| segment.mode).collect();
+        assert_eq!(modes, [Mode::Alphanumeric, Mode::Numeric]);
+        assert_eq!(segments[1].data, b"12345678901234");
+        let len = |segments: &[Segment]| -> usize {
+            segments
+                .iter()
+                .map(|segment| spec.segment_len(segment.mode, segment.count))
+                .sum()
+        };
+        assert!(len(&segments) < len(&split_segments(data, Mode::Alphanumeric, &spec)));
+
+        // a few digits aren't worth the extra header
+        let segments = optimal_segments(b"hello 123 world", &spec);
+        assert_eq!(segments.len(), 1);
+        assert_eq!(segments[0].mode, Mode::Byte);
+
+        // counts that would overflow the 8 bits of byte mode in versions 1 to 9
+        let segments = optimal_segments(&[b'a'; 300], &spec);
+        assert_eq!(
+            segments.iter().map(|s| s.count).collect::<Vec<_>>(),
+            [255, 45]
+        );
+    }
+
+    #[test]
+    fn test_parse_alphanumeric_segment() {
+        // 0010 000000101 00111001110 11100111001 000010 0000, "AC-42" from the spec
+        let data = [0x20, 0x29, 0xCE, 0xE7, 0x21, 0x00];
+        let mut reader = BitReader::new(&data, data.len() * 8);
+        let segments = parse_segments(&mut reader, &HeaderSpec::qr(1)).unwrap();
+        assert_eq!(segments[0].data, b"AC-42");
+    }
+}
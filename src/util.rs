@@ -1,5 +1,7 @@
-use crate::viz::Visualizer;
-use anyhow::{anyhow, Result};
+use crate::{
+    error::{DecodeError, Result},
+    viz::Visualizer,
+};
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct Rect {
@@ -10,7 +12,6 @@ pub struct Rect {
 }
 
 impl Rect {
-    #[allow(dead_code)]
     pub fn from_center_and_size(cx: f32, cy: f32, width: f32, height: f32) -> Self {
         Self {
             top: cy - height / 2.0,
@@ -20,7 +21,6 @@ impl Rect {
         }
     }
 
-    #[allow(dead_code)]
     pub fn from_corners(left: f32, top: f32, right: f32, bottom: f32) -> Self {
         Self {
             top,
@@ -30,12 +30,10 @@ impl Rect {
         }
     }
 
-    #[allow(dead_code)]
     pub fn to_corners(&self) -> (f32, f32, f32, f32) {
         (self.left, self.top, self.right, self.bottom)
     }
 
-    #[allow(dead_code)]
     pub fn to_center_and_size(&self) -> (f32, f32, f32, f32) {
         (
             (self.left + self.right) / 2.0,
@@ -45,45 +43,45 @@ impl Rect {
         )
     }
 
-    #[allow(dead_code)]
     pub fn cx(&self) -> f32 {
         (self.left + self.right) / 2.0
     }
 
-    #[allow(dead_code)]
     pub fn cy(&self) -> f32 {
         (self.top + self.bottom) / 2.0
     }
 
-    #[allow(dead_code)]
     pub fn width(&self) -> f32 {
         self.right - self.left
     }
 
-    #[allow(dead_code)]
     pub fn height(&self) -> f32 {
         self.bottom - self.top
     }
 
-    #[allow(dead_code)]
     pub fn top(&self) -> f32 {
         self.top
     }
-    #[allow(dead_code)]
     pub fn bottom(&self) -> f32 {
         self.bottom
     }
-    #[allow(dead_code)]
     pub fn left(&self) -> f32 {
         self.left
     }
-    #[allow(dead_code)]
     pub fn right(&self) -> f32 {
         self.right
     }
 
-    #[allow(dead_code)]
-    pub fn draw(&self, viz: &mut Visualizer, color: &str, fill: Option<&str>) -> Result<()> {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        (self.left..=self.right).contains(&x) && (self.top..=self.bottom).contains(&y)
+    }
+
+    pub fn draw(
+        &self,
+        viz: &mut dyn Visualizer,
+        color: &str,
+        fill: Option<&str>,
+    ) -> std::io::Result<()> {
         viz.draw_rect(
             self.cx(),
             self.cy(),
@@ -94,6 +92,24 @@ impl Rect {
         )?;
         Ok(())
     }
+
+    pub fn draw_with_title(
+        &self,
+        viz: &mut dyn Visualizer,
+        color: &str,
+        fill: Option<&str>,
+        title: &str,
+    ) -> std::io::Result<()> {
+        viz.draw_rect_with_title(
+            self.cx(),
+            self.cy(),
+            self.width(),
+            self.height(),
+            color,
+            fill,
+            title,
+        )
+    }
 }
 
 pub trait IteratorExt: Iterator {
@@ -106,7 +122,12 @@ pub trait IteratorExt: Iterator {
         for _ in 0..n {
             match self.next() {
                 Some(item) => result.push(item),
-                None => return Err(anyhow!("Not enough elements in iterator")),
+                None => {
+                    return Err(DecodeError::TruncatedData {
+                        needed: n,
+                        remaining: result.len(),
+                    })
+                }
             }
         }
 
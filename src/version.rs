@@ -0,0 +1,137 @@
+use crate::{codewords::BlockLayout, format::EcLevel, util::GridPoint};
+
+pub const MIN_VERSION: u8 = 1;
+pub const MAX_VERSION: u8 = 40;
+/// Versions below this don't carry version info blocks
+pub const MIN_VERSION_WITH_INFO: u8 = 7;
+
+pub fn version_to_size(version: u8) -> usize {
+    17 + 4 * version as usize
+}
+
+pub fn size_to_version(size: usize) -> Option<u8> {
+    if size < version_to_size(MIN_VERSION) || !(size - 17).is_multiple_of(4) {
+        return None;
+    }
+    let version = ((size - 17) / 4) as u8;
+    (version <= MAX_VERSION).then_some(version)
+}
+
+/// Error correction codewords per block, indexed by ec level (L, M, Q, H) then version
+#[rustfmt::skip]
+const EC_CODEWORDS_PER_BLOCK: [[usize; 41]; 4] = [
+    [0, 7, 10, 15, 20, 26, 18, 20, 24, 30, 18, 20, 24, 26, 30, 22, 24, 28, 30, 28, 28, 28, 28, 30, 30, 26, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 10, 16, 26, 18, 24, 16, 18, 22, 22, 26, 30, 22, 22, 24, 24, 28, 28, 26, 26, 26, 26, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28, 28],
+    [0, 13, 22, 18, 26, 18, 24, 18, 22, 20, 24, 28, 26, 24, 20, 30, 24, 28, 28, 26, 30, 28, 30, 30, 30, 30, 28, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+    [0, 17, 28, 22, 16, 22, 28, 26, 26, 24, 28, 24, 28, 22, 24, 24, 30, 28, 28, 26, 28, 30, 24, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30, 30],
+];
+
+/// Number of error correction blocks, indexed by ec level (L, M, Q, H) then version
+#[rustfmt::skip]
+const NUM_EC_BLOCKS: [[usize; 41]; 4] = [
+    [0, 1, 1, 1, 1, 1, 2, 2, 2, 2, 4, 4, 4, 4, 4, 6, 6, 6, 6, 7, 8, 8, 9, 9, 10, 12, 12, 12, 13, 14, 15, 16, 17, 18, 19, 19, 20, 21, 22, 24, 25],
+    [0, 1, 1, 1, 2, 2, 4, 4, 4, 5, 5, 5, 8, 9, 9, 10, 10, 11, 13, 14, 16, 17, 17, 18, 20, 21, 23, 25, 26, 28, 29, 31, 33, 35, 37, 38, 40, 43, 45, 47, 49],
+    [0, 1, 1, 2, 2, 4, 4, 6, 6, 8, 8, 8, 10, 12, 16, 12, 17, 16, 18, 21, 20, 23, 23, 25, 27, 29, 34, 34, 35, 38, 40, 43, 45, 48, 51, 53, 56, 59, 62, 65, 68],
+    [0, 1, 1, 2, 4, 4, 4, 5, 6, 8, 8, 11, 11, 16, 16, 18, 16, 19, 21, 25, 25, 25, 34, 30, 32, 35, 37, 40, 42, 45, 48, 51, 54, 57, 60, 63, 66, 70, 74, 77, 81],
+];
+
+/// Modules left for data and ec codewords once all function patterns are placed,
+/// including the remainder bits that don't fill a codeword
+pub fn num_raw_data_modules(version: u8) -> usize {
+    let v = version as usize;
+    let mut result = (16 * v + 128) * v + 64;
+    if version >= 2 {
+        let num_align = v / 7 + 2;
+        result -= (25 * num_align - 10) * num_align - 55;
+        if version >= MIN_VERSION_WITH_INFO {
+            result -= 36;
+        }
+    }
+    result
+}
+
+pub fn num_codewords(version: u8) -> usize {
+    num_raw_data_modules(version) / 8
+}
+
+pub fn block_layout(version: u8, ec_level: EcLevel) -> BlockLayout {
+    let level = ec_level as usize;
+    BlockLayout::new(
+        num_codewords(version),
+        NUM_EC_BLOCKS[level][version as usize],
+        EC_CODEWORDS_PER_BLOCK[level][version as usize],
+    )
+}
+
+/// Centres of the alignment patterns along one axis, the same for rows and columns
+pub fn alignment_pattern_centres(version: u8) -> Vec<usize> {
+    if version == 1 {
+        return vec![];
+    }
+    let version = version as usize;
+    let num_align = version / 7 + 2;
+    let step = if version == 32 {
+        26
+    } else {
+        (version * 4 + num_align * 2 + 1) / (num_align * 2 - 2) * 2
+    };
+
+    let last = version_to_size(version as u8) - 7;
+    let mut result = vec![6];
+    for i in 0..num_align - 1 {
+        result.insert(1, last - i * step);
+    }
+    result
+}
+
+/// Top left corners of all alignment patterns, skipping the ones that would overlap finders
+pub fn alignment_pattern_positions(version: u8) -> Vec<GridPoint> {
+    let centres = alignment_pattern_centres(version);
+    let (first, last) = match (centres.first(), centres.last()) {
+        (Some(&first), Some(&last)) => (first, last),
+        _ => return vec![],
+    };
+
+    let mut result = vec![];
+    for &cy in centres.iter() {
+        for &cx in centres.iter() {
+            let overlaps_finder =
+                (cx == first && (cy == first || cy == last)) || (cx == last && cy == first);
+            if !overlaps_finder {
+                result.push(GridPoint {
+                    x: cx - 2,
+                    y: cy - 2,
+                });
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_num_codewords() {
+        assert_eq!(num_codewords(1), 26);
+        assert_eq!(num_codewords(7), 196);
+        assert_eq!(num_codewords(25), 1588);
+        assert_eq!(block_layout(25, EcLevel::L).num_data_codewords(), 1276);
+        assert_eq!(num_codewords(40), 3706);
+        assert_eq!(block_layout(5, EcLevel::Q).num_data_codewords(), 62);
+        assert_eq!(block_layout(40, EcLevel::H).num_data_codewords(), 1276);
+    }
+
+    #[test]
+    fn test_alignment_pattern_centres() {
+        assert_eq!(alignment_pattern_centres(1), vec![]);
+        assert_eq!(alignment_pattern_centres(5), vec![6, 30]);
+        assert_eq!(alignment_pattern_centres(7), vec![6, 22, 38]);
+        assert_eq!(alignment_pattern_centres(32), vec![6, 34, 60, 86, 112, 138]);
+        assert_eq!(
+            alignment_pattern_centres(36),
+            vec![6, 24, 50, 76, 102, 128, 154]
+        );
+    }
+}
@@ -1,81 +1,284 @@
 use std::{
     fs::{self, File},
-    path::PathBuf,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
 };
 
-use anyhow::anyhow;
-use anyhow::Result;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use image::{Rgba, RgbaImage};
 use xml::{writer::XmlEvent, EmitterConfig, EventWriter};
 
-pub struct Visualizer {
-    #[allow(dead_code)]
+/// Drawn for colours [`RasterVisualizer`] can't parse, so they stand out
+const UNKNOWN_COLOR: Rgba<u8> = Rgba([255, 0, 255, 255]);
+
+/// The image drawn under everything else
+#[derive(Debug, Clone, PartialEq)]
+pub enum Background {
+    /// Linked by its path, relative to the SVG
+    Link(PathBuf),
+    /// Kept in the SVG as a data URI, so it can be moved around on its own
+    Embedded { mime: String, data: Vec<u8> },
+}
+
+impl Background {
+    /// Embeds the file as it is, the type is guessed from its extension
+    pub fn embed_file(path: &Path) -> io::Result<Self> {
+        let mime = image::ImageFormat::from_path(path)
+            .map(|format| format.to_mime_type())
+            .unwrap_or("application/octet-stream");
+        Ok(Self::Embedded {
+            mime: mime.to_string(),
+            data: fs::read(path)?,
+        })
+    }
+
+    pub(crate) fn href(&self) -> String {
+        match self {
+            Self::Link(path) => path.to_string_lossy().into_owned(),
+            Self::Embedded { mime, data } => {
+                format!("data:{mime};base64,{}", STANDARD.encode(data))
+            }
+        }
+    }
+}
+
+/// Where each step of decoding draws what it found, in pixels of the image. Colours
+/// are CSS colours, backends that aren't SVG only know a few of them.
+pub trait Visualizer {
+    fn draw_circle(&mut self, x: f32, y: f32, r: f32, color: &str) -> io::Result<()>;
+
+    fn draw_rect(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        w: f32,
+        h: f32,
+        color: &str,
+        fill: Option<&str>,
+    ) -> io::Result<()>;
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, color: &str) -> io::Result<()>;
+
+    /// A rect with a tooltip, backends without tooltips just draw the rect
+    #[allow(clippy::too_many_arguments)]
+    fn draw_rect_with_title(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        w: f32,
+        h: f32,
+        color: &str,
+        fill: Option<&str>,
+        _title: &str,
+    ) -> io::Result<()> {
+        self.draw_rect(cx, cy, w, h, color, fill)
+    }
+
+    /// Puts everything drawn until [`Self::end_layer`] in a group, so it can be hidden
+    /// on its own. `color` is what the legend shows it as, `style` applies to the
+    /// whole group, e.g. `opacity: 0.5`. Layers can be nested, and a name should
+    /// only be begun once as it becomes the id of the group.
+    fn begin_layer(&mut self, _name: &str, _color: &str, _style: Option<&str>) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn end_layer(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out anything still pending, nothing should be drawn after
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes the drawings as SVG elements to any sink, a file with
+/// [`SvgVisualizer::create`] or a string with [`SvgVisualizer::in_memory`]
+pub struct SvgVisualizer<W: Write> {
     width: u32,
-    #[allow(dead_code)]
-    height: u32,
-    svg_writer: EventWriter<File>,
+    /// Taken back out by [`SvgVisualizer::into_inner`]
+    svg_writer: Option<EventWriter<W>>,
 
     stroke_width: f32,
     font_size: f32,
 
+    /// Every layer begun so far with its colour, in order, for the legend
+    layers: Vec<(String, String)>,
+    open_layers: usize,
     finished: bool,
 }
 
-impl Visualizer {
+/// Visualizers report io errors, the SVG writer's own ones are wrapped in one
+fn svg_error(err: xml::writer::Error) -> io::Error {
+    match err {
+        xml::writer::Error::Io(err) => err,
+        err => io::Error::other(err),
+    }
+}
+
+/// The id of the group a layer is drawn in
+fn layer_id(name: &str) -> String {
+    format!("layer-{}", name.to_lowercase().replace(' ', "-"))
+}
+
+impl SvgVisualizer<BufWriter<File>> {
+    pub fn create(
+        width: u32,
+        height: u32,
+        output_path: &Path,
+        background: Option<Background>,
+    ) -> io::Result<Self> {
+        let file = fs::File::create(output_path)?;
+        Self::new(width, height, BufWriter::new(file), background)
+    }
+}
+
+impl SvgVisualizer<Vec<u8>> {
+    pub fn in_memory(width: u32, height: u32, background: Option<Background>) -> io::Result<Self> {
+        Self::new(width, height, Vec::new(), background)
+    }
+
+    /// Finishes the SVG and returns it
+    pub fn into_string(self) -> io::Result<String> {
+        let svg = self.into_inner()?;
+        Ok(String::from_utf8(svg).expect("The writer emits UTF-8"))
+    }
+}
+
+impl<W: Write> SvgVisualizer<W> {
     pub fn new(
         width: u32,
         height: u32,
-        output_path: &PathBuf,
-        img_path: Option<PathBuf>,
-    ) -> Result<Self> {
-        let file = fs::File::create(output_path).unwrap();
+        sink: W,
+        background: Option<Background>,
+    ) -> io::Result<Self> {
         let mut svg_writer = EmitterConfig::new()
             .perform_indent(true)
-            .create_writer(file);
-
-        svg_writer.write(
-            XmlEvent::start_element("svg")
-                .attr("xmlns", "http://www.w3.org/2000/svg")
-                .attr("width", &width.to_string())
-                .attr("height", &height.to_string())
-                .attr("style", "zoom: 2"),
-        )?;
+            .create_writer(sink);
 
-        if let Some(img_path) = img_path {
-            svg_writer.write(
-                XmlEvent::start_element("image")
-                    .attr("href", &img_path.to_str().ok_or(anyhow!("Invalid path"))?)
+        svg_writer
+            .write(
+                XmlEvent::start_element("svg")
+                    .attr("xmlns", "http://www.w3.org/2000/svg")
                     .attr("width", &width.to_string())
-                    .attr("height", &height.to_string()),
-            )?;
+                    .attr("height", &height.to_string())
+                    .attr("style", "zoom: 2"),
+            )
+            .map_err(svg_error)?;
 
-            svg_writer.write(XmlEvent::end_element())?;
+        if let Some(background) = background {
+            svg_writer
+                .write(
+                    XmlEvent::start_element("image")
+                        .attr("href", &background.href())
+                        .attr("width", &width.to_string())
+                        .attr("height", &height.to_string()),
+                )
+                .map_err(svg_error)?;
+
+            svg_writer
+                .write(XmlEvent::end_element())
+                .map_err(svg_error)?;
         }
 
-        Ok(Visualizer {
+        Ok(SvgVisualizer {
             width,
-            height,
-            svg_writer,
+            svg_writer: Some(svg_writer),
             stroke_width: (width as f32) / 1000.0,
             font_size: (width as f32) / 500.0,
+            layers: Vec::new(),
+            open_layers: 0,
             finished: false,
         })
     }
 
-    #[allow(dead_code)]
-    pub fn draw_circle(&mut self, x: f32, y: f32, r: f32, color: &str) -> Result<()> {
-        self.svg_writer.write(
+    /// Finishes the SVG and returns the sink it was written to
+    pub fn into_inner(mut self) -> io::Result<W> {
+        self.finish()?;
+        let mut svg_writer = self.svg_writer.take().expect("Only taken here");
+        svg_writer.inner_mut().flush()?;
+        Ok(svg_writer.into_inner())
+    }
+
+    fn write<'a>(&mut self, event: impl Into<XmlEvent<'a>>) -> io::Result<()> {
+        self.svg_writer
+            .as_mut()
+            .expect("Only taken when consumed")
+            .write(event)
+            .map_err(svg_error)
+    }
+
+    /// Lists the layers in the top left corner, clicking one hides or shows it
+    fn draw_legend(&mut self) -> io::Result<()> {
+        if self.layers.is_empty() {
+            return Ok(());
+        }
+        let line_height = self.width as f32 / 40.0;
+        let longest = self.layers.iter().map(|(name, _)| name.len()).max();
+        let legend_width = line_height * (1.5 + 0.6 * longest.unwrap_or(0) as f32);
+        let legend_height = line_height * (self.layers.len() as f32 + 0.5);
+
+        self.write(XmlEvent::start_element("g").attr("id", "legend"))?;
+        self.write(
+            XmlEvent::start_element("rect")
+                .attr("width", &legend_width.to_string())
+                .attr("height", &legend_height.to_string())
+                .attr("fill", "white")
+                .attr("fill-opacity", "0.8"),
+        )?;
+        self.write(XmlEvent::end_element())?;
+
+        for (i, (name, color)) in std::mem::take(&mut self.layers).iter().enumerate() {
+            let y = line_height * (i as f32 + 0.25);
+            let toggle = format!(
+                "var g = document.getElementById('{}'); \
+                 g.style.display = g.style.display == 'none' ? '' : 'none'",
+                layer_id(name)
+            );
+            self.write(
+                XmlEvent::start_element("g")
+                    .attr("onclick", &toggle)
+                    .attr("style", "cursor: pointer"),
+            )?;
+            self.write(
+                XmlEvent::start_element("rect")
+                    .attr("x", &(line_height * 0.25).to_string())
+                    .attr("y", &(y + line_height * 0.15).to_string())
+                    .attr("width", &(line_height * 0.7).to_string())
+                    .attr("height", &(line_height * 0.7).to_string())
+                    .attr("fill", color),
+            )?;
+            self.write(XmlEvent::end_element())?;
+            self.write(
+                XmlEvent::start_element("text")
+                    .attr("x", &(line_height * 1.2).to_string())
+                    .attr("y", &(y + line_height * 0.8).to_string())
+                    .attr("font-size", &(line_height * 0.8).to_string())
+                    .attr("fill", "black"),
+            )?;
+            self.write(XmlEvent::characters(name))?;
+            self.write(XmlEvent::end_element())?;
+            self.write(XmlEvent::end_element())?;
+        }
+        self.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Visualizer for SvgVisualizer<W> {
+    fn draw_circle(&mut self, x: f32, y: f32, r: f32, color: &str) -> io::Result<()> {
+        self.write(
             XmlEvent::start_element("circle")
                 .attr("cx", &x.to_string())
                 .attr("cy", &y.to_string())
                 .attr("r", &r.to_string())
                 .attr("fill", color),
         )?;
-        self.svg_writer.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn draw_rect(
+    fn draw_rect(
         &mut self,
         cx: f32,
         cy: f32,
@@ -83,10 +286,11 @@ impl Visualizer {
         h: f32,
         color: &str,
         fill: Option<&str>,
-    ) -> Result<()> {
+    ) -> io::Result<()> {
         let x = cx - w / 2.0;
         let y = cy - h / 2.0;
-        self.svg_writer.write(
+        let stroke_width = self.stroke_width.to_string();
+        self.write(
             XmlEvent::start_element("rect")
                 .attr("x", &x.to_string())
                 .attr("y", &y.to_string())
@@ -94,40 +298,360 @@ impl Visualizer {
                 .attr("height", &h.to_string())
                 .attr("stroke", color)
                 .attr("fill", fill.unwrap_or("none"))
-                .attr("stroke-width", self.stroke_width.to_string().as_str()),
+                .attr("stroke-width", stroke_width.as_str()),
         )?;
-        self.svg_writer.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
         Ok(())
     }
 
-    #[allow(dead_code)]
-    pub fn draw_text(&mut self, x: f32, y: f32, text: &str, color: &str) -> Result<()> {
+    fn draw_rect_with_title(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        w: f32,
+        h: f32,
+        color: &str,
+        fill: Option<&str>,
+        title: &str,
+    ) -> io::Result<()> {
+        let x = cx - w / 2.0;
+        let y = cy - h / 2.0;
+        let stroke_width = self.stroke_width.to_string();
+        // hovering anywhere inside shows the title, even without a fill
+        self.write(
+            XmlEvent::start_element("rect")
+                .attr("x", &x.to_string())
+                .attr("y", &y.to_string())
+                .attr("width", &w.to_string())
+                .attr("height", &h.to_string())
+                .attr("stroke", color)
+                .attr("fill", fill.unwrap_or("none"))
+                .attr("stroke-width", stroke_width.as_str())
+                .attr("pointer-events", "visible"),
+        )?;
+        self.write(XmlEvent::start_element("title"))?;
+        self.write(XmlEvent::characters(title))?;
+        self.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn draw_text(&mut self, x: f32, y: f32, text: &str, color: &str) -> io::Result<()> {
         let x = x - 0.5;
         let y = y + 0.5;
-        self.svg_writer.write(
+        let font_size = self.font_size.to_string();
+        let stroke_width = self.stroke_width.to_string();
+        // labels sit on top of modules, and shouldn't hide their tooltips
+        self.write(
             XmlEvent::start_element("text")
                 .attr("x", &x.to_string())
                 .attr("y", &y.to_string())
-                .attr("font-size", self.font_size.to_string().as_str())
-                .attr("stroke-width", self.stroke_width.to_string().as_str())
-                .attr("fill", color),
+                .attr("font-size", font_size.as_str())
+                .attr("stroke-width", stroke_width.as_str())
+                .attr("fill", color)
+                .attr("pointer-events", "none"),
         )?;
-        self.svg_writer.write(XmlEvent::characters(text))?;
-        self.svg_writer.write(XmlEvent::end_element())?;
+        self.write(XmlEvent::characters(text))?;
+        self.write(XmlEvent::end_element())?;
+        Ok(())
+    }
+
+    fn begin_layer(&mut self, name: &str, color: &str, style: Option<&str>) -> io::Result<()> {
+        let id = layer_id(name);
+        let mut group = XmlEvent::start_element("g").attr("id", &id);
+        if let Some(style) = style {
+            group = group.attr("style", style);
+        }
+        self.write(group)?;
+        if !self.layers.iter().any(|(layer, _)| layer == name) {
+            self.layers.push((name.to_string(), color.to_string()));
+        }
+        self.open_layers += 1;
         Ok(())
     }
 
-    pub fn finish(&mut self) -> Result<()> {
+    fn end_layer(&mut self) -> io::Result<()> {
+        if self.open_layers > 0 {
+            self.open_layers -= 1;
+            self.write(XmlEvent::end_element())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
         if !self.finished {
             self.finished = true;
-            self.svg_writer.write(XmlEvent::end_element())?;
+            while self.open_layers > 0 {
+                self.end_layer()?;
+            }
+            self.draw_legend()?;
+            self.write(XmlEvent::end_element())?;
         }
         Ok(())
     }
 }
 
-impl Drop for Visualizer {
+impl<W: Write> Drop for SvgVisualizer<W> {
     fn drop(&mut self) {
-        self.finish().unwrap();
+        if self.svg_writer.is_some() {
+            // nothing to report a failure to, callers who care call finish first
+            let _ = self.finish();
+        }
+    }
+}
+
+/// Draws straight onto a copy of the image, for a PNG overlay. Text is left out as
+/// there is no font to draw it with, and layers can't be hidden after the fact.
+pub struct RasterVisualizer {
+    img: RgbaImage,
+}
+
+impl RasterVisualizer {
+    pub fn new(img: &image::DynamicImage) -> Self {
+        Self {
+            img: img.to_rgba8(),
+        }
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.img
+    }
+
+    pub fn into_image(self) -> RgbaImage {
+        self.img
+    }
+
+    pub fn save(&self, path: &Path) -> image::ImageResult<()> {
+        self.img.save(path)
+    }
+
+    /// Blends `color` over the pixel, if it's inside the image
+    fn blend(&mut self, x: i64, y: i64, color: Rgba<u8>) {
+        let (width, height) = self.img.dimensions();
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            return;
+        }
+        let pixel = self.img.get_pixel_mut(x as u32, y as u32);
+        let alpha = color.0[3] as u32;
+        for i in 0..3 {
+            pixel.0[i] =
+                ((color.0[i] as u32 * alpha + pixel.0[i] as u32 * (255 - alpha)) / 255) as u8;
+        }
+    }
+}
+
+impl Visualizer for RasterVisualizer {
+    fn draw_circle(&mut self, x: f32, y: f32, r: f32, color: &str) -> io::Result<()> {
+        let color = parse_color(color).unwrap_or(UNKNOWN_COLOR);
+        let r = r.max(0.5);
+        let (top, bottom) = ((y - r).floor() as i64, (y + r).ceil() as i64);
+        let (left, right) = ((x - r).floor() as i64, (x + r).ceil() as i64);
+        for py in top..=bottom {
+            for px in left..=right {
+                let (dx, dy) = (px as f32 + 0.5 - x, py as f32 + 0.5 - y);
+                if dx * dx + dy * dy <= r * r {
+                    self.blend(px, py, color);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_rect(
+        &mut self,
+        cx: f32,
+        cy: f32,
+        w: f32,
+        h: f32,
+        color: &str,
+        fill: Option<&str>,
+    ) -> io::Result<()> {
+        let left = (cx - w / 2.0).round() as i64;
+        let top = (cy - h / 2.0).round() as i64;
+        let right = ((cx + w / 2.0).round() as i64 - 1).max(left);
+        let bottom = ((cy + h / 2.0).round() as i64 - 1).max(top);
+        if let Some(fill) = fill {
+            let fill = parse_color(fill).unwrap_or(UNKNOWN_COLOR);
+            for y in top + 1..bottom {
+                for x in left + 1..right {
+                    self.blend(x, y, fill);
+                }
+            }
+        }
+        let color = parse_color(color).unwrap_or(UNKNOWN_COLOR);
+        for x in left..=right {
+            self.blend(x, top, color);
+            if bottom != top {
+                self.blend(x, bottom, color);
+            }
+        }
+        for y in top + 1..bottom {
+            self.blend(left, y, color);
+            if right != left {
+                self.blend(right, y, color);
+            }
+        }
+        Ok(())
+    }
+
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _color: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Throws everything away, for when nobody looks at the diagnostics
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoopVisualizer;
+
+impl Visualizer for NoopVisualizer {
+    fn draw_circle(&mut self, _x: f32, _y: f32, _r: f32, _color: &str) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn draw_rect(
+        &mut self,
+        _cx: f32,
+        _cy: f32,
+        _w: f32,
+        _h: f32,
+        _color: &str,
+        _fill: Option<&str>,
+    ) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn draw_text(&mut self, _x: f32, _y: f32, _text: &str, _color: &str) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// The named colours this crate draws with, `#rrggbb`, and `rgb()` or `rgba()`
+fn parse_color(color: &str) -> Option<Rgba<u8>> {
+    let named = match color {
+        "black" => Some([0, 0, 0]),
+        "white" => Some([255, 255, 255]),
+        "gray" | "grey" => Some([128, 128, 128]),
+        "red" => Some([255, 0, 0]),
+        "green" => Some([0, 128, 0]),
+        "blue" => Some([0, 0, 255]),
+        "yellow" => Some([255, 255, 0]),
+        "orange" => Some([255, 165, 0]),
+        "purple" => Some([128, 0, 128]),
+        _ => None,
+    };
+    if let Some([r, g, b]) = named {
+        return Some(Rgba([r, g, b, 255]));
+    }
+
+    if let Some(hex) = color.strip_prefix('#').filter(|hex| hex.len() == 6) {
+        let channel = |i: usize| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok();
+        return Some(Rgba([channel(0)?, channel(2)?, channel(4)?, 255]));
+    }
+
+    let args = color
+        .strip_prefix("rgba(")
+        .or_else(|| color.strip_prefix("rgb("))?
+        .strip_suffix(')')?;
+    let args: Vec<&str> = args.split(',').map(str::trim).collect();
+    let channel = |i: usize| args.get(i)?.parse::<u8>().ok();
+    let alpha = match args.get(3) {
+        Some(alpha) => (alpha.parse::<f32>().ok()?.clamp(0.0, 1.0) * 255.0).round() as u8,
+        None if args.len() == 3 => 255,
+        None => return None,
+    };
+    Some(Rgba([channel(0)?, channel(1)?, channel(2)?, alpha]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_svg_in_memory() {
+        let mut vis = SvgVisualizer::in_memory(100, 100, None).unwrap();
+        vis.begin_layer("Data order", "orange", Some("opacity: 0.5"))
+            .unwrap();
+        vis.draw_rect(10.0, 10.0, 4.0, 4.0, "orange", None).unwrap();
+        vis.end_layer().unwrap();
+        vis.draw_circle(5.0, 5.0, 1.0, "blue").unwrap();
+        let svg = vis.into_string().unwrap();
+
+        assert!(svg.contains(r#"<g id="layer-data-order" style="opacity: 0.5">"#));
+        assert!(svg.contains(r#"<rect x="8" y="8" width="4" height="4""#));
+        assert!(svg.contains(r#"<g id="legend">"#));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_svg_title() {
+        let mut vis = SvgVisualizer::in_memory(100, 100, None).unwrap();
+        vis.draw_rect_with_title(10.0, 10.0, 4.0, 4.0, "orange", None, "module (3, 4)")
+            .unwrap();
+        let svg = vis.into_string().unwrap();
+        assert!(svg.contains("<title>module (3, 4)</title>"));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_svg_layers_and_legend() {
+        let mut vis = SvgVisualizer::in_memory(100, 100, None).unwrap();
+        vis.begin_layer("Finders", "yellow", None).unwrap();
+        vis.draw_rect(10.0, 10.0, 4.0, 4.0, "yellow", None).unwrap();
+        vis.end_layer().unwrap();
+        vis.begin_layer("Data order", "orange", None).unwrap();
+        vis.draw_rect(20.0, 20.0, 4.0, 4.0, "orange", None).unwrap();
+        vis.end_layer().unwrap();
+        // a layer begun again is listed once, and one left open is closed
+        vis.begin_layer("Finders", "yellow", None).unwrap();
+        vis.draw_rect(30.0, 30.0, 4.0, 4.0, "yellow", None).unwrap();
+        let svg = vis.into_string().unwrap();
+
+        assert_eq!(svg.matches(r#"<g id="layer-finders">"#).count(), 2);
+        assert_eq!(svg.matches(r#"<g id="layer-data-order">"#).count(), 1);
+        assert_eq!(svg.matches("<g ").count(), svg.matches("</g>").count());
+        let legend = &svg[svg.find(r#"<g id="legend">"#).unwrap()..];
+        for (name, color) in [("Finders", "yellow"), ("Data order", "orange")] {
+            assert_eq!(legend.matches(&format!(">{name}</text>")).count(), 1);
+            assert!(legend.contains(&format!(r#"fill="{color}""#)));
+            let toggle = format!("getElementById(&apos;{}&apos;)", layer_id(name));
+            assert!(legend.contains(&toggle));
+        }
+    }
+
+    #[test]
+    fn test_svg_embedded_background() {
+        let path = Path::new("test-data/hello-world.png");
+        let background = Background::embed_file(path).unwrap();
+        let svg = SvgVisualizer::in_memory(100, 100, Some(background))
+            .unwrap()
+            .into_string()
+            .unwrap();
+        let data = STANDARD.encode(fs::read(path).unwrap());
+        assert!(svg.contains(&format!(r#"<image href="data:image/png;base64,{data}""#)));
+    }
+
+    #[test]
+    fn test_raster() {
+        let img = image::DynamicImage::new_luma8(10, 10);
+        let mut vis = RasterVisualizer::new(&img);
+        vis.draw_rect(5.0, 5.0, 6.0, 6.0, "red", Some("rgba(0, 255, 0, 0.5)"))
+            .unwrap();
+        let img = vis.into_image();
+        assert_eq!(*img.get_pixel(2, 2), Rgba([255, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(7, 7), Rgba([255, 0, 0, 255]));
+        assert_eq!(*img.get_pixel(5, 5), Rgba([0, 128, 0, 255]));
+        assert_eq!(*img.get_pixel(1, 1), Rgba([0, 0, 0, 255]));
+    }
+
+    #[test]
+    fn test_parse_color() {
+        assert_eq!(parse_color("orange"), Some(Rgba([255, 165, 0, 255])));
+        assert_eq!(parse_color("#0a0B0c"), Some(Rgba([10, 11, 12, 255])));
+        assert_eq!(
+            parse_color("rgba(0, 255, 0, 0.6)"),
+            Some(Rgba([0, 255, 0, 153]))
+        );
+        assert_eq!(parse_color("rgb(1,2,3)"), Some(Rgba([1, 2, 3, 255])));
+        assert_eq!(parse_color("chartreuse"), None);
     }
 }